@@ -0,0 +1,4732 @@
+// Core of safe_backup: path validation, backups, restores and logging.
+// The command-line interface lives in main.rs
+
+mod s3;
+#[cfg(feature = "chrono")]
+mod schedule;
+mod sftp;
+mod storage;
+mod timestamp;
+#[cfg(all(feature = "tls", unix))]
+mod tls;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::{Component, Path, PathBuf, Prefix};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, mpsc};
+use std::time::{Duration, Instant};
+use aes_gcm::{Aes256Gcm, Key, KeyInit};
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use argon2::Argon2;
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, Utc};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use filetime::FileTime;
+use hmac::{Hmac, Mac};
+use indicatif::{ProgressBar, ProgressStyle};
+use owo_colors::{OwoColorize, Stream};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub use s3::S3Storage;
+#[cfg(feature = "chrono")]
+pub use schedule::{CronSchedule, ScheduledBackup};
+pub use sftp::SftpTarget;
+pub use storage::{LocalFs, StorageBackend, StorageEntry, ZipStorage};
+use storage::RelativeNames;
+#[cfg(feature = "sftp")]
+use sftp::SftpStorage;
+pub use timestamp::Timestamp;
+
+// Custom error types for better error handling
+#[derive(Debug)]
+pub enum SafeBackupError {
+    InvalidPath(String),
+    FileNotFound(String),
+    IoError(io::Error),
+    // An IO error on a known path, so the message names the file that failed
+    ContextError { path: PathBuf, source: io::Error },
+    PermissionDenied(String),
+    IntegrityError(String),
+    DecryptionError(String),
+    BatchFailed { succeeded: Vec<PathBuf>, failures: Vec<(String, SafeBackupError)> },
+    FileTooLarge { size: u64, limit: u64 },
+    DestinationExists(String),
+    // The source is held open by another process that does not share it for reading
+    FileInUse(String),
+}
+
+impl SafeBackupError {
+    // Stable name of the failure kind, for machine-readable error output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SafeBackupError::InvalidPath(_) => "invalid_path",
+            SafeBackupError::FileNotFound(_) => "file_not_found",
+            SafeBackupError::IoError(_) | SafeBackupError::ContextError { .. } => "io_error",
+            SafeBackupError::PermissionDenied(_) => "permission_denied",
+            SafeBackupError::IntegrityError(_) => "integrity_error",
+            SafeBackupError::DecryptionError(_) => "decryption_error",
+            SafeBackupError::BatchFailed { .. } => "batch_failed",
+            SafeBackupError::FileTooLarge { .. } => "file_too_large",
+            SafeBackupError::DestinationExists(_) => "destination_exists",
+            SafeBackupError::FileInUse(_) => "file_in_use",
+        }
+    }
+
+    // Process exit code for each kind of failure, so scripts can branch on it:
+    // 1 = verification mismatch, 2 = invalid path or arguments, 3 = file not found,
+    // 4 = IO error, 5 = permission denied, 6 = integrity check failed, 7 = some files in a batch failed,
+    // 8 = an encrypted backup could not be decrypted, 9 = the source is over the size limit,
+    // 10 = restore refused to overwrite an existing file, 11 = the source is locked by another process
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SafeBackupError::InvalidPath(_) => 2,
+            SafeBackupError::FileNotFound(_) => 3,
+            SafeBackupError::IoError(_) | SafeBackupError::ContextError { .. } => 4,
+            SafeBackupError::PermissionDenied(_) => 5,
+            SafeBackupError::IntegrityError(_) => 6,
+            SafeBackupError::BatchFailed { .. } => 7,
+            SafeBackupError::DecryptionError(_) => 8,
+            SafeBackupError::FileTooLarge { .. } => 9,
+            SafeBackupError::DestinationExists(_) => 10,
+            SafeBackupError::FileInUse(_) => 11,
+        }
+    }
+}
+
+impl From<io::Error> for SafeBackupError {
+    fn from(error: io::Error) -> Self {
+        // Decryption failures surface mid-stream as IO errors, but are reported as their own kind
+        if error.get_ref().is_some_and(|inner| inner.is::<CorruptChunk>()) {
+            return SafeBackupError::DecryptionError("Invalid passphrase or corrupt backup".to_string());
+        }
+        if error.kind() == io::ErrorKind::PermissionDenied {
+            return SafeBackupError::PermissionDenied(error.to_string());
+        }
+        SafeBackupError::IoError(error)
+    }
+}
+
+// Convert an IO error on a known path, keeping the path for the message
+fn io_error_at(path: &Path, error: io::Error) -> SafeBackupError {
+    if error.kind() == io::ErrorKind::PermissionDenied {
+        return SafeBackupError::PermissionDenied(format!("'{}': {}", path.display(), error));
+    }
+    match error.into() {
+        SafeBackupError::IoError(source) => SafeBackupError::ContextError { path: path.to_path_buf(), source },
+        other => other,
+    }
+}
+
+impl std::fmt::Display for SafeBackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SafeBackupError::InvalidPath(msg) => write!(f, "Invalid path: {}", msg),
+            SafeBackupError::FileNotFound(msg) => write!(f, "File not found: {}", msg),
+            SafeBackupError::IoError(err) => write!(f, "IO error: {}", err),
+            SafeBackupError::ContextError { path, source } => write!(f, "IO error on '{}': {}", path.display(), source),
+            SafeBackupError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+            SafeBackupError::IntegrityError(msg) => write!(f, "Integrity check failed: {}", msg),
+            SafeBackupError::DecryptionError(msg) => write!(f, "Decryption failed: {}", msg),
+            SafeBackupError::FileTooLarge { size, limit } => write!(f, "File too large: {} bytes exceeds the limit of {} bytes", size, limit),
+            SafeBackupError::DestinationExists(msg) => write!(f, "Destination exists: {}", msg),
+            SafeBackupError::FileInUse(msg) => write!(f, "File in use: {}", msg),
+            SafeBackupError::BatchFailed { succeeded, failures } => {
+                write!(f, "{} of {} file(s) failed", failures.len(), succeeded.len() + failures.len())?;
+                for (filename, err) in failures {
+                    write!(f, "\n  {}: {}", filename, err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, SafeBackupError>;
+
+// Log size that triggers rotation, and how many rotated logs are kept by default
+const DEFAULT_MAX_LOG_SIZE: u64 = 5 * 1024 * 1024;
+const DEFAULT_LOG_KEEP: usize = 5;
+
+// Attempts for IO that may fail transiently on network filesystems, and the delay before the first retry
+const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// How long the delete confirmation waits for an answer before cancelling
+const DEFAULT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How long a watched file must stay unchanged before the automatic backup runs
+const DEFAULT_WATCH_DEBOUNCE: Duration = Duration::from_secs(1);
+
+// Changed lines shown from each end of the diff in a restore preview
+const PREVIEW_LINES: usize = 5;
+
+// Ends backup names unless backup_suffix sets another one
+const DEFAULT_BACKUP_SUFFIX: &str = ".bak";
+
+// Gzip level of compressed backups: 0 stores, 1 is fastest, 9 compresses most
+const DEFAULT_COMPRESSION_LEVEL: u32 = 6;
+const MAX_COMPRESSION_LEVEL: u32 = 9;
+
+// Where actions are logged unless another log file is configured
+pub const DEFAULT_LOG_FILE: &str = "logfile.txt";
+
+// Extensions this program adds after the backup suffix or uses for its own files
+const RESERVED_EXTENSIONS: [&str; 8] = [".gz", ".enc", ".ptr", ".delta", ".sha256", ".meta", ".tmp", ".lock"];
+
+// Errors worth retrying; anything else (NotFound, PermissionDenied, ...) fails immediately
+fn is_transient(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+// Whether opening a file failed because another process holds it exclusively
+// Windows reports ERROR_SHARING_VIOLATION (32) or ERROR_LOCK_VIOLATION (33); elsewhere this is EBUSY
+fn is_sharing_violation(error: &io::Error) -> bool {
+    (cfg!(windows) && matches!(error.raw_os_error(), Some(32 | 33))) || error.kind() == io::ErrorKind::ResourceBusy
+}
+
+// Colored terminal output; colors are dropped when the stream is not a TTY or NO_COLOR is set
+pub fn print_success(message: &str) {
+    println!("{}", message.if_supports_color(Stream::Stdout, |text| text.green()));
+}
+
+pub fn print_cancelled(message: &str) {
+    println!("{}", message.if_supports_color(Stream::Stdout, |text| text.yellow()));
+}
+
+pub fn print_error(message: &str) {
+    eprintln!("{}", message.if_supports_color(Stream::Stderr, |text| text.red()));
+}
+
+// Print rows under a header with each column padded to its widest cell
+fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    let header = header.map(String::from);
+    let mut widths = [0; N];
+    for row in std::iter::once(&header).chain(rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows) {
+        let cells: Vec<String> = row.iter().zip(widths).map(|(cell, width)| format!("{:<width$}", cell)).collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+// Hex encoding of a digest
+fn hex_digest(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Sidecar file holding the expected SHA-256 of a backup's original contents
+fn checksum_path(backup_path: &Path) -> PathBuf {
+    let mut name = backup_path.as_os_str().to_os_string();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+// Sidecar file holding the original file's permissions and modification time
+fn metadata_path(backup_path: &Path) -> PathBuf {
+    let mut name = backup_path.as_os_str().to_os_string();
+    name.push(".meta");
+    PathBuf::from(name)
+}
+
+// Serialize the permissions and mtime of a source file as key=value lines
+fn format_file_metadata(metadata: &fs::Metadata) -> String {
+    let mut lines = Vec::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        lines.push(format!("mode={:o}", metadata.permissions().mode()));
+    }
+    lines.push(format!("readonly={}", metadata.permissions().readonly()));
+    let mtime = FileTime::from_last_modification_time(metadata);
+    lines.push(format!("mtime={}.{:09}", mtime.unix_seconds(), mtime.nanoseconds()));
+    lines.join("\n") + "\n"
+}
+
+// Hidden temporary file next to `path` that is renamed over it once complete
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.tmp", name))
+}
+
+// Temp files of every AtomicFile not yet persisted or dropped, so an interrupt can remove them
+static IN_PROGRESS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+fn in_progress() -> MutexGuard<'static, Vec<PathBuf>> {
+    IN_PROGRESS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// A temp file in the same directory as its destination, renamed into place by persist()
+// and removed if dropped before that, so the destination is either complete or absent
+struct AtomicFile {
+    temp: PathBuf,
+    dest: PathBuf,
+    persisted: bool,
+}
+
+impl AtomicFile {
+    fn create(dest: &Path) -> Result<(AtomicFile, fs::File)> {
+        let temp = temp_path(dest);
+        let mut registry = in_progress();
+        let file = fs::File::create(&temp).map_err(|e| io_error_at(&temp, e))?;
+        registry.push(temp.clone());
+        Ok((AtomicFile { temp, dest: dest.to_path_buf(), persisted: false }, file))
+    }
+
+    fn path(&self) -> &Path {
+        &self.temp
+    }
+
+    // The registry stays locked across the rename so an interrupt cannot remove the file mid-rename
+    fn persist(&mut self) -> Result<()> {
+        let mut registry = in_progress();
+        rename_across_devices(&self.temp, &self.dest).map_err(|e| io_error_at(&self.dest, e))?;
+        self.persisted = true;
+        registry.retain(|path| path != &self.temp);
+        Ok(())
+    }
+
+    // Rename into a different destination than the one the temp file was created for,
+    // which may be on another filesystem
+    fn persist_to(&mut self, dest: &Path) -> Result<()> {
+        self.dest = dest.to_path_buf();
+        self.persist()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let mut registry = in_progress();
+            let _ = fs::remove_file(&self.temp);
+            registry.retain(|path| path != &self.temp);
+        }
+    }
+}
+
+// Rename from onto to; when they are on different filesystems the data is copied to a temp file
+// next to `to` and renamed from there, so `to` still only ever appears complete
+fn rename_across_devices(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let staged = temp_path(to);
+            let copied = fs::copy(from, &staged)
+                .and_then(|_| fs::File::open(&staged)?.sync_all())
+                .and_then(|_| fs::rename(&staged, to));
+            if copied.is_err() {
+                let _ = fs::remove_file(&staged);
+            }
+            copied?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+// Advisory lock on a hidden `.{name}.lock` file next to a path, released when dropped
+// The lock files are left in place, since removing them would race with other processes
+struct FileLock {
+    _file: fs::File,
+}
+
+impl FileLock {
+    fn open(path: &Path) -> Result<fs::File> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let lock_path = path.with_file_name(format!(".{}.lock", name));
+        fs::OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path)
+            .map_err(|e| io_error_at(&lock_path, e))
+    }
+
+    // Fails straight away if another process holds the lock
+    fn try_acquire(path: &Path) -> Result<FileLock> {
+        let file = Self::open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(FileLock { _file: file }),
+            Err(fs::TryLockError::WouldBlock) => Err(SafeBackupError::IoError(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("'{}' is locked by another safe_backup run, try again once it finishes", path.display()),
+            ))),
+            Err(fs::TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    // Waits for the lock; only for short critical sections such as a manifest update
+    fn acquire(path: &Path) -> Result<FileLock> {
+        let file = Self::open(path)?;
+        file.lock()?;
+        Ok(FileLock { _file: file })
+    }
+}
+
+// HMAC-SHA256 of a log line chained to the previous line's HMAC (empty for the first line of a file)
+fn sign_log_line(key: &[u8], previous: &str, line: &str) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(previous.as_bytes());
+    mac.update(b"\n");
+    mac.update(line.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+// Append the HMAC to a log line: as a "hmac" key of a JSON line, otherwise as a trailing hmac=<hex>
+fn append_signature(line: &str, hmac: &str) -> String {
+    match line.strip_suffix('}') {
+        Some(object) if line.starts_with('{') => format!("{},\"hmac\":\"{}\"}}", object, hmac),
+        _ => format!("{} hmac={}", line, hmac),
+    }
+}
+
+// Split a signed log line into the line as it was signed and its HMAC, or None if it is unsigned
+fn split_signature(line: &str) -> Option<(String, &str)> {
+    let (unsigned, hmac) = if line.starts_with('{') {
+        let (object, rest) = line.rsplit_once(",\"hmac\":\"")?;
+        (format!("{}}}", object), rest.strip_suffix("\"}")?)
+    } else {
+        let (unsigned, hmac) = line.rsplit_once(" hmac=")?;
+        (unsigned.to_string(), hmac)
+    };
+    (hmac.len() == 64 && hmac.bytes().all(|b| b.is_ascii_hexdigit())).then_some((unsigned, hmac))
+}
+
+// The HMAC on the last line of a log, or an empty chain start when the log is missing, empty or unsigned
+// Only the tail is read, a block at a time going back until it holds the whole last line
+fn last_log_signature(path: &Path) -> io::Result<String> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(String::new()),
+        Err(e) => return Err(e),
+    };
+    let mut start = file.metadata()?.len();
+    let mut tail = Vec::new();
+    while start > 0 {
+        let block = start.min(4096);
+        start -= block;
+        io::Seek::seek(&mut file, io::SeekFrom::Start(start))?;
+        let mut chunk = vec![0; block as usize];
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+        // A newline before the final one marks where the last line starts
+        if tail.strip_suffix(b"\n").unwrap_or(&tail).contains(&b'\n') {
+            break;
+        }
+    }
+    let tail = String::from_utf8_lossy(&tail);
+    let last = tail.lines().last().unwrap_or_default();
+    Ok(split_signature(last).map(|(_, hmac)| hmac.to_string()).unwrap_or_default())
+}
+
+// Read one line from stdin, or None if nothing arrives within timeout
+// After a timeout the reader thread stays blocked on stdin until the process exits
+fn read_line_timeout(timeout: Duration) -> Result<Option<String>> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut input = String::new();
+        let result = io::stdin().read_line(&mut input).map(|_| input);
+        let _ = sender.send(result);
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => Ok(Some(result?)),
+        Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(io::Error::other("stdin reader stopped unexpectedly").into()),
+    }
+}
+
+// Prompt text with the default appended before the trailing colon, e.g. "(yes/no) [no]: "
+fn prompt_with_default(prompt: &str, default: Option<&str>) -> String {
+    match default {
+        Some(default) => {
+            let stem = prompt.trim_end();
+            let stem = stem.strip_suffix(':').unwrap_or(stem).trim_end();
+            format!("{} [{}]: ", stem, default)
+        }
+        None => prompt.to_string(),
+    }
+}
+
+// A line read from the user, trimmed and length-checked, with an empty line replaced by the default
+fn checked_input(input: &str, default: Option<&str>) -> Result<String> {
+    let trimmed_input = input.trim();
+    if trimmed_input.len() > 255 {
+        return Err(SafeBackupError::InvalidPath("Input too long".to_string()));
+    }
+    match default {
+        Some(default) if trimmed_input.is_empty() => Ok(default.to_string()),
+        _ => Ok(trimmed_input.to_string()),
+    }
+}
+
+// The digest stored in a checksum sidecar, None when the sidecar is empty or only whitespace
+// Empty contents still have a digest, so an empty sidecar always means it was truncated
+fn sidecar_digest(sidecar: &str) -> Option<&str> {
+    sidecar.split_whitespace().next()
+}
+
+// Atomically write a small file such as a sidecar
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let (mut temp, mut file) = AtomicFile::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    temp.persist()
+}
+
+// Mark a finished backup read-only, so editors and scripts cannot change it in place
+fn make_read_only(path: &Path) -> Result<()> {
+    let mut permissions = fs::metadata(path).map_err(|e| io_error_at(path, e))?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(path, permissions).map_err(|e| io_error_at(path, e))
+}
+
+// Delete a backup file that may be read-only; Windows refuses to remove those until the bit is cleared
+fn remove_backup_file(path: &Path) -> io::Result<()> {
+    #[cfg(windows)]
+    if let Ok(metadata) = fs::symlink_metadata(path)
+        && metadata.permissions().readonly()
+    {
+        let mut permissions = metadata.permissions();
+        // Only the Windows read-only attribute is cleared here, not Unix mode bits
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(path, permissions)?;
+    }
+    fs::remove_file(path)
+}
+
+// Digest used for integrity hashes: SHA-256 by default, or BLAKE3, which is much faster on large files
+// Stored digests are plain hex for SHA-256 and "blake3:{hex}" for BLAKE3, so each one says how to check it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+const BLAKE3_TAG: &str = "blake3:";
+
+impl HashAlgorithm {
+    // The algorithm that produced a stored digest; untagged digests are SHA-256
+    fn of(digest: &str) -> Self {
+        if digest.starts_with(BLAKE3_TAG) { HashAlgorithm::Blake3 } else { HashAlgorithm::Sha256 }
+    }
+}
+
+// Incremental hasher for either algorithm, finishing into a tagged digest
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => hex_digest(&hasher.finalize()),
+            Hasher::Blake3(hasher) => format!("{}{}", BLAKE3_TAG, hasher.finalize().to_hex()),
+        }
+    }
+}
+
+// Reader adapter that hashes everything read through it and advances a progress bar
+struct HashingReader<R> {
+    inner: R,
+    hasher: Hasher,
+    progress: ProgressBar,
+    bytes: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R, progress: ProgressBar, algorithm: HashAlgorithm) -> Self {
+        HashingReader { inner, hasher: Hasher::new(algorithm), progress, bytes: 0 }
+    }
+
+    // Digest and byte count of everything read so far
+    fn finish(self) -> (String, u64) {
+        self.progress.finish_and_clear();
+        (self.hasher.finish(), self.bytes)
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.progress.inc(read as u64);
+        self.bytes += read as u64;
+        Ok(read)
+    }
+}
+
+// Digest of everything a reader yields, streamed so memory use stays constant
+fn hash_reader(reader: impl Read, algorithm: HashAlgorithm) -> Result<String> {
+    let mut reader = HashingReader::new(reader, ProgressBar::hidden(), algorithm);
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.finish().0)
+}
+
+// Copy reader into writer, gzipping on the way at the given level when there is one
+fn copy_compressed(reader: &mut impl Read, writer: &mut impl Write, compression: Option<Compression>) -> Result<()> {
+    if let Some(compression) = compression {
+        let mut encoder = GzEncoder::new(writer, compression);
+        io::copy(reader, &mut encoder)?;
+        encoder.finish()?;
+    } else {
+        io::copy(reader, writer)?;
+    }
+    Ok(())
+}
+
+// Environment variable holding the passphrase for encrypted backups; prompted for when unset
+const PASSPHRASE_ENV: &str = "SAFE_BACKUP_PASSPHRASE";
+
+// Environment variable answering yes/no confirmations when stdin is not a terminal
+const CONFIRM_ENV: &str = "SAFE_BACKUP_CONFIRM";
+
+// Encrypted backups start with this header: magic, Argon2 salt, then the STREAM nonce prefix.
+// The data follows as AES-256-GCM chunks of ENCRYPTION_CHUNK_SIZE plaintext bytes, each with
+// its own tag, so files of any size are encrypted without being held in memory
+const ENCRYPTION_MAGIC: &[u8; 8] = b"SBKENC01";
+const SALT_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 7;
+const ENCRYPTION_CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+// Derive the AES-256 key for a backup from the passphrase and its salt
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key<Aes256Gcm>> {
+    let mut key = Key::<Aes256Gcm>::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| SafeBackupError::DecryptionError(format!("Key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+// Read up to `len` bytes, stopping early only at end of input
+fn read_chunk(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(len);
+    reader.take(len as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+// A chunk of an encrypted backup failed authentication: wrong passphrase, corruption or truncation
+#[derive(Debug)]
+struct CorruptChunk;
+
+impl std::fmt::Display for CorruptChunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "encrypted backup chunk failed authentication")
+    }
+}
+
+impl std::error::Error for CorruptChunk {}
+
+// Writer adapter that encrypts everything written through it; finish() must be called
+// to write the final chunk, without which the backup is rejected as truncated
+struct EncryptWriter<W: Write> {
+    inner: W,
+    encryptor: EncryptorBE32<Aes256Gcm>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    fn new(mut inner: W, passphrase: &str) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        let key = derive_key(passphrase, &salt)?;
+
+        inner.write_all(ENCRYPTION_MAGIC)?;
+        inner.write_all(&salt)?;
+        inner.write_all(&nonce)?;
+        let encryptor = EncryptorBE32::from_aead(Aes256Gcm::new(&key), GenericArray::from_slice(&nonce));
+        Ok(EncryptWriter { inner, encryptor, buffer: Vec::with_capacity(ENCRYPTION_CHUNK_SIZE) })
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        let chunk = self.encryptor.encrypt_last(self.buffer.as_slice())
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        self.inner.write_all(&chunk)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full chunk is only sealed once more data arrives, since the last chunk is sealed differently
+        if self.buffer.len() == ENCRYPTION_CHUNK_SIZE {
+            let chunk = self.encryptor.encrypt_next(self.buffer.as_slice())
+                .map_err(|_| io::Error::other("encryption failed"))?;
+            self.inner.write_all(&chunk)?;
+            self.buffer.clear();
+        }
+        let taken = buf.len().min(ENCRYPTION_CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..taken]);
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Reader adapter that decrypts a backup written by EncryptWriter, one chunk at a time
+struct DecryptReader<R: Read> {
+    inner: R,
+    // None once the final chunk has been decrypted
+    decryptor: Option<DecryptorBE32<Aes256Gcm>>,
+    next_chunk: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptReader<R> {
+    fn new(mut inner: R, passphrase: &str) -> Result<Self> {
+        let mut header = [0u8; ENCRYPTION_MAGIC.len() + SALT_LEN + NONCE_PREFIX_LEN];
+        inner.read_exact(&mut header)
+            .map_err(|_| SafeBackupError::DecryptionError("Backup is too short to be encrypted".to_string()))?;
+        let (magic, rest) = header.split_at(ENCRYPTION_MAGIC.len());
+        if magic != ENCRYPTION_MAGIC {
+            return Err(SafeBackupError::DecryptionError("Backup is not encrypted by safe_backup".to_string()));
+        }
+        let (salt, nonce) = rest.split_at(SALT_LEN);
+        let key = derive_key(passphrase, salt)?;
+        let decryptor = DecryptorBE32::from_aead(Aes256Gcm::new(&key), GenericArray::from_slice(nonce));
+
+        let next_chunk = read_chunk(&mut inner, ENCRYPTION_CHUNK_SIZE + TAG_LEN)?;
+        let mut reader = DecryptReader { inner, decryptor: Some(decryptor), next_chunk, plaintext: Vec::new(), position: 0 };
+
+        // Decrypt the first chunk up front so a wrong passphrase is caught before anything is written
+        reader.refill()?;
+        Ok(reader)
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        let Some(decryptor) = self.decryptor.as_mut() else {
+            return Ok(());
+        };
+        let chunk = std::mem::take(&mut self.next_chunk);
+        self.next_chunk = read_chunk(&mut self.inner, ENCRYPTION_CHUNK_SIZE + TAG_LEN)?;
+        let plaintext = if self.next_chunk.is_empty() {
+            self.decryptor.take().map(|decryptor| decryptor.decrypt_last(chunk.as_slice()))
+        } else {
+            Some(decryptor.decrypt_next(chunk.as_slice()))
+        };
+        self.plaintext = plaintext.unwrap_or(Ok(Vec::new()))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, CorruptChunk))?;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.decryptor.is_none() {
+                return Ok(0);
+            }
+            self.refill()?;
+        }
+        let read = buf.len().min(self.plaintext.len() - self.position);
+        buf[..read].copy_from_slice(&self.plaintext[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+// Whether a file name ends in one of the backup extensions: {suffix}[.gz][.enc][.ptr] or {suffix}.delta,
+// where the suffix is .bak unless configured otherwise
+fn has_backup_extension(name: &str, suffix: &str) -> bool {
+    let name = name.strip_suffix(".ptr").or_else(|| name.strip_suffix(".delta")).unwrap_or(name);
+    let name = name.strip_suffix(".enc").unwrap_or(name);
+    let name = name.strip_suffix(".gz").unwrap_or(name);
+    name.ends_with(suffix)
+}
+
+// A backup suffix must stay inside the file name on every platform and must not be mistaken
+// for one of the extensions added after it
+fn validate_backup_suffix(suffix: &str) -> Result<()> {
+    let invalid = |reason: &str| Err(SafeBackupError::InvalidPath(format!("Invalid backup suffix '{}': {}", suffix, reason)));
+    if suffix.is_empty() {
+        return invalid("it cannot be empty");
+    }
+    if suffix.len() > 32 {
+        return invalid("it is longer than 32 characters");
+    }
+    if suffix.contains("..") || suffix.chars().any(|c| c.is_control() || "/\\<>:\"|?*".contains(c)) {
+        return invalid("it contains invalid path characters");
+    }
+    if suffix.ends_with('.') || suffix.ends_with(' ') {
+        return invalid("Windows would strip a trailing dot or space");
+    }
+    if RESERVED_EXTENSIONS.iter().any(|extension| suffix.ends_with(extension)) {
+        return invalid("it ends with an extension safe_backup adds itself");
+    }
+    Ok(())
+}
+
+// Whether a path is a backup or checksum file written by this program
+fn is_backup_artifact(path: &Path, suffix: &str) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    has_backup_extension(&name, suffix) || name.ends_with(".sha256") || name.ends_with(".meta") || is_internal_file(path)
+}
+
+// Whether a path is one of the hidden temp or lock files used while writing
+fn is_internal_file(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.starts_with('.') && (name.ends_with(".tmp") || name.ends_with(".lock"))
+}
+
+// Split a backup file name of the form {original}.{timestamp}{suffix}[.gz][.enc][.ptr] or {suffix}.delta into its parts:
+// original, version, timestamp, compressed, encrypted (of the stored blob, for pointers)
+// Content-named backups carry a digest prefix instead of a timestamp, and have no timestamp
+fn parse_backup_name<'a>(name: &'a str, suffix: &str) -> Option<(&'a str, &'a str, Option<Timestamp>, bool, bool)> {
+    let name = name.strip_suffix(".ptr").or_else(|| name.strip_suffix(".delta")).unwrap_or(name);
+    let (rest, encrypted) = match name.strip_suffix(".enc") {
+        Some(rest) => (rest, true),
+        None => (name, false),
+    };
+    let (rest, compressed) = match rest.strip_suffix(".gz") {
+        Some(rest) => (rest, true),
+        None => (rest, false),
+    };
+    let (original, version) = rest.strip_suffix(suffix)?.rsplit_once('.')?;
+    let timestamp = match parse_version_timestamp(version) {
+        Some((timestamp, _)) => Some(timestamp),
+        None if is_content_version(version) => None,
+        None => return None,
+    };
+    Some((original, version, timestamp, compressed, encrypted))
+}
+
+// The original part of a backup name that need not be UTF-8, given the tail its lossy text parsed from.
+// Lossy conversion leaves valid text such as the tail as it was, so dropping one extension per dot in
+// the tail keeps the original's bytes as given
+fn raw_original_name<'a>(raw_name: &'a OsStr, tail: &str) -> Option<&'a OsStr> {
+    let mut original = Path::new(raw_name);
+    for _ in tail.matches('.') {
+        original = Path::new(original.file_stem()?);
+    }
+    let tail_len = raw_name.len().checked_sub(original.as_os_str().len())?;
+    (tail_len == tail.len()).then_some(original.as_os_str())
+}
+
+// Timestamp of a timestamped version and its counter: backups made within the same second
+// are told apart by a -1, -2, ... after the timestamp, and the first one has none (counter 0)
+fn parse_version_timestamp(version: &str) -> Option<(Timestamp, u32)> {
+    if let Some(timestamp) = timestamp::parse_version(version) {
+        return Some((timestamp, 0));
+    }
+    let (timestamp, counter) = version.rsplit_once('-')?;
+    if counter.starts_with('0') || !counter.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((timestamp::parse_version(timestamp)?, counter.parse().ok()?))
+}
+
+// Order of backups sharing a timestamp, oldest first
+fn version_counter(version: &str) -> u32 {
+    parse_version_timestamp(version).map_or(0, |(_, counter)| counter)
+}
+
+// Version of a content-named backup: the first hex digits of its digest, without the algorithm tag
+fn content_version(digest: &str) -> String {
+    digest.strip_prefix(BLAKE3_TAG).unwrap_or(digest).chars().take(CONTENT_VERSION_LEN).collect()
+}
+
+fn is_content_version(version: &str) -> bool {
+    version.len() == CONTENT_VERSION_LEN && version.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+// Dedup backups are small pointer files naming a shared blob in the content-addressed store
+fn is_pointer(path: &Path) -> bool {
+    path.file_name().unwrap_or_default().to_string_lossy().ends_with(".ptr")
+}
+
+// Delta backups hold the changes that turn the next newer backup of the same file back into this one
+fn is_delta(path: &Path) -> bool {
+    path.file_name().unwrap_or_default().to_string_lossy().ends_with(".delta")
+}
+
+// Unified diff from old to new, empty when they match; binary contents are summarized by size
+fn diff_contents(old_label: &str, old: &[u8], new_label: &str, new: &[u8]) -> String {
+    match (as_text(old), as_text(new)) {
+        _ if old == new => String::new(),
+        (Some(old_text), Some(new_text)) => similar::TextDiff::from_lines(old_text, new_text)
+            .unified_diff()
+            .header(old_label, new_label)
+            .to_string(),
+        _ => format!("Binary files {} ({} bytes) and {} ({} bytes) differ\n", old_label, old.len(), new_label, new.len()),
+    }
+}
+
+// The first and last few changed lines of a unified diff, with a count of the ones left out between them
+fn summarize_diff(diff: &str) -> String {
+    let changed: Vec<&str> = diff.lines()
+        .filter(|line| (line.starts_with('+') || line.starts_with('-')) && !line.starts_with("+++") && !line.starts_with("---"))
+        .collect();
+    if diff.is_empty() {
+        return "No changes".to_string();
+    }
+    if changed.is_empty() {
+        return diff.trim_end().to_string();
+    }
+    if changed.len() <= 2 * PREVIEW_LINES {
+        return changed.join("\n");
+    }
+    format!(
+        "{}\n... {} more changed line(s) ...\n{}",
+        changed[..PREVIEW_LINES].join("\n"), changed.len() - 2 * PREVIEW_LINES, changed[changed.len() - PREVIEW_LINES..].join("\n")
+    )
+}
+
+// Contents as text, or None for binary data (invalid UTF-8 or containing NUL)
+fn as_text(bytes: &[u8]) -> Option<&str> {
+    std::str::from_utf8(bytes).ok().filter(|text| !text.contains('\0'))
+}
+
+// A reverse delta: copying byte ranges of the base and inserting text reproduces the older version
+#[derive(Debug, Serialize, Deserialize)]
+struct Delta {
+    // File name of the newer backup in the same directory when the delta was written;
+    // it gains a .delta suffix once a later backup turns it into a delta as well
+    base: String,
+    ops: Vec<DeltaOp>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DeltaOp {
+    Copy { offset: usize, len: usize },
+    Insert(String),
+}
+
+// Line-based delta turning base into target
+fn text_delta(base: &str, target: &str) -> Vec<DeltaOp> {
+    let diff = similar::TextDiff::from_lines(base, target);
+    let base_lines: Vec<&str> = diff.iter_old_slices().collect();
+    let target_lines: Vec<&str> = diff.iter_new_slices().collect();
+    let mut offsets = vec![0];
+    for line in &base_lines {
+        offsets.push(offsets.last().unwrap_or(&0) + line.len());
+    }
+
+    let mut ops = Vec::new();
+    for op in diff.ops() {
+        let (tag, base_range, target_range) = op.as_tag_tuple();
+        match tag {
+            similar::DiffTag::Equal => ops.push(DeltaOp::Copy {
+                offset: offsets[base_range.start],
+                len: offsets[base_range.end] - offsets[base_range.start],
+            }),
+            similar::DiffTag::Delete => {}
+            similar::DiffTag::Insert | similar::DiffTag::Replace => ops.push(DeltaOp::Insert(target_lines[target_range].concat())),
+        }
+    }
+    ops
+}
+
+// Rebuild the older version from its base; ranges outside the base mean the delta is corrupt
+fn apply_delta(base: &[u8], ops: &[DeltaOp]) -> Option<Vec<u8>> {
+    let mut contents = Vec::new();
+    for op in ops {
+        match op {
+            DeltaOp::Copy { offset, len } => contents.extend_from_slice(base.get(*offset..offset.checked_add(*len)?)?),
+            DeltaOp::Insert(text) => contents.extend_from_slice(text.as_bytes()),
+        }
+    }
+    Some(contents)
+}
+
+// Name of a zip archive entry, which always uses '/' between components
+fn archive_entry_name(path: &Path) -> String {
+    normalized_path(path).components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Reference count kept next to each blob in the store
+fn refs_path(blob_path: &Path) -> PathBuf {
+    let mut name = blob_path.as_os_str().to_os_string();
+    name.push(".refs");
+    PathBuf::from(name)
+}
+
+// Seconds in a relative duration such as "90m", "12h", "7d" or "2w"
+fn duration_seconds(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let unit_start = value.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: u64 = amount.parse().ok()?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    amount.checked_mul(unit_seconds)
+}
+
+// Parse a relative duration such as "90m", "12h", "7d" or "2w"
+#[cfg(feature = "chrono")]
+pub fn parse_duration(value: &str) -> Option<chrono::Duration> {
+    chrono::Duration::try_seconds(i64::try_from(duration_seconds(value)?).ok()?)
+}
+
+// parse_duration for timeouts and delays, which is available without the chrono feature
+pub fn parse_std_duration(value: &str) -> Option<Duration> {
+    Some(Duration::from_secs(duration_seconds(value)?))
+}
+
+// Parse a point in time given as a date ("2024-01-01", midnight UTC), an RFC 3339 timestamp,
+// or an age relative to now such as "7d"
+#[cfg(feature = "chrono")]
+pub fn parse_since(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some(date.and_hms_opt(0, 0, 0)?.and_utc());
+    }
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+    Utc::now().checked_sub_signed(parse_duration(value)?)
+}
+
+// Parse a byte size such as "4096", "512K", "100MB" or "2GiB"; units are powers of 1024
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let unit_start = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: u64 = amount.parse().ok()?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return None,
+    };
+    amount.checked_mul(multiplier)
+}
+
+// Format a byte count for messages, e.g. "512 bytes", "1.5 KB" or "2.0 GB"; units are powers of 1024 like parse_size
+// JSON output and the log keep raw byte counts
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return if bytes == 1 { "1 byte".to_string() } else { format!("{} bytes", bytes) };
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// The user's home directory, from HOME or, on Windows, USERPROFILE
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).filter(|home| !home.is_empty()).map(PathBuf::from)
+}
+
+// Expand a leading "~" in a configured path such as the backup directory or log file to the home directory
+// "~user" forms and a "~" anywhere else are left as they are; filenames to back up are never expanded
+pub fn expand_home(path: &Path) -> Result<PathBuf> {
+    let mut components = path.components();
+    if components.next() != Some(Component::Normal("~".as_ref())) {
+        return Ok(path.to_path_buf());
+    }
+    let home = home_dir().ok_or_else(|| SafeBackupError::InvalidPath(format!(
+        "Cannot expand '~' in '{}': the home directory is unknown (HOME is not set)", path.display()
+    )))?;
+    Ok(home.join(components.as_path()))
+}
+
+// Hex digits of the digest in the names of content-named backups
+const CONTENT_VERSION_LEN: usize = 16;
+
+// Format of the entries written to the log file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+// Severity of a log entry, from most to least severe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    fn label(self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+        }
+    }
+}
+
+// Outcome recorded alongside each log entry
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStatus {
+    Info,
+    Success,
+    Cancelled,
+    Error,
+}
+
+impl LogStatus {
+    // Level of an entry logged with this outcome: failures are errors and cancellations warnings
+    fn level(self) -> LogLevel {
+        match self {
+            LogStatus::Info | LogStatus::Success => LogLevel::Info,
+            LogStatus::Cancelled => LogLevel::Warn,
+            LogStatus::Error => LogLevel::Error,
+        }
+    }
+}
+
+// Number of hex digits of a digest kept in log entries
+const LOG_HASH_PREFIX_LEN: usize = 16;
+
+// Structured details attached to a log entry; text logs append them as key=value pairs
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LogFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+impl LogFields {
+    // Byte size and digest prefix of the original contents of a backed-up file,
+    // under the name of the algorithm that produced the digest
+    pub fn source(size: u64, digest: &str) -> Self {
+        let prefix = |hex: &str| Some(hex.chars().take(LOG_HASH_PREFIX_LEN).collect());
+        match digest.strip_prefix(BLAKE3_TAG) {
+            Some(hex) => LogFields { size: Some(size), blake3: prefix(hex), ..LogFields::default() },
+            None => LogFields { size: Some(size), sha256: prefix(digest), ..LogFields::default() },
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut pairs = Vec::new();
+        if let Some(size) = self.size {
+            pairs.push(format!("size={}", size));
+        }
+        if let Some(sha256) = &self.sha256 {
+            pairs.push(format!("sha256={}", sha256));
+        }
+        if let Some(blake3) = &self.blake3 {
+            pairs.push(format!("blake3={}", blake3));
+        }
+        pairs.join(" ")
+    }
+}
+
+// One JSON log line
+#[derive(Serialize)]
+struct LogRecord<'a> {
+    timestamp: String,
+    level: LogLevel,
+    action: &'a str,
+    status: LogStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<&'a str>,
+    #[serde(flatten)]
+    fields: &'a LogFields,
+}
+
+// A command given on the command line or at the prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Backup,
+    Restore,
+    Delete,
+    List,
+    RestoreAll,
+    Verify,
+    Diff,
+    Prune,
+    Status,
+    Usage,
+    Check,
+    Watch,
+    Migrate,
+    VerifyLog,
+    Daemon,
+}
+
+impl Command {
+    pub const ALL: [Command; 15] = [
+        Command::Backup, Command::Restore, Command::Delete, Command::List, Command::RestoreAll, Command::Verify, Command::Diff,
+        Command::Prune, Command::Status, Command::Usage, Command::Check, Command::Watch, Command::Migrate, Command::VerifyLog,
+        Command::Daemon,
+    ];
+
+    // The name typed to run the command
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Backup => "backup",
+            Command::Restore => "restore",
+            Command::Delete => "delete",
+            Command::List => "list",
+            Command::RestoreAll => "restore-all",
+            Command::Verify => "verify",
+            Command::Diff => "diff",
+            Command::Prune => "prune",
+            Command::Status => "status",
+            Command::Usage => "usage",
+            Command::Check => "check",
+            Command::Watch => "watch",
+            Command::Migrate => "migrate",
+            Command::VerifyLog => "verify-log",
+            Command::Daemon => "daemon",
+        }
+    }
+
+    // Commands that work on every backup instead of a named file; restore-all takes an optional directory
+    pub fn takes_filename(self) -> bool {
+        !matches!(
+            self,
+            Command::Status | Command::Usage | Command::Check | Command::Migrate | Command::VerifyLog | Command::RestoreAll | Command::Daemon
+        )
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Command names are matched case-insensitively
+impl TryFrom<&str> for Command {
+    type Error = SafeBackupError;
+
+    fn try_from(name: &str) -> Result<Self> {
+        Command::ALL.into_iter()
+            .find(|command| command.name().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| SafeBackupError::InvalidPath(format!("Unknown command: '{}'", name)))
+    }
+}
+
+// What a command did, so callers can inspect the result instead of scraping stdout
+// Serializes as a JSON object whose "action" key names the variant
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ActionOutcome {
+    #[serde(rename = "backup")]
+    Backed {
+        #[serde(rename = "backup_path")]
+        path: PathBuf,
+        // Size of the original contents read, before compression or encryption
+        bytes: u64,
+    },
+    Unchanged { latest: PathBuf },
+    #[serde(rename = "backup_many")]
+    BackedMany { paths: Vec<PathBuf>, bytes: u64 },
+    #[serde(rename = "restore")]
+    Restored { path: PathBuf, from: PathBuf, bytes: u64 },
+    #[serde(rename = "restore_all")]
+    RestoredAll { summary: RestoreSummary },
+    #[serde(rename = "restore_all_versions")]
+    ExportedVersions { paths: Vec<PathBuf>, bytes: u64 },
+    #[serde(rename = "backup_directory")]
+    BackedDirectory { summary: DirectoryBackupSummary },
+    #[serde(rename = "delete")]
+    Deleted,
+    #[serde(rename = "delete_many")]
+    DeletedMany { paths: Vec<PathBuf> },
+    Cancelled,
+    #[serde(rename = "verify")]
+    Verified { matches: bool },
+    #[serde(rename = "diff")]
+    Diffed { diff: String },
+    #[serde(rename = "prune")]
+    Pruned { removed: Vec<PathBuf> },
+    #[serde(rename = "list")]
+    Listed { backups: Vec<BackupEntry> },
+    Status { files: Vec<FileStatus> },
+    Usage { files: Vec<FileUsage>, total: u64 },
+    #[serde(rename = "check")]
+    Checked { problems: Vec<IntegrityProblem> },
+    #[serde(rename = "watch")]
+    Watched { backups: usize },
+    #[serde(rename = "migrate")]
+    Migrated { paths: Vec<PathBuf> },
+    #[serde(rename = "verify_log")]
+    LogVerified { entries: usize },
+    UnknownCommand { command: String },
+}
+
+// Result of restoring every backed-up file under a directory
+#[derive(Debug, Default, Serialize)]
+pub struct RestoreSummary {
+    pub restored: Vec<PathBuf>,
+    pub skipped: usize,
+    pub failed: usize,
+    pub bytes: u64,
+}
+
+// What restoring the latest backup of a file would change, shown before the restore goes ahead
+#[derive(Debug, Serialize)]
+pub struct RestorePreview {
+    pub backup: PathBuf,
+    pub version: String,
+    // None when the file does not exist and the restore would create it
+    pub current_size: Option<u64>,
+    // Size of the original contents in the backup, after decompressing and decrypting
+    pub backup_size: u64,
+    // The first and last changed lines of the diff from the current file to the backup
+    pub summary: String,
+}
+
+// Result of backing up every file under a directory
+#[derive(Debug, Default, Serialize)]
+pub struct DirectoryBackupSummary {
+    pub backed_up: Vec<PathBuf>,
+    // The files the backups in backed_up were made of, in the same order
+    pub backed_up_files: Vec<PathBuf>,
+    pub unchanged: usize,
+    pub failed: usize,
+    pub skipped_symlinks: usize,
+    pub excluded: usize,
+    pub skipped_hidden: usize,
+    // Files outside the exclude_larger_than / exclude_smaller_than range
+    pub skipped_size: usize,
+    // Directories reached again through a followed symlink, which are not walked twice
+    pub skipped_cycles: usize,
+    pub bytes: u64,
+}
+
+// How a live file compares with its latest backup
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LiveState {
+    Matches,
+    Differs,
+    Missing,
+}
+
+// Backup health of one original file, as reported by status
+#[derive(Debug, Serialize)]
+pub struct FileStatus {
+    pub original: PathBuf,
+    pub versions: usize,
+    #[cfg_attr(not(feature = "chrono"), serde(serialize_with = "timestamp::serialize"))]
+    pub newest: Timestamp,
+    pub total_size: u64,
+    pub live: LiveState,
+}
+
+// Disk space taken by the backups of one original file, as reported by usage
+#[derive(Debug, Serialize)]
+pub struct FileUsage {
+    pub original: PathBuf,
+    pub versions: usize,
+    pub bytes: u64,
+}
+
+// A backup that failed the integrity sweep, and why
+#[derive(Debug, Serialize)]
+pub struct IntegrityProblem {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+// A single backup found on disk for a given file
+#[derive(Debug, Serialize)]
+pub struct BackupEntry {
+    pub path: PathBuf,
+    pub version: String,
+    #[cfg_attr(not(feature = "chrono"), serde(serialize_with = "timestamp::serialize"))]
+    pub created: Timestamp,
+    pub size: u64,
+    pub compressed: bool,
+    pub encrypted: bool,
+}
+
+// Names Windows reserves for devices, such as CON or NUL.txt
+#[cfg(windows)]
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// Index of every backup, kept as manifest.json at the root of the backup directory
+// so backups can be listed and located without scanning directories
+const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Manifest {
+    backups: Vec<ManifestEntry>,
+}
+
+// One backup recorded in the manifest; paths are stored without any "./" prefix
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManifestEntry {
+    original: PathBuf,
+    path: PathBuf,
+    version: String,
+    created: Timestamp,
+    size: u64,
+    // Tagged digest of the original contents; manifests written before BLAKE3 support call it sha256
+    #[serde(alias = "sha256")]
+    digest: String,
+    compressed: bool,
+    encrypted: bool,
+}
+
+impl ManifestEntry {
+    fn to_backup_entry(&self) -> BackupEntry {
+        BackupEntry {
+            path: self.path.clone(),
+            version: self.version.clone(),
+            created: self.created,
+            size: self.size,
+            compressed: self.compressed,
+            encrypted: self.encrypted,
+        }
+    }
+}
+
+// A completed step of a multi-file operation, and how to undo it
+enum Step {
+    // A new backup, undone by removing it with its sidecars and manifest entry
+    Created(PathBuf),
+    // A file renamed into a backup, undone by moving it back
+    Moved { from: PathBuf, to: PathBuf },
+}
+
+// Completed steps of a batch backup or migration, so a failure partway through can undo them
+// instead of leaving the backups half done
+#[derive(Default)]
+struct Transaction {
+    steps: Vec<Step>,
+}
+
+impl Transaction {
+    fn created(&mut self, path: PathBuf) {
+        self.steps.push(Step::Created(path));
+    }
+
+    fn moved(&mut self, from: PathBuf, to: PathBuf) {
+        self.steps.push(Step::Moved { from, to });
+    }
+
+    // Undo the steps newest first, returning how many were undone; every step is attempted,
+    // and the first one that could not be undone is returned as the error
+    fn rollback(self, app: &SafeBackup) -> Result<usize> {
+        let mut first_error = None;
+        let mut undone = 0;
+        for step in self.steps.into_iter().rev() {
+            let (result, step) = match step {
+                Step::Created(path) => (app.remove_backup(&path), format!("backup '{}'", path.display())),
+                Step::Moved { from, to } => (app.move_back(&to, &from), format!("'{}' to '{}'", to.display(), from.display())),
+            };
+            match result {
+                Ok(()) => {
+                    undone += 1;
+                    app.log_event(&format!("Rolled back {}", step), LogStatus::Cancelled, None)?;
+                }
+                Err(e) => {
+                    app.log_event(&format!("Could not roll back {}: {}", step, e), LogStatus::Error, None)?;
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(undone),
+        }
+    }
+}
+
+// Fully resolved form of a path for diagnostics, or the path itself when it cannot be resolved
+fn resolved_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Resolved path used to tell whether two paths are the same file; a path that does not
+// exist yet is resolved through its parent directory
+fn comparable_path(path: &Path) -> Option<PathBuf> {
+    if let Ok(resolved) = fs::canonicalize(path) {
+        return Some(resolved);
+    }
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    Some(fs::canonicalize(parent).ok()?.join(path.file_name()?))
+}
+
+// Refuse to copy a file onto itself, which would read and write the same path at once
+fn ensure_distinct(source: &Path, destination: &Path) -> Result<()> {
+    if let (Some(source_resolved), Some(destination_resolved)) = (comparable_path(source), comparable_path(destination))
+        && source_resolved == destination_resolved
+    {
+        return Err(SafeBackupError::InvalidPath(format!(
+            "'{}' and '{}' are the same file", source.display(), destination.display()
+        )));
+    }
+    Ok(())
+}
+
+// Fill in the placeholders of a backup path template for one file at one point in time
+// Unknown placeholders and templates that expand to nothing are rejected
+fn expand_path_template(template: &str, file_path: &Path, now: Timestamp) -> Result<String> {
+    let invalid = |reason: &str| SafeBackupError::InvalidPath(format!("Invalid path template '{}': {}", template, reason));
+    let lossy = |part: Option<&std::ffi::OsStr>| part.unwrap_or_default().to_string_lossy().into_owned();
+
+    let parts = timestamp::parts(&now);
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| invalid("unclosed '{'"))? + start;
+        expanded.push_str(&match &rest[start + 1..end] {
+            "year" => format!("{:04}", parts.year),
+            "month" => format!("{:02}", parts.month),
+            "day" => format!("{:02}", parts.day),
+            "timestamp" => timestamp::format_version(&now),
+            "filename" => lossy(file_path.file_name()),
+            "name" => lossy(file_path.file_stem()),
+            "ext" => lossy(file_path.extension()),
+            other => return Err(invalid(&format!("unknown placeholder {{{}}}", other))),
+        });
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    if expanded.trim().is_empty() || expanded.ends_with('/') || expanded.ends_with('\\') {
+        return Err(invalid("it does not end in a file name"));
+    }
+    Ok(expanded)
+}
+
+// Path without the Windows extended-length prefix (\\?\C:\ or \\?\UNC\), which fs::canonicalize adds
+// and current_dir usually lacks, so containment checks compare like with like; other paths are unchanged
+fn without_verbatim_prefix(path: &Path) -> PathBuf {
+    let mut components = path.components();
+    let Some(Component::Prefix(prefix)) = components.next() else {
+        return path.to_path_buf();
+    };
+    let mut plain = match prefix.kind() {
+        Prefix::VerbatimDisk(disk) => PathBuf::from(format!("{}:", disk as char)),
+        Prefix::VerbatimUNC(server, share) => PathBuf::from(format!(r"\\{}\{}", server.to_string_lossy(), share.to_string_lossy())),
+        _ => return path.to_path_buf(),
+    };
+    plain.push(components.as_path());
+    plain
+}
+
+// Path with "." components removed, so "./notes.txt" and "notes.txt" name the same manifest entry
+fn normalized_path(path: &Path) -> PathBuf {
+    path.components().filter(|c| !matches!(c, Component::CurDir)).collect()
+}
+
+pub struct SafeBackup {
+    log_file: PathBuf,
+    // Directory filenames are checked against, captured once so validate_path does not read the global
+    base_dir: PathBuf,
+    // What validated paths are joined to: nothing when base_dir is the working directory, so they stay
+    // relative, and base_dir itself otherwise, so they can be opened from anywhere
+    root: PathBuf,
+    compress: bool,
+    compression_level: u32,
+    force: bool,
+    dry_run: bool,
+    quiet: bool,
+    verbose: bool,
+    follow_symlinks: bool,
+    encrypt: bool,
+    // Read from the environment or prompted for at most once per run
+    passphrase: OnceLock<String>,
+    // Held while rotating and appending to the log, so concurrent backups never interleave lines
+    log_lock: Mutex<()>,
+    backup_dir: PathBuf,
+    log_format: LogFormat,
+    // Entries less severe than this are not written
+    log_level: LogLevel,
+    max_log_size: u64,
+    log_keep: usize,
+    retry_attempts: u32,
+    retry_delay: Duration,
+    // Offsite destination replacing the local backup directory for new backups
+    remote: Option<SftpTarget>,
+    // Largest source file backup_file accepts, in bytes; None means unlimited
+    max_file_size: Option<u64>,
+    // Glob patterns skipped by backup_directory
+    exclude: Vec<glob::Pattern>,
+    exclude_larger_than: Option<u64>,
+    exclude_smaller_than: Option<u64>,
+    // Whether backup_directory walks into entries whose name starts with '.'
+    include_hidden: bool,
+    // Mark new backups read-only so they are not modified by accident
+    read_only_backups: bool,
+    // Store contents once under store/{sha256} and write pointers as the per-file backups
+    dedup: bool,
+    // Unanswered delete confirmations are treated as "no" after this long
+    confirm_timeout: Duration,
+    // Zip archive collecting new backups instead of individual .bak files
+    archive: Option<PathBuf>,
+    // Keep only the newest backup of a text file in full and turn older ones into reverse deltas
+    delta: bool,
+    // Restore refuses to replace an existing file unless force is also set
+    no_clobber: bool,
+    // Read sources from a point-in-time snapshot of their volume instead of the live file
+    snapshot: bool,
+    // Digest recorded for new backups; existing backups are checked with the one they were recorded with
+    hash_algorithm: HashAlgorithm,
+    // Quiet period after a change before watch_file backs the file up
+    watch_debounce: Duration,
+    // Layout of new backups under the backup directory, such as "{year}/{month}/{filename}"
+    path_template: Option<String>,
+    // Name new backups after a prefix of their digest instead of the time, so identical contents share one backup
+    content_names: bool,
+    // Ends every backup name before the .gz/.enc/.ptr/.delta extensions; backups made with
+    // another suffix are not listed, restored or pruned
+    backup_suffix: String,
+    // How many files backup_files backs up at the same time
+    jobs: usize,
+    // Undo the completed steps of a batch backup or migration when one of its files fails
+    rollback: bool,
+    // Signs each log line with an HMAC chained to the previous line, so edits show up in verify_log
+    log_key: Option<Vec<u8>>,
+    // Where backups and sidecars are written and read: LocalFs, the archive, or a custom backend
+    storage: Arc<dyn StorageBackend>,
+}
+
+// Chainable options for SafeBackup; build() checks that they fit together
+#[derive(Debug, Clone)]
+pub struct SafeBackupBuilder {
+    log_file: PathBuf,
+    base_dir: Option<PathBuf>,
+    compress: bool,
+    compression_level: u32,
+    force: bool,
+    dry_run: bool,
+    quiet: bool,
+    verbose: bool,
+    follow_symlinks: bool,
+    encrypt: bool,
+    backup_dir: PathBuf,
+    log_format: LogFormat,
+    log_level: LogLevel,
+    max_log_size: u64,
+    log_keep: usize,
+    retry_attempts: u32,
+    retry_delay: Duration,
+    remote: Option<SftpTarget>,
+    max_file_size: Option<u64>,
+    exclude: Vec<String>,
+    exclude_larger_than: Option<u64>,
+    exclude_smaller_than: Option<u64>,
+    include_hidden: bool,
+    read_only_backups: bool,
+    dedup: bool,
+    confirm_timeout: Duration,
+    archive: Option<PathBuf>,
+    delta: bool,
+    no_clobber: bool,
+    snapshot: bool,
+    hash_algorithm: HashAlgorithm,
+    watch_debounce: Duration,
+    path_template: Option<String>,
+    content_names: bool,
+    backup_suffix: String,
+    jobs: usize,
+    rollback: bool,
+    log_key: Option<Vec<u8>>,
+    storage: Option<Arc<dyn StorageBackend>>,
+}
+
+impl Default for SafeBackupBuilder {
+    fn default() -> Self {
+        SafeBackupBuilder {
+            log_file: PathBuf::from(DEFAULT_LOG_FILE),
+            base_dir: None,
+            compress: false,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            force: false,
+            dry_run: false,
+            quiet: false,
+            verbose: false,
+            follow_symlinks: false,
+            encrypt: false,
+            backup_dir: PathBuf::from("."),
+            log_format: LogFormat::Text,
+            log_level: LogLevel::Info,
+            max_log_size: DEFAULT_MAX_LOG_SIZE,
+            log_keep: DEFAULT_LOG_KEEP,
+            retry_attempts: DEFAULT_RETRY_ATTEMPTS,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            remote: None,
+            max_file_size: None,
+            exclude: Vec::new(),
+            exclude_larger_than: None,
+            exclude_smaller_than: None,
+            include_hidden: false,
+            read_only_backups: false,
+            dedup: false,
+            confirm_timeout: DEFAULT_CONFIRM_TIMEOUT,
+            archive: None,
+            delta: false,
+            no_clobber: false,
+            snapshot: false,
+            hash_algorithm: HashAlgorithm::Sha256,
+            watch_debounce: DEFAULT_WATCH_DEBOUNCE,
+            path_template: None,
+            content_names: false,
+            backup_suffix: DEFAULT_BACKUP_SUFFIX.to_string(),
+            jobs: 1,
+            rollback: false,
+            log_key: None,
+            storage: None,
+        }
+    }
+}
+
+impl SafeBackupBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Log to a custom path, which may live outside the working directory
+    pub fn log_file(mut self, log_file: impl Into<PathBuf>) -> Self {
+        self.log_file = log_file.into();
+        self
+    }
+
+    // Directory that filenames are relative to and must stay inside, instead of the working directory at build time
+    pub fn base_dir(mut self, base_dir: impl Into<PathBuf>) -> Self {
+        self.base_dir = Some(base_dir.into());
+        self
+    }
+
+    // Store backups under this directory (relative to the working directory) instead of next to each file
+    pub fn backup_dir(mut self, backup_dir: impl Into<PathBuf>) -> Self {
+        self.backup_dir = backup_dir.into();
+        self
+    }
+
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    // Gzip level for compressed backups, from 0 (no compression) to 9 (smallest, slowest); default 6
+    pub fn compression_level(mut self, compression_level: u32) -> Self {
+        self.compression_level = compression_level;
+        self
+    }
+
+    // Skip confirmations, and let restore-all overwrite files newer than their backup
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    // Print resolved paths and byte counts for each operation
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    pub fn encrypt(mut self, encrypt: bool) -> Self {
+        self.encrypt = encrypt;
+        self
+    }
+
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+        self
+    }
+
+    // Only write log entries at least as severe as level; debug also logs the --verbose details
+    pub fn log_level(mut self, level: LogLevel) -> Self {
+        self.log_level = level;
+        self
+    }
+
+    // Rotate the log once it reaches max_log_size bytes, keeping log_keep rotated files
+    pub fn log_rotation(mut self, max_log_size: u64, log_keep: usize) -> Self {
+        self.max_log_size = max_log_size;
+        self.log_keep = log_keep;
+        self
+    }
+
+    // Try transient IO failures up to `attempts` times in total, doubling the delay after each one
+    pub fn retry(mut self, attempts: u32, initial_delay: Duration) -> Self {
+        self.retry_attempts = attempts;
+        self.retry_delay = initial_delay;
+        self
+    }
+
+    // Keep backups on an SFTP server instead of the local backup directory
+    pub fn remote(mut self, remote: SftpTarget) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    // Refuse to back up files larger than this many bytes
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    // Skip files and directories matching these glob patterns (e.g. "*.log", "node_modules") in
+    // directory backups; each is matched against the path relative to the directory and the entry name
+    pub fn exclude(mut self, patterns: Vec<String>) -> Self {
+        self.exclude = patterns;
+        self
+    }
+
+    // Skip files of more than this many bytes in directory backups, such as videos among documents
+    pub fn exclude_larger_than(mut self, bytes: u64) -> Self {
+        self.exclude_larger_than = Some(bytes);
+        self
+    }
+
+    // Skip files of fewer than this many bytes in directory backups, such as empty lock or temp files
+    pub fn exclude_smaller_than(mut self, bytes: u64) -> Self {
+        self.exclude_smaller_than = Some(bytes);
+        self
+    }
+
+    // Back up dotfiles and dot-directories such as .env or .ssh in directory backups, which are skipped by default
+    pub fn include_hidden(mut self, include_hidden: bool) -> Self {
+        self.include_hidden = include_hidden;
+        self
+    }
+
+    // Make each new backup file read-only once it is written; pruning and deleting still remove it
+    // Fails to build with an archive or an sftp:// destination, whose backups are not local files
+    pub fn read_only_backups(mut self, read_only_backups: bool) -> Self {
+        self.read_only_backups = read_only_backups;
+        self
+    }
+
+    // Write each backup's contents once to a content-addressed store, so identical files share one copy
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    // Cancel a delete whose confirmation is not answered within this time (30 seconds by default)
+    pub fn confirm_timeout(mut self, confirm_timeout: Duration) -> Self {
+        self.confirm_timeout = confirm_timeout;
+        self
+    }
+
+    // Wait until a watched file has been unchanged this long before backing it up (1 second by default)
+    pub fn watch_debounce(mut self, watch_debounce: Duration) -> Self {
+        self.watch_debounce = watch_debounce;
+        self
+    }
+
+    // Place new backups at this template under the backup directory, with {year}, {month}, {day},
+    // {timestamp}, {filename}, {name} (the stem) and {ext} filled in; the usual .{timestamp}.bak
+    // suffix is still appended, so "{year}/{month}/{filename}" gives 2024/05/notes.txt.{timestamp}.bak
+    pub fn path_template(mut self, path_template: impl Into<String>) -> Self {
+        self.path_template = Some(path_template.into());
+        self
+    }
+
+    // Name new backups {filename}.{digest prefix}.bak instead of by timestamp; backing up contents
+    // that already have a backup only marks that backup as the newest, so repeated runs are idempotent
+    pub fn content_names(mut self, content_names: bool) -> Self {
+        self.content_names = content_names;
+        self
+    }
+
+    // Sign every log line with HMAC-SHA256 under this key, each chained to the line before it,
+    // so verify_log can tell when lines were edited, inserted, reordered or removed
+    pub fn log_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.log_key = Some(key.into());
+        self
+    }
+
+    // Back up this many files of a batch at once (default 1); progress bars are hidden when above 1
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    // When a file of backup_files or migrate fails, remove the backups the batch already wrote (or move
+    // migrated files back) before returning the error, instead of keeping the part that succeeded
+    pub fn rollback(mut self, rollback: bool) -> Self {
+        self.rollback = rollback;
+        self
+    }
+
+    // End backup names with this suffix instead of .bak, e.g. "~" or ".backup"
+    pub fn backup_suffix(mut self, backup_suffix: impl Into<String>) -> Self {
+        self.backup_suffix = backup_suffix.into();
+        self
+    }
+
+    // Keep backups and their sidecars in this backend instead of as files on the local filesystem
+    // Dedup, delta, content-named and read-only backups and archives need the local filesystem
+    pub fn storage(mut self, storage: impl StorageBackend + 'static) -> Self {
+        self.storage = Some(Arc::new(storage));
+        self
+    }
+
+    // Append backups to this zip archive (created on first use) instead of writing .bak files;
+    // each entry is named {original}.{timestamp}.bak[.gz][.enc] with its sidecars next to it
+    pub fn archive(mut self, archive: impl Into<PathBuf>) -> Self {
+        self.archive = Some(archive.into());
+        self
+    }
+
+    // Store the newest backup of a text file in full and rewrite the previous one as a reverse delta
+    // against it, so restoring an older version walks the chain; binary files keep full copies
+    pub fn delta(mut self, delta: bool) -> Self {
+        self.delta = delta;
+        self
+    }
+
+    // Make restore fail with DestinationExists instead of overwriting an existing file;
+    // force still allows the overwrite
+    pub fn no_clobber(mut self, no_clobber: bool) -> Self {
+        self.no_clobber = no_clobber;
+        self
+    }
+
+    // Back up from a volume snapshot (such as a Windows shadow copy), so files other programs
+    // hold open are copied consistently; no platform supports this yet and backups fail when set
+    pub fn snapshot(mut self, snapshot: bool) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    // Digest recorded in sidecars and the manifest for new backups (SHA-256 by default);
+    // BLAKE3 is several times faster on large files
+    pub fn hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    // Fails with IoError if the log cannot be opened for appending,
+    // and with InvalidPath if the backup directory is unsafe or not a directory, or an exclude pattern is invalid
+    pub fn build(self) -> Result<SafeBackup> {
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_file)?;
+
+        let exclude = self.exclude.iter()
+            .map(|pattern| glob::Pattern::new(pattern)
+                .map_err(|e| SafeBackupError::InvalidPath(format!("Invalid exclude pattern '{}': {}", pattern, e))))
+            .collect::<Result<Vec<_>>>()?;
+
+        let current_dir = std::env::current_dir()?;
+        let base_dir = match self.base_dir {
+            Some(dir) => {
+                let dir = current_dir.join(dir);
+                without_verbatim_prefix(&fs::canonicalize(&dir).map_err(|e| io_error_at(&dir, e))?)
+            }
+            None => without_verbatim_prefix(&current_dir),
+        };
+        let root = if base_dir == without_verbatim_prefix(&current_dir) { PathBuf::new() } else { base_dir.clone() };
+
+        let mut app = SafeBackup {
+            log_file: self.log_file,
+            base_dir,
+            root,
+            compress: self.compress,
+            compression_level: self.compression_level,
+            force: self.force,
+            dry_run: self.dry_run,
+            quiet: self.quiet,
+            verbose: self.verbose,
+            follow_symlinks: self.follow_symlinks,
+            encrypt: self.encrypt,
+            passphrase: OnceLock::new(),
+            log_lock: Mutex::new(()),
+            backup_dir: PathBuf::from("."),
+            log_format: self.log_format,
+            log_level: self.log_level,
+            max_log_size: self.max_log_size,
+            log_keep: self.log_keep,
+            retry_attempts: self.retry_attempts.max(1),
+            retry_delay: self.retry_delay,
+            remote: self.remote,
+            max_file_size: self.max_file_size,
+            exclude,
+            exclude_larger_than: self.exclude_larger_than,
+            exclude_smaller_than: self.exclude_smaller_than,
+            include_hidden: self.include_hidden,
+            read_only_backups: self.read_only_backups,
+            dedup: self.dedup,
+            confirm_timeout: self.confirm_timeout,
+            archive: None,
+            delta: self.delta,
+            no_clobber: self.no_clobber,
+            snapshot: self.snapshot,
+            hash_algorithm: self.hash_algorithm,
+            watch_debounce: self.watch_debounce,
+            path_template: self.path_template,
+            content_names: self.content_names,
+            backup_suffix: self.backup_suffix,
+            jobs: self.jobs.max(1),
+            rollback: self.rollback,
+            log_key: self.log_key,
+            storage: Arc::new(LocalFs),
+        };
+        if let Some(storage) = &self.storage {
+            app.storage = RelativeNames::under(&app.root, Arc::clone(storage));
+        }
+
+        if app.remote.is_some() && !cfg!(feature = "sftp") {
+            return Err(SafeBackupError::InvalidPath("sftp:// destinations need safe_backup built with the sftp feature".to_string()));
+        }
+        if app.remote.is_some() && app.dedup {
+            return Err(SafeBackupError::InvalidPath("Deduplicated backups cannot be uploaded to an sftp:// destination".to_string()));
+        }
+        if app.read_only_backups && (app.remote.is_some() || self.archive.is_some()) {
+            return Err(SafeBackupError::InvalidPath("Read-only backups cannot be combined with an archive or an sftp:// destination".to_string()));
+        }
+        if app.delta && (app.compress || app.encrypt || app.dedup || app.remote.is_some() || self.archive.is_some()) {
+            return Err(SafeBackupError::InvalidPath("Delta backups cannot be combined with compression, encryption, dedup, an archive or an sftp:// destination".to_string()));
+        }
+        if app.content_names && (app.delta || app.remote.is_some() || self.archive.is_some()) {
+            return Err(SafeBackupError::InvalidPath("Content-named backups cannot be combined with delta backups, an archive or an sftp:// destination".to_string()));
+        }
+        if app.rollback && (app.delta || app.remote.is_some() || self.archive.is_some()) {
+            return Err(SafeBackupError::InvalidPath("Rollback cannot be combined with delta backups, an archive or an sftp:// destination".to_string()));
+        }
+        if self.storage.is_some()
+            && (app.dedup || app.delta || app.content_names || app.read_only_backups || app.remote.is_some() || self.archive.is_some())
+        {
+            return Err(SafeBackupError::InvalidPath(
+                "A custom storage backend cannot be combined with dedup, delta, content-named or read-only backups, an archive or an sftp:// destination".to_string()
+            ));
+        }
+        if app.compression_level > MAX_COMPRESSION_LEVEL {
+            return Err(SafeBackupError::InvalidPath(format!(
+                "Compression level {} is out of range (expected 0 to {})", app.compression_level, MAX_COMPRESSION_LEVEL
+            )));
+        }
+        if let (Some(larger), Some(smaller)) = (app.exclude_larger_than, app.exclude_smaller_than)
+            && smaller > larger
+        {
+            return Err(SafeBackupError::InvalidPath(format!(
+                "Excluding files smaller than {} and larger than {} would exclude every file", format_size(smaller), format_size(larger)
+            )));
+        }
+        validate_backup_suffix(&app.backup_suffix)?;
+        if let Some(template) = &app.path_template {
+            if !cfg!(feature = "chrono") {
+                return Err(SafeBackupError::InvalidPath("A path template needs the chrono feature, as its backups are only found through the manifest".to_string()));
+            }
+            if app.remote.is_some() || self.archive.is_some() || app.delta {
+                return Err(SafeBackupError::InvalidPath("A path template cannot be combined with an sftp:// destination, an archive or delta backups".to_string()));
+            }
+            expand_path_template(template, Path::new("file.txt"), timestamp::now())?;
+        }
+        if let Some(archive) = &self.archive {
+            if app.remote.is_some() || app.dedup {
+                return Err(SafeBackupError::InvalidPath("A zip archive cannot be combined with an sftp:// destination or dedup".to_string()));
+            }
+            let archive = app.validate_path(&archive.to_string_lossy())?;
+            if archive.exists() && !archive.is_file() {
+                return Err(SafeBackupError::InvalidPath(format!("Archive '{}' is not a file", archive.display())));
+            }
+            app.storage = Arc::new(ZipStorage::new(&archive));
+            app.archive = Some(archive);
+        }
+        #[cfg(feature = "sftp")]
+        if let Some(remote) = &app.remote {
+            app.storage = RelativeNames::under(&app.root, Arc::new(SftpStorage::new(remote.clone())));
+        }
+
+        // An absolute backup directory, such as an expanded ~/backups, is accepted when it is inside the base directory
+        let backup_dir = match self.backup_dir.strip_prefix(&app.base_dir) {
+            Ok(relative) if relative.as_os_str().is_empty() => PathBuf::from("."),
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) if self.backup_dir.is_absolute() => {
+                return Err(SafeBackupError::InvalidPath(format!(
+                    "Backup directory '{}' must be inside the current directory", self.backup_dir.display()
+                )));
+            }
+            Err(_) => self.backup_dir,
+        };
+        if backup_dir != Path::new(".") {
+            let backup_dir = app.validate_path(&backup_dir.to_string_lossy())?;
+            if backup_dir.exists() && !backup_dir.is_dir() {
+                return Err(SafeBackupError::InvalidPath(format!("Backup directory '{}' is not a directory", backup_dir.display())));
+            }
+            app.backup_dir = backup_dir;
+        }
+        Ok(app)
+    }
+}
+
+impl SafeBackup {
+    // All-default configuration, logging to logfile.txt
+    pub fn new() -> Result<Self> {
+        SafeBackupBuilder::new().build()
+    }
+
+    // Log to a custom path, which may live outside the working directory
+    // Fails early with IoError if the log cannot be opened for appending
+    pub fn new_with_log(log_file: PathBuf) -> Result<Self> {
+        SafeBackupBuilder::new().log_file(log_file).build()
+    }
+
+    pub fn builder() -> SafeBackupBuilder {
+        SafeBackupBuilder::new()
+    }
+
+    // Secure path validation - prevents path traversal attacks
+    pub fn validate_path(&self, filename: &str) -> Result<PathBuf> {
+        self.validate_os_path(OsStr::new(filename))
+    }
+
+    // validate_path for names that need not be UTF-8, as filenames on Unix (arbitrary bytes) and
+    // Windows (UTF-16) may be. The checks run on the lossy text, where only invalid sequences become
+    // U+FFFD, so dots, separators and control characters are still seen; the path itself is kept as given
+    pub fn validate_os_path(&self, os_filename: &OsStr) -> Result<PathBuf> {
+        // Paths returned for a base directory other than the working directory start with it,
+        // so they are checked again as the relative paths they were made from
+        let os_filename = self.relative_path(Path::new(os_filename)).as_os_str();
+        let lossy = os_filename.to_string_lossy();
+        let filename = lossy.as_ref();
+
+        // Check for empty filename
+        if filename.trim().is_empty() {
+            return Err(SafeBackupError::InvalidPath("Filename cannot be empty".to_string()));
+        }
+
+        // Check for path traversal sequences
+        if filename.contains("..") {
+            return Err(SafeBackupError::InvalidPath("Path traversal sequences are not allowed".to_string()));
+        }
+
+        // Check for invalid characters: NUL and control characters everywhere,
+        // plus the characters Windows reserves in filenames
+        if filename.chars().any(|c| c.is_control()) {
+            return Err(SafeBackupError::InvalidPath("Filename contains invalid characters".to_string()));
+        }
+        #[cfg(windows)]
+        {
+            let invalid_chars = ['<', '>', ':', '"', '|', '?', '*'];
+            if filename.chars().any(|c| invalid_chars.contains(&c)) {
+                return Err(SafeBackupError::InvalidPath("Filename contains invalid characters".to_string()));
+            }
+
+            // Device names are reserved in every directory, whatever the case and with or without an extension.
+            // Trailing dots and spaces are silently stripped by Windows, so "data.txt " would clobber "data.txt"
+            for component in Path::new(os_filename).components() {
+                let Component::Normal(name) = component else { continue };
+                let name = name.to_string_lossy();
+                if name.ends_with('.') || name.ends_with(' ') {
+                    return Err(SafeBackupError::InvalidPath(format!(
+                        "'{}' ends with a dot or space, which Windows would strip from the name", name
+                    )));
+                }
+                let base = name.split('.').next().unwrap_or_default().trim_end();
+                if WINDOWS_RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(base)) {
+                    return Err(SafeBackupError::InvalidPath(format!("'{}' is a reserved device name on Windows", name)));
+                }
+            }
+        }
+
+        // Prevent absolute paths
+        let path = Path::new(os_filename);
+        if path.is_absolute() {
+            return Err(SafeBackupError::InvalidPath("Absolute paths are not allowed".to_string()));
+        }
+
+        // "~" is only expanded in configured paths; as a filename it would silently name a local "~" directory
+        if path.components().next() == Some(Component::Normal("~".as_ref())) {
+            return Err(SafeBackupError::InvalidPath("'~' is not expanded in filenames; give a path inside the current directory".to_string()));
+        }
+
+        let current_dir = &self.base_dir;
+        let full_path = current_dir.join(path);
+        
+        // Ensure the joined path is still within the current directory
+        if !full_path.starts_with(current_dir) {
+            return Err(SafeBackupError::InvalidPath("Path escapes current directory".to_string()));
+        }
+
+        // Canonicalize the parent directory so a symlinked subdirectory cannot lead outside.
+        // The parent may not exist yet (backup subdirectories are created on demand), so the
+        // nearest existing ancestor is resolved instead; the file itself is left to check_symlink
+        let canonical_dir = without_verbatim_prefix(&fs::canonicalize(current_dir)?);
+        let parent = full_path.parent().unwrap_or(current_dir);
+        if let Some(existing) = parent.ancestors().find(|dir| dir.is_dir()) {
+            let resolved = without_verbatim_prefix(&fs::canonicalize(existing)?);
+            if !resolved.starts_with(&canonical_dir) {
+                return Err(SafeBackupError::InvalidPath(format!(
+                    "'{}' resolves outside the current directory through a symlink", filename
+                )));
+            }
+        }
+
+        Ok(self.root.join(os_filename))
+    }
+
+    // A validated path as it is relative to the base directory, as used for names inside the backup directory
+    fn relative_path<'a>(&self, path: &'a Path) -> &'a Path {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) if !self.root.as_os_str().is_empty() => relative,
+            _ => path,
+        }
+    }
+
+    // The base directory as a path to open: "." unless it differs from the working directory
+    fn root_dir(&self) -> PathBuf {
+        if self.root.as_os_str().is_empty() { PathBuf::from(".") } else { self.root.clone() }
+    }
+
+    // Informational output, silenced by quiet mode; errors are always printed
+    pub fn report(&self, message: &str) {
+        if !self.quiet {
+            println!("{}", message);
+        }
+    }
+
+    fn report_success(&self, message: &str) {
+        if !self.quiet {
+            print_success(message);
+        }
+    }
+
+    fn report_cancelled(&self, message: &str) {
+        if !self.quiet {
+            print_cancelled(message);
+        }
+    }
+
+    // Diagnostic detail shown only in verbose mode, on stderr so it never mixes with command output
+    // Verbose details also go to the log at debug level; a failure to log them is not worth failing over
+    pub fn report_verbose(&self, message: &str) {
+        if self.verbose {
+            eprintln!("[verbose] {}", message);
+        }
+        let _ = self.log_at(LogLevel::Debug, message, LogStatus::Info, None, &LogFields::default());
+    }
+
+    pub fn log_file(&self) -> &Path {
+        &self.log_file
+    }
+
+    // Where backups of a validated file path are stored, mirroring its relative location
+    fn backup_location(&self, file_path: &Path) -> PathBuf {
+        let relative = self.relative_path(file_path);
+        if self.backup_dir == Path::new(".") {
+            self.root.join(relative)
+        } else {
+            self.backup_dir.join(relative)
+        }
+    }
+
+    // The directory walked to find every backup: the backup directory, or the base directory when
+    // backups sit next to their files
+    fn backup_root(&self) -> PathBuf {
+        if self.backup_dir == Path::new(".") { self.root_dir() } else { self.backup_dir.clone() }
+    }
+
+    // Where the backups of a validated file path are named in storage: under the storage's root,
+    // such as an archive, when it has one, and at their backup location otherwise
+    fn storage_location(&self, file_path: &Path) -> PathBuf {
+        match self.storage.root() {
+            Some(root) => root.join(self.relative_path(file_path)),
+            None => self.backup_location(file_path),
+        }
+    }
+
+    // Where every backup in storage is listed from
+    fn storage_root(&self) -> PathBuf {
+        self.storage.root().map_or_else(|| self.backup_root(), Path::to_path_buf)
+    }
+
+    // Path whose lock serializes backups of a file; templated layouts may put each backup in a new
+    // directory, so their locks live at the root of the backup directory instead
+    fn backup_lock_path(&self, file_path: &Path) -> PathBuf {
+        match self.path_template {
+            Some(_) => self.backup_dir.join(archive_entry_name(self.relative_path(file_path)).replace('/', "%")),
+            None => self.backup_location(file_path),
+        }
+    }
+
+    // Called from a Ctrl-C handler: remove any half-written temp files, log the abort and exit with 130
+    // The temp file registry stays locked until exit, so no write can be renamed into place meanwhile
+    pub fn exit_interrupted(&self) -> ! {
+        let registry = in_progress();
+        for temp in registry.iter() {
+            let _ = fs::remove_file(temp);
+            let _ = self.log_event(&format!("Aborted: interrupted while writing, removed '{}'", temp.display()), LogStatus::Cancelled, None);
+        }
+        if registry.is_empty() {
+            let _ = self.log_event("Aborted: interrupted", LogStatus::Cancelled, None);
+        }
+        print_cancelled("Interrupted");
+        std::process::exit(130);
+    }
+
+    // Secure logging with proper error handling
+    pub fn log_action(&self, action: &str) -> Result<()> {
+        self.log_event(action, LogStatus::Info, None)
+    }
+
+    // Log an action with its outcome and the file it concerns, in the configured format
+    pub fn log_event(&self, action: &str, status: LogStatus, filename: Option<&str>) -> Result<()> {
+        self.log_event_with_fields(action, status, filename, &LogFields::default())
+    }
+
+    // Like log_event, with structured details written as extra JSON keys or trailing key=value pairs
+    pub fn log_event_with_fields(&self, action: &str, status: LogStatus, filename: Option<&str>, fields: &LogFields) -> Result<()> {
+        self.log_at(status.level(), action, status, filename, fields)
+    }
+
+    // Log a problem that did not stop the operation
+    fn log_warning(&self, action: &str, status: LogStatus, filename: Option<&str>) -> Result<()> {
+        self.log_at(LogLevel::Warn, action, status, filename, &LogFields::default())
+    }
+
+    // Write a log entry at the given level, skipping it when the level is below the configured minimum
+    pub fn log_at(&self, level: LogLevel, action: &str, status: LogStatus, filename: Option<&str>, fields: &LogFields) -> Result<()> {
+        if level > self.log_level {
+            return Ok(());
+        }
+        let action = if self.dry_run { format!("[DRY-RUN] {}", action) } else { action.to_string() };
+        // A signed log is verified line by line, so an entry must not span several lines
+        let action = if self.log_key.is_some() { action.replace('\n', "\\n") } else { action };
+        let log_entry = match self.log_format {
+            LogFormat::Text => {
+                let timestamp = timestamp::format_utc(&timestamp::now());
+                let details = fields.to_text();
+                if details.is_empty() {
+                    format!("[{}] {} {}\n", timestamp, level.label(), action)
+                } else {
+                    format!("[{}] {} {} ({})\n", timestamp, level.label(), action, details)
+                }
+            }
+            LogFormat::Json => {
+                let record = LogRecord {
+                    #[cfg(feature = "chrono")]
+                    timestamp: Utc::now().to_rfc3339(),
+                    #[cfg(not(feature = "chrono"))]
+                    timestamp: timestamp::format_rfc3339(&timestamp::now(), "+00:00"),
+                    level,
+                    action: &action,
+                    status,
+                    filename,
+                    fields,
+                };
+                let line = serde_json::to_string(&record).map_err(io::Error::other)?;
+                format!("{}\n", line)
+            }
+        };
+
+        let _guard = self.log_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Other runs appending between reading the previous signature and writing would break the chain
+        let _file_lock = match self.log_key {
+            Some(_) => Some(FileLock::acquire(&self.log_file)?),
+            None => None,
+        };
+        if fs::metadata(&self.log_file).is_ok_and(|m| m.len() >= self.max_log_size) {
+            self.rotate_log()?;
+        }
+        // Each log file starts a new chain, so a rotated log still verifies on its own
+        let log_entry = match &self.log_key {
+            Some(key) => {
+                let line = log_entry.trim_end_matches('\n');
+                let previous = last_log_signature(&self.log_file).map_err(|e| io_error_at(&self.log_file, e))?;
+                format!("{}\n", append_signature(line, &sign_log_line(key, &previous, line)))
+            }
+            None => log_entry,
+        };
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_file)?;
+
+        file.write_all(log_entry.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+
+    // Walk the signed log and check every HMAC against the chain, returning how many entries verified
+    // The first line that was edited, inserted, reordered or left unsigned fails with IntegrityError;
+    // unsigned lines are only accepted before the first signed one, from before signing was enabled
+    pub fn verify_log(&self) -> Result<usize> {
+        let Some(key) = &self.log_key else {
+            return Err(SafeBackupError::InvalidPath("Verifying the log needs the key it was signed with".to_string()));
+        };
+        let contents = fs::read_to_string(&self.log_file).map_err(|e| io_error_at(&self.log_file, e))?;
+
+        let mut previous = String::new();
+        let mut signed = 0;
+        for (index, line) in contents.lines().enumerate() {
+            let tampered = |reason: &str| SafeBackupError::IntegrityError(format!(
+                "line {} of '{}' {}", index + 1, self.log_file.display(), reason
+            ));
+            match split_signature(line) {
+                Some((unsigned, hmac)) => {
+                    if sign_log_line(key, &previous, &unsigned) != hmac {
+                        self.log_event(&format!("Log verification failed at line {}", index + 1), LogStatus::Error, None)?;
+                        return Err(tampered("was modified, or lines before it were removed or reordered"));
+                    }
+                    previous = hmac.to_string();
+                    signed += 1;
+                }
+                None if signed > 0 => {
+                    self.log_event(&format!("Log verification failed at line {}", index + 1), LogStatus::Error, None)?;
+                    return Err(tampered("is not signed"));
+                }
+                None => {}
+            }
+        }
+
+        self.report_success(&format!("The log is intact: {} signed entries verified", signed));
+        self.log_action(&format!("Verified {} signed log entries", signed))?;
+        Ok(signed)
+    }
+
+    // Shift logfile.txt.1 .. logfile.txt.{keep-1} up one slot and move the current log to .1
+    fn rotate_log(&self) -> Result<()> {
+        let archive = |index: usize| {
+            let mut name = self.log_file.as_os_str().to_os_string();
+            name.push(format!(".{}", index));
+            PathBuf::from(name)
+        };
+
+        if self.log_keep == 0 {
+            fs::remove_file(&self.log_file).map_err(|e| io_error_at(&self.log_file, e))?;
+            return Ok(());
+        }
+
+        // The oldest archive falls off the end
+        let oldest = archive(self.log_keep);
+        if oldest.exists() {
+            fs::remove_file(&oldest).map_err(|e| io_error_at(&oldest, e))?;
+        }
+
+        for index in (1..self.log_keep).rev() {
+            let from = archive(index);
+            if from.exists() {
+                let to = archive(index + 1);
+                // rename does not replace an existing file on every platform
+                if to.exists() {
+                    fs::remove_file(&to).map_err(|e| io_error_at(&to, e))?;
+                }
+                fs::rename(&from, &to).map_err(|e| io_error_at(&from, e))?;
+            }
+        }
+
+        let first = archive(1);
+        if first.exists() {
+            fs::remove_file(&first).map_err(|e| io_error_at(&first, e))?;
+        }
+        fs::rename(&self.log_file, &first).map_err(|e| io_error_at(&self.log_file, e))?;
+        Ok(())
+    }
+
+    // Secure file backup with comprehensive error handling
+    // Each backup gets its own timestamped name so earlier backups are never overwritten
+    pub fn backup_file(&self, filename: &str) -> Result<ActionOutcome> {
+        self.backup_os_file(OsStr::new(filename))
+    }
+
+    // backup_file for a name that need not be UTF-8; messages and the log show it lossily
+    pub fn backup_os_file(&self, os_filename: &OsStr) -> Result<ActionOutcome> {
+        let file_path = self.validate_os_path(os_filename)?;
+        let lossy = os_filename.to_string_lossy();
+        let filename = lossy.as_ref();
+
+        // Check if source file exists and is readable
+        if !file_path.exists() {
+            return Err(SafeBackupError::FileNotFound(format!("Source file '{}' does not exist", filename)));
+        }
+
+        // is_file() follows symlinks, so check the link itself first
+        self.check_symlink(&file_path, filename)?;
+
+        if !file_path.is_file() {
+            return Err(SafeBackupError::InvalidPath(format!("'{}' is not a regular file", filename)));
+        }
+
+        // Backing up a backup would only produce name.bak.bak files; back up the original instead
+        if is_backup_artifact(&file_path, &self.backup_suffix) {
+            return Err(SafeBackupError::InvalidPath(format!("'{}' is a backup file, back up the original instead", filename)));
+        }
+
+        // Stream the source file contents securely
+        let source_metadata = fs::metadata(&file_path).map_err(|e| io_error_at(&file_path, e))?;
+        if let Some(limit) = self.max_file_size
+            && source_metadata.len() > limit
+        {
+            return Err(SafeBackupError::FileTooLarge { size: source_metadata.len(), limit });
+        }
+        let mut source = self.open_source(filename, &file_path)?;
+
+        self.write_backup(filename, &file_path, &mut source, Some(&source_metadata))
+    }
+
+    // Open the file a backup is read from, reporting a file locked by another process as FileInUse
+    fn open_source(&self, filename: &str, file_path: &Path) -> Result<fs::File> {
+        let source_path = self.snapshot_path(file_path)?;
+        self.with_retry(&format!("opening '{}'", filename), || match fs::File::open(&source_path) {
+            Ok(file) => Ok(file),
+            Err(e) if is_sharing_violation(&e) => Err(SafeBackupError::FileInUse(format!(
+                "'{}' is open in another program that does not allow reading it; close it and try again", filename
+            ))),
+            Err(e) if cfg!(windows) && e.kind() == io::ErrorKind::PermissionDenied => Err(SafeBackupError::PermissionDenied(format!(
+                "'{}': {} (it may be locked by another program)", file_path.display(), e
+            ))),
+            Err(e) => Err(io_error_at(file_path, e)),
+        })
+    }
+
+    // The path to read file_path from: the live file, or its copy in a volume snapshot with --snapshot
+    // This is where shadow copy support would resolve the snapshot; until then snapshots are refused
+    fn snapshot_path(&self, file_path: &Path) -> Result<PathBuf> {
+        if self.snapshot {
+            return Err(SafeBackupError::InvalidPath(
+                "--snapshot is not supported on this platform yet; back up without it, or close programs holding the file".to_string(),
+            ));
+        }
+        Ok(file_path.to_path_buf())
+    }
+
+    // Refuse symlinks unless following them is enabled, and even then only when
+    // the target stays inside the current directory
+    fn check_symlink(&self, file_path: &Path, filename: &str) -> Result<()> {
+        let is_symlink = match fs::symlink_metadata(file_path) {
+            Ok(metadata) => metadata.file_type().is_symlink(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if !is_symlink {
+            return Ok(());
+        }
+
+        if !self.follow_symlinks {
+            return Err(SafeBackupError::InvalidPath(format!(
+                "'{}' is a symlink; use --follow-symlinks to follow it", filename
+            )));
+        }
+
+        let current_dir = fs::canonicalize(&self.base_dir)?;
+        let target = fs::canonicalize(file_path)?;
+        if !target.starts_with(&current_dir) {
+            return Err(SafeBackupError::InvalidPath(format!(
+                "Symlink '{}' points outside the current directory", filename
+            )));
+        }
+        Ok(())
+    }
+
+    // Back up data piped on stdin under a logical filename, which is validated like any other
+    pub fn backup_stdin(&self, logical_name: &str) -> Result<ActionOutcome> {
+        let file_path = self.validate_path(logical_name)?;
+
+        self.write_backup(logical_name, &file_path, &mut io::stdin().lock(), None)
+    }
+
+    // Run an IO operation, retrying transient failures with exponential backoff and logging each retry
+    fn with_retry<T>(&self, operation: &str, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(SafeBackupError::IoError(e) | SafeBackupError::ContextError { source: e, .. })
+                    if is_transient(&e) && attempt < self.retry_attempts =>
+                {
+                    self.log_event(&format!(
+                        "Transient error while {} (attempt {} of {}), retrying in {} ms: {}",
+                        operation, attempt, self.retry_attempts, delay.as_millis(), e
+                    ), LogStatus::Info, None)?;
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Stream source into a new timestamped backup of file_path, with checksum and metadata sidecars
+    // source_metadata is absent for data that does not come from a file, such as stdin
+    fn write_backup(&self, filename: &str, file_path: &Path, source: &mut dyn Read, source_metadata: Option<&fs::Metadata>) -> Result<ActionOutcome> {
+        let now = timestamp::now();
+        // Content-named backups need the digest for their name, so the source is hashed before it is copied
+        let mut buffered;
+        let (source, content_digest): (&mut dyn Read, Option<String>) = match (self.content_names, source_metadata) {
+            (false, _) => (source, None),
+            (true, Some(_)) => (source, Some(hash_reader(BufReader::new(self.open_source(filename, file_path)?), self.hash_algorithm)?)),
+            (true, None) => {
+                let mut contents = Vec::new();
+                source.read_to_end(&mut contents)?;
+                let digest = hash_reader(contents.as_slice(), self.hash_algorithm)?;
+                buffered = io::Cursor::new(contents);
+                (&mut buffered, Some(digest))
+            }
+        };
+        let mut version = match &content_digest {
+            Some(digest) => content_version(digest),
+            None => timestamp::format_version(&now),
+        };
+        let extension = match (self.compress, self.encrypt) {
+            (false, false) => self.backup_suffix.clone(),
+            (true, false) => format!("{}.gz", self.backup_suffix),
+            (false, true) => format!("{}.enc", self.backup_suffix),
+            (true, true) => format!("{}.gz.enc", self.backup_suffix),
+        };
+        let pointer = if self.dedup { ".ptr" } else { "" };
+        // A templated location still has to pass validate_path, so it cannot leave the working directory
+        let location = match &self.path_template {
+            Some(template) => self.backup_dir.join(expand_path_template(template, file_path, now)?),
+            None => self.backup_location(file_path),
+        };
+        // Archived backups are staged where a .bak file would go, then appended to the zip once verified
+        let paths_for = |version: &str| -> Result<(PathBuf, PathBuf)> {
+            let mut backup_name = location.clone().into_os_string();
+            backup_name.push(format!(".{}{}{}", version, extension, pointer));
+            let local_path = self.validate_os_path(&backup_name)?;
+            let backup_path = match self.storage.root() {
+                Some(root) => root.join(format!("{}.{}{}", archive_entry_name(self.relative_path(file_path)), version, extension)),
+                None => local_path.clone(),
+            };
+            Ok((local_path, backup_path))
+        };
+        let (mut local_path, mut backup_path) = paths_for(&version)?;
+        ensure_distinct(file_path, &backup_path)?;
+
+        // These contents were backed up before, so that backup only becomes the newest one again
+        if content_digest.is_some() && backup_path.is_file() {
+            if self.dry_run {
+                self.report(&format!("[DRY-RUN] Would mark '{}' as the latest backup of '{}'", backup_path.display(), filename));
+            } else {
+                filetime::set_file_mtime(&backup_path, FileTime::from_system_time(timestamp::to_system_time(now))).map_err(|e| io_error_at(&backup_path, e))?;
+                self.touch_in_manifest(&backup_path, now)?;
+                self.report(&format!("File unchanged, already backed up as {}", backup_path.display()));
+            }
+            self.log_event(&format!("Skipped backup of '{}': contents already backed up as '{}'", filename, backup_path.display()), LogStatus::Info, Some(filename))?;
+            return Ok(ActionOutcome::Unchanged { latest: backup_path });
+        }
+
+        let progress = self.progress_bar(source_metadata.map(|m| m.len()));
+        progress.set_message(filename.to_string());
+        let mut reader = HashingReader::new(BufReader::new(source), progress, self.hash_algorithm);
+
+        if self.dry_run {
+            io::copy(&mut reader, &mut io::sink())?;
+            let (expected_hash, bytes) = reader.finish();
+            if let Some(outcome) = self.unchanged_since_latest(filename, file_path, &expected_hash)? {
+                return Ok(outcome);
+            }
+            self.report(&format!("[DRY-RUN] Would back up '{}' ({}) to '{}'", filename, format_size(bytes), self.storage.describe(&backup_path)));
+            self.log_backup(filename, &self.storage.describe(&backup_path), &LogFields::source(bytes, &expected_hash))?;
+            return Ok(ActionOutcome::Backed { path: backup_path, bytes });
+        }
+
+        // Create the backup directory (and any subdirectories) on first use
+        if let Some(parent) = local_path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent).map_err(|e| io_error_at(parent, e))?;
+        }
+
+        // Held until the backup and its sidecars are written, so a concurrent run cannot interleave with this one
+        let _lock = FileLock::try_acquire(&self.backup_lock_path(file_path))?;
+
+        // A backup made earlier in the same second already has this version, and one with the same
+        // options also the name, so storing over it would lose it
+        if content_digest.is_none() {
+            let taken: BTreeSet<String> = self.list_os_backups(file_path.as_os_str())?.into_iter().map(|backup| backup.version).collect();
+            let timestamp = version.clone();
+            let mut counter = 0;
+            while taken.contains(&version) || self.storage.exists(&backup_path) {
+                counter += 1;
+                version = format!("{}-{}", timestamp, counter);
+                (local_path, backup_path) = paths_for(&version)?;
+            }
+        }
+
+        // Copy in chunks into a temp file, gzipping and then encrypting on the way when enabled
+        // Plain copies of files are cloned instead where the filesystem supports reflinks
+        let (mut temp, file) = self.with_retry(&format!("creating '{}'", local_path.display()), || AtomicFile::create(&local_path))?;
+        let file = if source_metadata.is_some() && !self.compress && !self.encrypt {
+            self.reflink_into(file_path, &temp, file)?
+        } else {
+            Some(file)
+        };
+        match file {
+            // The source is still read once to hash it for the unchanged and integrity checks
+            None => {
+                io::copy(&mut reader, &mut io::sink())?;
+                fs::OpenOptions::new().write(true).open(temp.path())?.sync_all()?;
+            }
+            Some(file) => {
+                let mut writer = BufWriter::new(file);
+                if self.encrypt {
+                    let mut encryptor = EncryptWriter::new(&mut writer, self.passphrase()?)?;
+                    copy_compressed(&mut reader, &mut encryptor, self.compression())?;
+                    encryptor.finish()?;
+                } else {
+                    copy_compressed(&mut reader, &mut writer, self.compression())?;
+                }
+                let file = writer.into_inner().map_err(|e| e.into_error())?;
+                file.sync_all()?;
+            }
+        }
+        let (expected_hash, bytes) = reader.finish();
+        if content_digest.as_ref().is_some_and(|digest| *digest != expected_hash) {
+            return Err(SafeBackupError::IntegrityError(format!("'{}' changed while it was being backed up", filename)));
+        }
+
+        // Dropping the temp file discards it when nothing changed
+        if let Some(outcome) = self.unchanged_since_latest(filename, file_path, &expected_hash)? {
+            return Ok(outcome);
+        }
+
+        // Re-read the backup before it is moved into place to confirm the bytes landed correctly
+        // The staged file is always local, whatever storage the backup goes to
+        let staged = BufReader::new(fs::File::open(temp.path()).map_err(|e| io_error_at(temp.path(), e))?);
+        let written_hash = hash_reader(self.decode_backup(Box::new(staged), self.compress, self.encrypt)?, self.hash_algorithm)?;
+        if written_hash != expected_hash {
+            return Err(SafeBackupError::IntegrityError(format!(
+                "Backup '{}' does not match the source (expected {}, got {})",
+                backup_path.display(), expected_hash, written_hash
+            )));
+        }
+
+        // Store the expected hash of the original contents next to the backup,
+        // and permissions and mtime so restore can reapply them
+        let checksum = format!("{}\n", expected_hash);
+        let metadata = source_metadata.map(format_file_metadata);
+        let size = fs::metadata(temp.path()).map_err(|e| io_error_at(temp.path(), e))?.len();
+        if self.dedup {
+            let blob_path = self.store_blob(&mut temp, &expected_hash)?;
+            let pointer = format!("{}\n", normalized_path(self.relative_path(&blob_path)).display());
+            self.with_retry("writing the pointer", || self.store_contents(&backup_path, pointer.as_bytes()))?;
+        } else {
+            // The staged temp file is gone once a local backup is renamed into place, and removed when dropped otherwise
+            self.with_retry(&format!("storing '{}'", backup_path.display()), || {
+                self.storage.store_file(&backup_path, temp.path()).map_err(|e| io_error_at(&backup_path, e))
+            })?;
+        }
+        if self.read_only_backups {
+            make_read_only(&backup_path)?;
+        }
+        self.with_retry("writing the checksum", || self.store_contents(&checksum_path(&backup_path), checksum.as_bytes()))?;
+        if let Some(metadata) = &metadata {
+            self.with_retry("writing the file metadata", || self.store_contents(&metadata_path(&backup_path), metadata.as_bytes()))?;
+        }
+
+        let fields = LogFields::source(bytes, &expected_hash);
+        self.record_backup(ManifestEntry {
+            original: normalized_path(file_path),
+            path: normalized_path(&backup_path),
+            version,
+            created: now,
+            size: if self.dedup { fs::metadata(&backup_path).map_err(|e| io_error_at(&backup_path, e))?.len() } else { size },
+            digest: expected_hash,
+            compressed: self.compress,
+            encrypted: self.encrypt,
+        })?;
+        if self.delta {
+            self.delta_encode_previous(filename, &backup_path)?;
+        }
+
+        self.report_verbose(&format!(
+            "Backed up {} ({} bytes) to {}",
+            resolved_path(file_path).display(), bytes, resolved_path(&backup_path).display()
+        ));
+        self.report_success(&format!("Backup created: {} ({})", self.storage.describe(&backup_path), format_size(bytes)));
+        self.log_backup(filename, &self.storage.describe(&backup_path), &fields)?;
+        Ok(ActionOutcome::Backed { path: backup_path, bytes })
+    }
+
+    // Log a completed backup with the size and hash of the source contents
+    fn log_backup(&self, filename: &str, location: &str, fields: &LogFields) -> Result<()> {
+        self.log_event_with_fields(&format!("Performed backup of '{}' to '{}'", filename, location), LogStatus::Success, Some(filename), fields)
+    }
+
+    // Replace the empty temp file with a copy-on-write clone of source (APFS, Btrfs, XFS, ReFS)
+    // Returns None once cloned, or a fresh temp file to stream into when the filesystem cannot clone
+    fn reflink_into(&self, source: &Path, temp: &AtomicFile, file: fs::File) -> Result<Option<fs::File>> {
+        drop(file);
+        fs::remove_file(temp.path()).map_err(|e| io_error_at(temp.path(), e))?;
+        match reflink_copy::reflink(source, temp.path()) {
+            Ok(()) => {
+                self.report_verbose(&format!("Cloned {} with a copy-on-write reflink", resolved_path(source).display()));
+                Ok(None)
+            }
+            Err(e) => {
+                self.report_verbose(&format!("Reflink of {} not possible ({}), falling back to a streaming copy", resolved_path(source).display(), e));
+                Ok(Some(fs::File::create(temp.path()).map_err(|e| io_error_at(temp.path(), e))?))
+            }
+        }
+    }
+
+    // Write a small file such as a sidecar or pointer to the storage that holds the backups
+    fn store_contents(&self, name: &Path, contents: &[u8]) -> Result<()> {
+        self.storage.store(name, &mut &contents[..]).map_err(|e| io_error_at(name, e))?;
+        Ok(())
+    }
+
+    // Contents of a checksum or metadata sidecar, from the storage that holds its backup
+    fn read_sidecar(&self, path: &Path) -> io::Result<String> {
+        let mut contents = String::new();
+        self.storage.load(path)?.read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    // Content-addressed blobs shared by dedup backups, at the root of the backup directory
+    fn store_dir(&self) -> PathBuf {
+        self.backup_location(Path::new("store"))
+    }
+
+    // Move a verified backup into the store as store/{digest}[.gz][.enc] and count one more reference to it
+    // When identical contents are already stored, the new copy is discarded
+    fn store_blob(&self, temp: &mut AtomicFile, digest: &str) -> Result<PathBuf> {
+        let suffix = match (self.compress, self.encrypt) {
+            (false, false) => "",
+            (true, false) => ".gz",
+            (false, true) => ".enc",
+            (true, true) => ".gz.enc",
+        };
+        let store_dir = self.store_dir();
+        fs::create_dir_all(&store_dir).map_err(|e| io_error_at(&store_dir, e))?;
+        // BLAKE3 blobs are named blake3-{hex}, since ':' is not allowed in Windows file names
+        let blob_path = store_dir.join(format!("{}{}", digest.replace(':', "-"), suffix));
+
+        let _lock = FileLock::acquire(&blob_path)?;
+        let refs = if blob_path.is_file() {
+            self.report_verbose(&format!("Contents already stored in {}", resolved_path(&blob_path).display()));
+            self.blob_references(&blob_path)?
+        } else {
+            self.with_retry(&format!("renaming into '{}'", blob_path.display()), || temp.persist_to(&blob_path))?;
+            0
+        };
+        write_atomic(&refs_path(&blob_path), format!("{}\n", refs + 1).as_bytes())?;
+        Ok(blob_path)
+    }
+
+    // Drop one reference to a blob, deleting it once no pointer refers to it any more
+    fn release_blob(&self, blob_path: &Path) -> Result<()> {
+        let _lock = FileLock::acquire(blob_path)?;
+        let refs = self.blob_references(blob_path)?.saturating_sub(1);
+        if refs > 0 {
+            return write_atomic(&refs_path(blob_path), format!("{}\n", refs).as_bytes());
+        }
+        for path in [blob_path.to_path_buf(), refs_path(blob_path)] {
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(io_error_at(&path, e)),
+            }
+        }
+        self.log_action(&format!("Removed unreferenced blob '{}'", blob_path.display()))
+    }
+
+    // Number of pointers referring to a blob; a stored blob without a count has one
+    fn blob_references(&self, blob_path: &Path) -> Result<u64> {
+        match fs::read_to_string(refs_path(blob_path)) {
+            Ok(refs) => refs.trim().parse().map_err(|_| SafeBackupError::IntegrityError(format!(
+                "Reference count of '{}' is corrupt", blob_path.display()
+            ))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(u64::from(blob_path.is_file())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // The store blob a pointer backup refers to
+    fn resolve_pointer(&self, pointer_path: &Path) -> Result<PathBuf> {
+        let target = fs::read_to_string(pointer_path).map_err(|e| io_error_at(pointer_path, e))?;
+        let blob_path = self.validate_path(target.trim())?;
+        if normalized_path(&blob_path).parent() != Some(normalized_path(&self.store_dir()).as_path()) {
+            return Err(SafeBackupError::IntegrityError(format!(
+                "Pointer '{}' does not refer to the backup store", pointer_path.display()
+            )));
+        }
+        Ok(blob_path)
+    }
+
+    // Gzip settings for new backups, or None when they are stored uncompressed
+    fn compression(&self) -> Option<Compression> {
+        self.compress.then(|| Compression::new(self.compression_level))
+    }
+
+    // Buffered reader over a backup's original contents, decrypting and inflating it as needed
+    fn open_backup(&self, backup_path: &Path, compressed: bool, encrypted: bool) -> Result<Box<dyn Read>> {
+        if is_pointer(backup_path) {
+            return self.open_backup(&self.resolve_pointer(backup_path)?, compressed, encrypted);
+        }
+        if is_delta(backup_path) {
+            return Ok(Box::new(io::Cursor::new(self.reconstruct_delta(backup_path)?)));
+        }
+        let file = self.with_retry(&format!("opening '{}'", backup_path.display()), || self.storage.load(backup_path).map_err(|e| io_error_at(backup_path, e)))?;
+        self.decode_backup(file, compressed, encrypted)
+    }
+
+    // Original contents of stored backup bytes, decrypting and inflating them as needed
+    fn decode_backup(&self, file: Box<dyn Read>, compressed: bool, encrypted: bool) -> Result<Box<dyn Read>> {
+        let data: Box<dyn Read> = if encrypted {
+            Box::new(DecryptReader::new(file, self.passphrase()?)?)
+        } else {
+            Box::new(file)
+        };
+        if compressed {
+            Ok(Box::new(GzDecoder::new(data)))
+        } else {
+            Ok(data)
+        }
+    }
+
+    // Replace the previous backup of filename with a reverse delta against the new latest backup
+    // Binary contents, and deltas that would not be smaller than the full copy, are left as they are
+    fn delta_encode_previous(&self, filename: &str, latest: &Path) -> Result<()> {
+        let backups = self.list_backups(filename)?;
+        let Some(previous) = backups.iter().find(|b| b.path != latest) else {
+            return Ok(());
+        };
+        if is_delta(&previous.path) || is_pointer(&previous.path) || previous.compressed || previous.encrypted {
+            return Ok(());
+        }
+
+        let (old, new) = (fs::read(&previous.path).map_err(|e| io_error_at(&previous.path, e))?, fs::read(latest).map_err(|e| io_error_at(latest, e))?);
+        let (Some(old_text), Some(new_text)) = (as_text(&old), as_text(&new)) else {
+            self.report_verbose(&format!("Kept '{}' as a full copy: binary contents", previous.path.display()));
+            return Ok(());
+        };
+        let delta = Delta {
+            base: latest.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            ops: text_delta(new_text, old_text),
+        };
+        let encoded = serde_json::to_vec(&delta).map_err(io::Error::other)?;
+        if encoded.len() >= old.len() {
+            self.report_verbose(&format!("Kept '{}' as a full copy: a delta would not be smaller", previous.path.display()));
+            return Ok(());
+        }
+
+        // The delta and its sidecars are in place before the full copy is removed
+        let mut delta_name = previous.path.as_os_str().to_os_string();
+        delta_name.push(".delta");
+        let delta_path = PathBuf::from(delta_name);
+        write_atomic(&delta_path, &encoded)?;
+        for (from, to) in [
+            (checksum_path(&previous.path), checksum_path(&delta_path)),
+            (metadata_path(&previous.path), metadata_path(&delta_path)),
+        ] {
+            match fs::rename(&from, &to) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if self.read_only_backups {
+            make_read_only(&delta_path)?;
+        }
+        remove_backup_file(&previous.path).map_err(|e| io_error_at(&previous.path, e))?;
+        self.move_in_manifest(&previous.path, &delta_path, encoded.len() as u64)?;
+
+        self.report_verbose(&format!("Stored '{}' as a {} byte delta against '{}'", previous.path.display(), encoded.len(), latest.display()));
+        self.log_action(&format!("Replaced backup '{}' with delta '{}'", previous.path.display(), delta_path.display()))
+    }
+
+    // Contents of a delta backup, rebuilt by applying it to its (possibly also reconstructed) base
+    fn reconstruct_delta(&self, delta_path: &Path) -> Result<Vec<u8>> {
+        let corrupt = |reason: &str| SafeBackupError::IntegrityError(format!("Delta backup '{}' {}", delta_path.display(), reason));
+        let delta: Delta = serde_json::from_slice(&fs::read(delta_path).map_err(|e| io_error_at(delta_path, e))?).map_err(|_| corrupt("is not a valid delta"))?;
+        if !matches!(Path::new(&delta.base).components().collect::<Vec<_>>()[..], [Component::Normal(_)]) {
+            return Err(corrupt("names an invalid base"));
+        }
+
+        // The base has usually been turned into a delta itself by a later backup
+        let mut base_path = delta_path.with_file_name(&delta.base);
+        if !base_path.exists() {
+            base_path = delta_path.with_file_name(format!("{}.delta", delta.base));
+        }
+        let mut base = Vec::new();
+        self.open_backup(&base_path, false, false)
+            .map_err(|e| corrupt(&format!("cannot be rebuilt from '{}': {}", base_path.display(), e)))?
+            .read_to_end(&mut base)?;
+        apply_delta(&base, &delta.ops).ok_or_else(|| corrupt("does not fit its base"))
+    }
+
+    // Passphrase for encrypted backups, from SAFE_BACKUP_PASSPHRASE or an unechoed prompt
+    // It is never logged or printed
+    fn passphrase(&self) -> Result<&str> {
+        if let Some(passphrase) = self.passphrase.get() {
+            return Ok(passphrase);
+        }
+        let passphrase = match std::env::var(PASSPHRASE_ENV) {
+            Ok(passphrase) => passphrase,
+            Err(_) => rpassword::prompt_password("Backup passphrase: ")?,
+        };
+        Ok(self.passphrase.get_or_init(|| passphrase))
+    }
+
+    // Outcome for contents whose hash matches the newest existing backup, if it does
+    fn unchanged_since_latest(&self, filename: &str, file_path: &Path, hash: &str) -> Result<Option<ActionOutcome>> {
+        let Some(latest) = self.list_os_backups(file_path.as_os_str())?.into_iter().next() else {
+            return Ok(None);
+        };
+        // After a switch of algorithm the latest backup is rehashed, so unchanged files are still skipped
+        let mut latest_digest = self.backup_digest(&latest)?;
+        if HashAlgorithm::of(&latest_digest) != HashAlgorithm::of(hash) {
+            latest_digest = hash_reader(self.open_backup(&latest.path, latest.compressed, latest.encrypted)?, HashAlgorithm::of(hash))?;
+        }
+        if latest_digest != hash {
+            return Ok(None);
+        }
+        self.report(&format!("File unchanged since last backup: {}", latest.path.display()));
+        self.log_event(&format!("Skipped backup of '{}': unchanged since '{}'", filename, latest.path.display()), LogStatus::Info, Some(filename))?;
+        Ok(Some(ActionOutcome::Unchanged { latest: latest.path }))
+    }
+
+    // Progress bar for a copy of `size` bytes (a spinner when unknown),
+    // hidden in quiet mode or when stderr is not a terminal
+    fn progress_bar(&self, size: Option<u64>) -> ProgressBar {
+        if self.quiet || self.jobs > 1 || !io::stderr().is_terminal() {
+            return ProgressBar::hidden();
+        }
+        match size {
+            Some(size) => {
+                let bar = ProgressBar::new(size);
+                if let Ok(style) = ProgressStyle::with_template("{msg} [{bar:40}] {bytes}/{total_bytes} ({eta})") {
+                    bar.set_style(style.progress_chars("=> "));
+                }
+                bar
+            }
+            None => ProgressBar::new_spinner(),
+        }
+    }
+
+    // Whether an entry found while walking root matches one of the exclude patterns
+    fn is_excluded(&self, root: &Path, path: &Path) -> bool {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let name = Path::new(path.file_name().unwrap_or_default());
+        self.exclude.iter().any(|pattern| pattern.matches_path(relative) || pattern.matches_path(name))
+    }
+
+    // Whether a file's size is outside the range set by exclude_larger_than and exclude_smaller_than
+    fn is_outside_size_limits(&self, size: u64) -> bool {
+        self.exclude_larger_than.is_some_and(|limit| size > limit) || self.exclude_smaller_than.is_some_and(|limit| size < limit)
+    }
+
+    // What a symlink found in a directory walk points to, when follow_symlinks allows walking through it:
+    // None for broken links and links leading outside the base directory
+    fn followed_symlink_type(&self, path: &Path, canonical_base: &Path) -> Result<Option<fs::FileType>> {
+        let target = match fs::canonicalize(path) {
+            Ok(target) => without_verbatim_prefix(&target),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(io_error_at(path, e)),
+        };
+        if !target.starts_with(canonical_base) {
+            return Ok(None);
+        }
+        Ok(Some(fs::metadata(&target).map_err(|e| io_error_at(&target, e))?.file_type()))
+    }
+
+    // Back up every regular file under a directory, mirroring its structure in the backup directory
+    // Symlinks are skipped unless follow_symlinks is set, and so are excluded paths and files outside the size limits and per-file failures are logged without stopping the walk
+    pub fn backup_directory(&self, dirname: &str) -> Result<DirectoryBackupSummary> {
+        let root = self.validate_path(dirname)?;
+        if !root.is_dir() {
+            return Err(SafeBackupError::InvalidPath(format!("'{}' is not a directory", dirname)));
+        }
+
+        // A dedicated backup directory or the dedup store inside the tree must not be backed up itself
+        let backup_root = if self.backup_dir == Path::new(".") {
+            None
+        } else {
+            fs::canonicalize(&self.backup_dir).ok()
+        };
+        let store_root = fs::canonicalize(self.store_dir()).ok();
+        let canonical_base = without_verbatim_prefix(&fs::canonicalize(&self.base_dir)?);
+
+        let mut summary = DirectoryBackupSummary::default();
+        let mut pending = vec![root.clone()];
+        // Each directory walked so far, by its canonical path, with the path it was first walked as
+        let mut visited: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+        while let Some(dir) = pending.pop() {
+            let canonical = fs::canonicalize(&dir).ok();
+            if canonical.is_some() && (canonical == backup_root || canonical == store_root) {
+                continue;
+            }
+            // A followed symlink may lead back to a directory already walked, and walking it again would never end
+            if let Some(canonical) = canonical {
+                if let Some(first) = visited.get(&canonical) {
+                    summary.skipped_cycles += 1;
+                    self.report_verbose(&format!("Skipped {}, already walked as {}", dir.display(), first.display()));
+                    self.log_action(&format!("Skipped '{}': symlink cycle, already walked as '{}'", dir.display(), first.display()))?;
+                    continue;
+                }
+                visited.insert(canonical, dir.clone());
+            }
+
+            for entry in fs::read_dir(&dir).map_err(|e| io_error_at(&dir, e))? {
+                let entry = entry?;
+                // Keep paths relative and free of a leading "./" so backups mirror them cleanly
+                let path = if dir == Path::new(".") { PathBuf::from(entry.file_name()) } else { entry.path() };
+                let file_type = entry.file_type()?;
+                // A followed symlink is walked as what it points to
+                let target_type = if file_type.is_symlink() && self.follow_symlinks {
+                    self.followed_symlink_type(&path, &canonical_base)?
+                } else {
+                    Some(file_type)
+                };
+
+                if self.is_excluded(&root, &path) {
+                    summary.excluded += 1;
+                    self.report_verbose(&format!("Excluded {}", path.display()));
+                    self.log_action(&format!("Excluded '{}'", path.display()))?;
+                } else if !self.include_hidden && entry.file_name().to_string_lossy().starts_with('.') && !is_internal_file(&path) {
+                    summary.skipped_hidden += 1;
+                    self.report_verbose(&format!("Skipped hidden {}", path.display()));
+                    self.log_action(&format!("Skipped hidden '{}'", path.display()))?;
+                } else if target_type.is_none_or(|target_type| target_type.is_symlink()) {
+                    summary.skipped_symlinks += 1;
+                    self.log_action(&format!("Skipped symlink '{}'", path.display()))?;
+                } else if target_type.is_some_and(|target_type| target_type.is_dir()) {
+                    pending.push(path);
+                } else if target_type.is_some_and(|target_type| target_type.is_file()) && !is_backup_artifact(&path, &self.backup_suffix) && path != self.manifest_path() {
+                    let size = fs::metadata(&path).map_err(|e| io_error_at(&path, e))?.len();
+                    if self.is_outside_size_limits(size) {
+                        summary.skipped_size += 1;
+                        self.report_verbose(&format!("Skipped {} ({}), outside the size limits", path.display(), format_size(size)));
+                        self.log_action(&format!("Skipped '{}' ({} bytes), outside the size limits", path.display(), size))?;
+                        continue;
+                    }
+                    let filename = path.to_string_lossy().into_owned();
+                    match self.backup_os_file(path.as_os_str()) {
+                        Ok(ActionOutcome::Backed { path: backup_path, bytes }) => {
+                            summary.bytes += bytes;
+                            summary.backed_up.push(backup_path);
+                            summary.backed_up_files.push(path);
+                        }
+                        Ok(_) => summary.unchanged += 1,
+                        Err(e) => {
+                            print_error(&format!("Backup of '{}' failed: {}", filename, e));
+                            self.log_event(&format!("Backup of '{}' failed: {}", filename, e), LogStatus::Error, Some(&filename))?;
+                            summary.failed += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.report(&format!(
+            "Backed up {} file(s) ({}) from '{}': {} unchanged, {} failed, {} symlink(s) skipped, {} excluded, {} hidden skipped, {} outside the size limits, {} symlink cycle(s) skipped",
+            summary.backed_up.len(), format_size(summary.bytes), dirname, summary.unchanged, summary.failed, summary.skipped_symlinks, summary.excluded,
+            summary.skipped_hidden, summary.skipped_size, summary.skipped_cycles
+        ));
+        self.log_action(&format!(
+            "Directory backup of '{}' finished: {} backed up ({} bytes), {} unchanged, {} failed, {} symlink(s) skipped, {} excluded, {} hidden skipped, {} outside the size limits, {} symlink cycle(s) skipped",
+            dirname, summary.backed_up.len(), summary.bytes, summary.unchanged, summary.failed, summary.skipped_symlinks, summary.excluded,
+            summary.skipped_hidden, summary.skipped_size, summary.skipped_cycles
+        ))?;
+        Ok(summary)
+    }
+
+    // Digest of a backup's original contents, from its checksum sidecar when present
+    fn backup_digest(&self, backup: &BackupEntry) -> Result<String> {
+        match self.read_sidecar(&checksum_path(&backup.path)) {
+            Ok(sidecar) => sidecar_digest(&sidecar).map(str::to_string)
+                .ok_or_else(|| SafeBackupError::IntegrityError(format!("Checksum file of '{}' is empty", backup.path.display()))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                hash_reader(self.open_backup(&backup.path, backup.compressed, backup.encrypted)?, self.hash_algorithm)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Back up several files, continuing past individual failures; up to jobs files are backed up at once
+    // Returns BackedMany with the new backups and the total bytes read from their sources
+    // With rollback, any failure removes the new backups of the other files again
+    pub fn backup_files(&self, filenames: &[String]) -> Result<ActionOutcome> {
+        let mut transaction = Transaction::default();
+        let mut succeeded = Vec::new();
+        let mut failures = Vec::new();
+        let mut total = 0;
+
+        // Workers take the next unclaimed file until none are left; results keep the order of filenames
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<(usize, Result<ActionOutcome>)>> = Mutex::new(Vec::with_capacity(filenames.len()));
+        std::thread::scope(|scope| {
+            for _ in 0..self.jobs.min(filenames.len()) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(filename) = filenames.get(index) else { break };
+                    let result = self.backup_file(filename);
+                    results.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push((index, result));
+                });
+            }
+        });
+        let mut results = results.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+        results.sort_by_key(|(index, _)| *index);
+
+        for (index, result) in results {
+            let filename = &filenames[index];
+            match result {
+                Ok(ActionOutcome::Backed { path, bytes }) => {
+                    transaction.created(path.clone());
+                    succeeded.push(path);
+                    total += bytes;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.log_event(&format!("Backup of '{}' failed: {}", filename, e), LogStatus::Error, Some(filename))?;
+                    failures.push((filename.clone(), e));
+                }
+            }
+        }
+
+        if failures.is_empty() {
+            self.report(&format!("Backed up {} file(s), {}", succeeded.len(), format_size(total)));
+            return Ok(ActionOutcome::BackedMany { paths: succeeded, bytes: total });
+        }
+        if self.rollback && !self.dry_run {
+            let undone = transaction.rollback(self)?;
+            self.report_cancelled(&format!("Rolled back {} backup(s) after {} failure(s)", undone, failures.len()));
+        }
+        Err(SafeBackupError::BatchFailed { succeeded, failures })
+    }
+
+    // Expand a glob pattern relative to the current directory into validated file paths
+    pub fn expand_pattern(&self, pattern: &str) -> Result<Vec<String>> {
+        self.expand_glob(pattern, false)
+    }
+
+    // expand_pattern, optionally keeping backups and their sidecars among the matches
+    fn expand_glob(&self, pattern: &str, include_backups: bool) -> Result<Vec<String>> {
+        // The pattern itself cannot go through validate_path since '*' and '?' are rejected there on Windows
+        if pattern.trim().is_empty() {
+            return Err(SafeBackupError::InvalidPath("Pattern cannot be empty".to_string()));
+        }
+        if pattern.contains("..") {
+            return Err(SafeBackupError::InvalidPath("Path traversal sequences are not allowed".to_string()));
+        }
+        if Path::new(pattern).is_absolute() {
+            return Err(SafeBackupError::InvalidPath("Absolute paths are not allowed".to_string()));
+        }
+
+        // The pattern is matched under the base directory, whose own name may hold glob characters
+        let rooted = match self.root.to_str() {
+            Some("") => pattern.to_string(),
+            Some(root) => format!("{}/{}", glob::Pattern::escape(root), pattern),
+            None => return Err(SafeBackupError::InvalidPath(format!("Cannot match '{}' under a base directory that is not valid UTF-8", pattern))),
+        };
+        let entries = glob::glob(&rooted)
+            .map_err(|e| SafeBackupError::InvalidPath(format!("Invalid pattern '{}': {}", pattern, e)))?;
+        let current_dir = fs::canonicalize(&self.base_dir)?;
+
+        let mut filenames = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| SafeBackupError::IoError(e.into()))?;
+
+            // Only regular files, and existing backups or their checksums only when asked for
+            if !path.is_file() || is_internal_file(&path) || (!include_backups && is_backup_artifact(&path, &self.backup_suffix)) {
+                continue;
+            }
+
+            // Matches reached through symlinked directories must still resolve inside the current directory
+            if !fs::canonicalize(&path)?.starts_with(&current_dir) {
+                return Err(SafeBackupError::InvalidPath(format!("'{}' resolves outside the current directory", path.display())));
+            }
+
+            let filename = self.relative_path(&path).to_string_lossy().into_owned();
+            self.validate_path(&filename)?;
+            filenames.push(filename);
+        }
+
+        Ok(filenames)
+    }
+
+    // Back up every file matching a glob pattern
+    pub fn backup_pattern(&self, pattern: &str) -> Result<ActionOutcome> {
+        let filenames = self.expand_pattern(pattern)?;
+        if filenames.is_empty() {
+            return Err(SafeBackupError::FileNotFound(format!("No files match the pattern '{}'", pattern)));
+        }
+        self.log_action(&format!("Pattern '{}' matched {} file(s)", pattern, filenames.len()))?;
+        self.backup_files(&filenames)
+    }
+
+    // Enumerate existing timestamped backups of a file, newest first
+    // Uses the manifest when it knows the file, and scans the backup directory otherwise
+    pub fn list_backups(&self, filename: &str) -> Result<Vec<BackupEntry>> {
+        self.list_os_backups(OsStr::new(filename))
+    }
+
+    // list_backups for a name that need not be UTF-8
+    pub fn list_os_backups(&self, filename: &OsStr) -> Result<Vec<BackupEntry>> {
+        let original = normalized_path(&self.validate_os_path(filename)?);
+        if let Some(manifest) = self.load_manifest()? {
+            // Entries whose backup was removed by hand are ignored until the next prune drops them,
+            // and backups made with another suffix are left to the configuration that made them
+            let mut backups: Vec<BackupEntry> = manifest.backups.iter()
+                .filter(|entry| entry.original == original && self.storage.exists(&entry.path))
+                .filter(|entry| has_backup_extension(&entry.path.file_name().unwrap_or_default().to_string_lossy(), &self.backup_suffix))
+                .map(ManifestEntry::to_backup_entry)
+                .collect();
+            if !backups.is_empty() {
+                backups.sort_by_key(|b| std::cmp::Reverse(b.created));
+                return Ok(backups);
+            }
+        }
+        Ok(self.scan_backups(filename)?.0)
+    }
+
+    // Backups of filename created at or after since, newest first
+    // Backups whose timestamp cannot be parsed are left out and noted in the output and the log
+    pub fn list_backups_since(&self, filename: &str, since: Timestamp) -> Result<Vec<BackupEntry>> {
+        let mut backups = self.list_backups(filename)?;
+        backups.retain(|backup| backup.created >= since);
+
+        let unparsed = self.scan_backups(OsStr::new(filename))?.1;
+        if !unparsed.is_empty() {
+            self.report_cancelled(&format!("Left out {} backup(s) of '{}' whose timestamp could not be parsed", unparsed.len(), filename));
+            for path in &unparsed {
+                self.log_warning(&format!("Warning: left '{}' out of the --since listing, its timestamp could not be parsed", path.display()), LogStatus::Info, None)?;
+            }
+        }
+        Ok(backups)
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.backup_location(Path::new(MANIFEST_NAME))
+    }
+
+    // The manifest, or None when it does not exist yet
+    // An unreadable manifest is logged and treated as missing, since directory scans can rebuild it
+    // Its timestamps are chrono's, so builds without the chrono feature leave it alone and scan instead
+    fn load_manifest(&self) -> Result<Option<Manifest>> {
+        if !cfg!(feature = "chrono") {
+            return Ok(None);
+        }
+        let contents = match fs::read_to_string(self.manifest_path()) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        // Paths are stored relative to the base directory, so the manifest still applies when it is reached another way
+        match serde_json::from_str::<Manifest>(&contents) {
+            Ok(mut manifest) => {
+                for entry in &mut manifest.backups {
+                    entry.original = self.root.join(&entry.original);
+                    entry.path = self.root.join(&entry.path);
+                }
+                Ok(Some(manifest))
+            }
+            Err(e) => {
+                self.log_warning(&format!("Warning: ignoring unreadable manifest '{}': {}", self.manifest_path().display(), e), LogStatus::Error, None)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn save_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let mut manifest = manifest.clone();
+        for entry in &mut manifest.backups {
+            entry.original = self.relative_path(&entry.original).to_path_buf();
+            entry.path = self.relative_path(&entry.path).to_path_buf();
+        }
+        let contents = serde_json::to_vec_pretty(&manifest).map_err(io::Error::other)?;
+        write_atomic(&self.manifest_path(), &contents)
+    }
+
+    // Add a new backup to the manifest
+    // The first time a file is recorded, its existing backups on disk are added as well
+    // JSON holds only UTF-8, so backups of other names are left out and found by scanning instead
+    fn record_backup(&self, entry: ManifestEntry) -> Result<()> {
+        if !cfg!(feature = "chrono") {
+            return Ok(());
+        }
+        if entry.original.to_str().is_none() || entry.path.to_str().is_none() {
+            self.report_verbose(&format!("Not recording '{}' in the manifest: its name is not valid UTF-8", entry.path.display()));
+            return Ok(());
+        }
+        let _lock = FileLock::acquire(&self.manifest_path())?;
+        let mut manifest = self.load_manifest()?.unwrap_or_default();
+        if !manifest.backups.iter().any(|known| known.original == entry.original) {
+            for backup in self.scan_backups(entry.original.as_os_str())?.0 {
+                if normalized_path(&backup.path) == entry.path {
+                    continue;
+                }
+                manifest.backups.push(ManifestEntry {
+                    original: entry.original.clone(),
+                    digest: self.backup_digest(&backup)?,
+                    path: normalized_path(&backup.path),
+                    version: backup.version,
+                    created: backup.created,
+                    size: backup.size,
+                    compressed: backup.compressed,
+                    encrypted: backup.encrypted,
+                });
+            }
+        }
+        manifest.backups.push(entry);
+        self.save_manifest(&manifest)
+    }
+
+    // Mark the manifest entry for a backup as created at this time
+    fn touch_in_manifest(&self, path: &Path, created: Timestamp) -> Result<()> {
+        let _lock = FileLock::acquire(&self.manifest_path())?;
+        let Some(mut manifest) = self.load_manifest()? else {
+            return Ok(());
+        };
+        let path = normalized_path(path);
+        for entry in manifest.backups.iter_mut().filter(|entry| entry.path == path) {
+            entry.created = created;
+        }
+        self.save_manifest(&manifest)
+    }
+
+    // Point the manifest entry for a backup at the file that now holds it
+    fn move_in_manifest(&self, from: &Path, to: &Path, size: u64) -> Result<()> {
+        let _lock = FileLock::acquire(&self.manifest_path())?;
+        let Some(mut manifest) = self.load_manifest()? else {
+            return Ok(());
+        };
+        let from = normalized_path(from);
+        for entry in manifest.backups.iter_mut().filter(|entry| entry.path == from) {
+            entry.path = normalized_path(to);
+            entry.size = size;
+        }
+        self.save_manifest(&manifest)
+    }
+
+    // Undo a migration: move a backup back to the file it was adopted from, without its checksum or manifest entry
+    fn move_back(&self, backup_path: &Path, original: &Path) -> Result<()> {
+        rename_across_devices(backup_path, original).map_err(|e| io_error_at(backup_path, e))?;
+        match fs::remove_file(checksum_path(backup_path)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.forget_backup(backup_path)
+    }
+
+    // Drop a removed backup from the manifest, along with entries for the same file whose backup is gone
+    fn forget_backup(&self, backup_path: &Path) -> Result<()> {
+        let _lock = FileLock::acquire(&self.manifest_path())?;
+        let Some(mut manifest) = self.load_manifest()? else {
+            return Ok(());
+        };
+        let backup_path = normalized_path(backup_path);
+        let original = manifest.backups.iter()
+            .find(|entry| entry.path == backup_path)
+            .map(|entry| entry.original.clone());
+        let before = manifest.backups.len();
+        manifest.backups.retain(|entry| {
+            entry.path != backup_path && (Some(&entry.original) != original.as_ref() || entry.path.is_file())
+        });
+        if manifest.backups.len() != before {
+            self.save_manifest(&manifest)?;
+        }
+        Ok(())
+    }
+
+    // Backups of a file, newest first, plus backup-looking files whose timestamp could not be parsed
+    fn scan_backups(&self, filename: &OsStr) -> Result<(Vec<BackupEntry>, Vec<PathBuf>)> {
+        let file_path = self.validate_os_path(filename)?;
+        let backup_base = self.storage_location(&file_path);
+        let dir = match backup_base.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let Some(raw_base_name) = file_path.file_name() else {
+            return Err(SafeBackupError::InvalidPath(format!("'{}' has no file name", file_path.display())));
+        };
+        // The rest of a backup name is ASCII, so it is parsed lossily; two non-UTF-8 names can share
+        // a lossy form, so the raw bytes must start with the original's as well
+        let base_name = raw_base_name.to_string_lossy();
+        let base_name = base_name.as_ref();
+
+        let prefix = format!("{}.", base_name);
+        let mut backups = Vec::new();
+        let mut unparsed = Vec::new();
+        // A backup directory that was never created simply lists no backups
+        for entry in self.storage.list(&dir).map_err(|e| io_error_at(&dir, e))? {
+            let Some(raw_name) = entry.name.file_name() else { continue };
+            if !raw_name.as_encoded_bytes().starts_with(raw_base_name.as_encoded_bytes()) {
+                continue;
+            }
+            let name = raw_name.to_string_lossy();
+            let name = name.as_ref();
+
+            // Only accept names of the form {filename}.{timestamp}.bak[.gz][.enc] or {filename}.{digest prefix}.bak[.gz][.enc]
+            let parsed = parse_backup_name(name, &self.backup_suffix).filter(|(original, ..)| *original == base_name);
+            // Content-named backups are as new as their last backup run, which touches them
+            let created = match parsed {
+                Some((_, _, Some(timestamp), ..)) => Some(timestamp),
+                Some(_) => entry.modified,
+                None => None,
+            };
+            let (Some((_, version, _, compressed, encrypted)), Some(created)) = (parsed, created) else {
+                if name.starts_with(&prefix) && has_backup_extension(name, &self.backup_suffix) {
+                    unparsed.push(entry.name.clone());
+                }
+                continue;
+            };
+
+            backups.push(BackupEntry {
+                version: version.to_string(),
+                path: entry.name,
+                created,
+                size: entry.size,
+                compressed,
+                encrypted,
+            });
+        }
+
+        backups.sort_by_key(|b| std::cmp::Reverse((b.created, version_counter(&b.version))));
+        Ok((backups, unparsed))
+    }
+
+    // Delete backups of a file whose embedded timestamp is older than max_age
+    // Backups without a parseable timestamp are never deleted, only reported
+    #[cfg(feature = "chrono")]
+    pub fn prune_older_than(&self, filename: &str, max_age: chrono::Duration) -> Result<Vec<PathBuf>> {
+        let (backups, unparsed) = self.scan_backups(OsStr::new(filename))?;
+        for path in &unparsed {
+            self.log_warning(&format!("Warning: left '{}' untouched, its timestamp could not be parsed", path.display()), LogStatus::Info, Some(filename))?;
+        }
+
+        let cutoff = Utc::now() - max_age;
+        let mut removed = Vec::new();
+        for backup in backups.into_iter().filter(|b| b.created < cutoff) {
+            self.remove_backup(&backup.path)?;
+            self.log_event(&format!("Pruned backup '{}' of '{}' (older than {})", backup.path.display(), filename, timestamp::format_utc(&cutoff)), LogStatus::Success, Some(filename))?;
+            removed.push(backup.path);
+        }
+
+        Ok(removed)
+    }
+
+    // Delete all but the `keep` most recent backups of a file, returning the removed paths
+    pub fn prune_backups(&self, filename: &str, keep: usize) -> Result<Vec<PathBuf>> {
+        let backups = self.list_backups(filename)?;
+        let mut removed = Vec::new();
+
+        // list_backups is newest first, so everything past `keep` is stale
+        for backup in backups.into_iter().skip(keep) {
+            self.remove_backup(&backup.path)?;
+            self.log_event(&format!("Pruned backup '{}' of '{}'", backup.path.display(), filename), LogStatus::Success, Some(filename))?;
+            removed.push(backup.path);
+        }
+
+        self.log_event(&format!("Performed prune of '{}': removed {} backup(s), keeping at most {}", filename, removed.len(), keep), LogStatus::Success, Some(filename))?;
+        Ok(removed)
+    }
+
+    // Remove a backup file together with its checksum and metadata sidecars
+    fn remove_backup(&self, backup_path: &Path) -> Result<()> {
+        if self.dry_run {
+            self.report(&format!("[DRY-RUN] Would remove backup '{}'", backup_path.display()));
+            return Ok(());
+        }
+
+        let blob_path = if is_pointer(backup_path) { Some(self.resolve_pointer(backup_path)?) } else { None };
+        // Storage such as a zip archive cannot remove single backups, which is not an IO failure
+        self.storage.remove(backup_path).map_err(|e| match e.kind() {
+            io::ErrorKind::Unsupported => SafeBackupError::InvalidPath(e.to_string()),
+            _ => io_error_at(backup_path, e),
+        })?;
+        for sidecar in [checksum_path(backup_path), metadata_path(backup_path)] {
+            match self.storage.remove(&sidecar) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(io_error_at(&sidecar, e)),
+            }
+        }
+        if let Some(blob_path) = blob_path {
+            self.release_blob(&blob_path)?;
+        }
+        self.forget_backup(backup_path)
+    }
+
+    // Print the backups available for a file
+    // Only backups created at or after since are printed when it is given
+    pub fn print_backups(&self, filename: &str, since: Option<Timestamp>) -> Result<ActionOutcome> {
+        let backups = match since {
+            Some(since) => self.list_backups_since(filename, since)?,
+            None => self.list_backups(filename)?,
+        };
+        if let (true, Some(since)) = (backups.is_empty(), since) {
+            println!("No backups found for '{}' since {}", filename, timestamp::format_utc(&since));
+        } else if backups.is_empty() {
+            println!("No backups found for '{}'", filename);
+        } else {
+            println!("Backups of '{}' (newest first):", filename);
+            for backup in &backups {
+                println!("  {}  {}  {}", backup.version, format_size(backup.size), backup.path.display());
+            }
+        }
+        self.log_action(&format!("Listed {} backup(s) of '{}'", backups.len(), filename))?;
+        Ok(ActionOutcome::Listed { backups })
+    }
+
+    // Restore a version picked from a numbered list of the backups of filename, newest first
+    // Invalid selections prompt again; an empty answer cancels
+    pub fn restore_interactive(&self, filename: &str, destination: Option<&str>) -> Result<ActionOutcome> {
+        self.validate_path(filename)?;
+        let backups = self.list_backups(filename)?;
+        if backups.is_empty() {
+            return Err(SafeBackupError::FileNotFound(format!("No backups of '{}' exist", filename)));
+        }
+
+        println!("Backups of '{}' (newest first):", filename);
+        for (number, backup) in backups.iter().enumerate() {
+            println!("  {:>3}) {}  {}  {}", number + 1, backup.version, format_size(backup.size), backup.path.display());
+        }
+
+        let backup = loop {
+            let selection = match Self::get_user_input(&format!("Select a backup to restore (1-{}, empty to cancel): ", backups.len()), None) {
+                Ok(selection) => selection,
+                Err(SafeBackupError::InvalidPath(message)) => {
+                    print_error(&message);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if selection.is_empty() {
+                self.report_cancelled("Restore cancelled.");
+                self.log_event(&format!("Interactive restore of '{}' cancelled", filename), LogStatus::Cancelled, Some(filename))?;
+                return Ok(ActionOutcome::Cancelled);
+            }
+            match selection.parse::<usize>() {
+                Ok(number) if (1..=backups.len()).contains(&number) => break &backups[number - 1],
+                _ => print_error(&format!("Invalid selection '{}': enter a number from 1 to {}", selection, backups.len())),
+            }
+        };
+
+        self.log_action(&format!("Selected backup '{}' of '{}' for restore", backup.version, filename))?;
+        self.restore_version_to(OsStr::new(filename), Some(&backup.version), OsStr::new(destination.unwrap_or(filename)))
+    }
+
+    // Secure file restoration with validation
+    // Restores the requested version, or the most recent backup when no version is given
+    pub fn restore_file(&self, filename: &str, version: Option<&str>) -> Result<ActionOutcome> {
+        self.restore_os_file(OsStr::new(filename), version)
+    }
+
+    // restore_file for a name that need not be UTF-8; messages and the log show it lossily
+    pub fn restore_os_file(&self, os_filename: &OsStr, version: Option<&str>) -> Result<ActionOutcome> {
+        self.restore_version_to(os_filename, version, os_filename)
+    }
+
+    // Restore the most recent backup of filename into another validated path, leaving the original untouched
+    pub fn restore_file_to(&self, filename: &str, destination: &str) -> Result<ActionOutcome> {
+        self.restore_os_file_to(OsStr::new(filename), OsStr::new(destination))
+    }
+
+    // restore_file_to for names that need not be UTF-8
+    pub fn restore_os_file_to(&self, os_filename: &OsStr, os_destination: &OsStr) -> Result<ActionOutcome> {
+        self.restore_version_to(os_filename, None, os_destination)
+    }
+
+    fn restore_version_to(&self, os_filename: &OsStr, version: Option<&str>, os_destination: &OsStr) -> Result<ActionOutcome> {
+        self.validate_os_path(os_filename)?;
+        let file_path = self.validate_os_path(os_destination)?;
+        let backups = self.list_os_backups(os_filename)?;
+        let (lossy_filename, lossy_destination) = (os_filename.to_string_lossy(), os_destination.to_string_lossy());
+        let (filename, destination) = (lossy_filename.as_ref(), lossy_destination.as_ref());
+
+        let selected = match version {
+            Some(requested) => backups.iter().find(|b| b.version == requested),
+            None => backups.first(),
+        };
+
+        let (backup_path, compressed, encrypted) = match selected {
+            Some(backup) => (self.validate_os_path(backup.path.as_os_str())?, backup.compressed, backup.encrypted),
+            None => {
+                let message = match version {
+                    Some(requested) => {
+                        let available: Vec<&str> = backups.iter().map(|b| b.version.as_str()).collect();
+                        let available = if available.is_empty() { "none".to_string() } else { available.join(", ") };
+                        format!("Backup version '{}' of '{}' does not exist (available: {})", requested, filename, available)
+                    }
+                    None => format!("No backups of '{}' exist", filename),
+                };
+                return Err(SafeBackupError::FileNotFound(message));
+            }
+        };
+
+        if !self.storage.exists(&backup_path) {
+            return Err(SafeBackupError::InvalidPath(format!("'{}' is not a regular file", backup_path.display())));
+        }
+
+        // Writing through a symlink could overwrite a file outside the current directory
+        self.check_symlink(&file_path, destination)?;
+        ensure_distinct(&backup_path, &file_path)?;
+
+        if self.no_clobber && !self.force && fs::symlink_metadata(&file_path).is_ok() {
+            self.log_event(&format!("Refused to overwrite existing '{}' with '{}'", destination, backup_path.display()), LogStatus::Cancelled, Some(filename))?;
+            return Err(SafeBackupError::DestinationExists(format!("'{}' already exists and was not overwritten", destination)));
+        }
+
+        let expected_hash = match self.read_sidecar(&checksum_path(&backup_path)) {
+            Ok(sidecar) => Some(sidecar_digest(&sidecar).map(str::to_string)
+                .ok_or_else(|| SafeBackupError::IntegrityError(format!("Checksum file of '{}' is empty", backup_path.display())))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.log_action(&format!("No checksum found for '{}', restoring without verification", backup_path.display()))?;
+                None
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // Stream the backup, decrypting and inflating it transparently; the size is only known up front for plain backups
+        let size = if compressed || encrypted || is_delta(&backup_path) { None } else { fs::metadata(&backup_path).ok().map(|metadata| metadata.len()) };
+        let progress = self.progress_bar(size);
+        progress.set_message(filename.to_string());
+        let algorithm = expected_hash.as_deref().map_or(self.hash_algorithm, HashAlgorithm::of);
+        let mut reader = HashingReader::new(self.open_backup(&backup_path, compressed, encrypted)?, progress, algorithm);
+
+        // Stage into a temp file so the original is only replaced once the checksum is validated
+        let staged = if self.dry_run {
+            io::copy(&mut reader, &mut io::sink())?;
+            None
+        } else {
+            let (temp, file) = self.with_retry(&format!("creating a temp file for '{}'", destination), || AtomicFile::create(&file_path))?;
+            let mut writer = BufWriter::new(file);
+            io::copy(&mut reader, &mut writer)?;
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+            Some(temp)
+        };
+        let (actual_hash, bytes) = reader.finish();
+
+        // Validate against the stored checksum before overwriting the original
+        if let Some(expected_hash) = expected_hash
+            && actual_hash != expected_hash
+        {
+            return Err(SafeBackupError::IntegrityError(format!(
+                "Backup '{}' is corrupt (expected {}, got {})",
+                backup_path.display(), expected_hash, actual_hash
+            )));
+        }
+
+        let Some(mut temp) = staged else {
+            self.report(&format!("[DRY-RUN] Would restore '{}' ({}) from '{}'", destination, format_size(bytes), backup_path.display()));
+            self.log_event(&format!("Performed restore to '{}' from '{}'", destination, backup_path.display()), LogStatus::Success, Some(filename))?;
+            return Ok(ActionOutcome::Restored { path: file_path, from: backup_path, bytes });
+        };
+
+        // Keep the permissions of the file being replaced; the metadata sidecar overrides them below
+        if let Ok(existing) = fs::metadata(&file_path) {
+            fs::set_permissions(temp.path(), existing.permissions()).map_err(|e| io_error_at(temp.path(), e))?;
+        }
+        self.with_retry(&format!("renaming into '{}'", destination), || temp.persist())?;
+        self.apply_file_metadata(&backup_path, &file_path)?;
+
+        self.report_verbose(&format!(
+            "Restored {} ({} bytes) to {}",
+            resolved_path(&backup_path).display(), bytes, resolved_path(&file_path).display()
+        ));
+        if os_destination == os_filename {
+            self.report_success(&format!("File restored from: {} ({})", backup_path.display(), format_size(bytes)));
+        } else {
+            self.report_success(&format!(
+                "Backup of '{}' restored to '{}' from: {} ({})", filename, destination, backup_path.display(), format_size(bytes)
+            ));
+        }
+        self.log_event(&format!("Performed restore to '{}' from '{}'", destination, backup_path.display()), LogStatus::Success, Some(filename))?;
+        Ok(ActionOutcome::Restored { path: file_path, from: backup_path, bytes })
+    }
+
+    // Restore the newest backup of every file backed up under a directory (default: everything)
+    // Files modified after their newest backup are skipped unless force is set
+    pub fn restore_all(&self, dir: Option<&Path>) -> Result<RestoreSummary> {
+        let relative_dir = match dir {
+            Some(dir) => self.validate_path(&dir.to_string_lossy())?,
+            None => PathBuf::new(),
+        };
+        let scan_root = if relative_dir.as_os_str().is_empty() {
+            self.storage_root()
+        } else {
+            self.storage_location(&relative_dir)
+        };
+
+        let mut summary = RestoreSummary::default();
+        for original in self.backed_up_originals(scan_root, relative_dir)? {
+            let filename = original.to_string_lossy().into_owned();
+            let newest = match self.list_os_backups(original.as_os_str()) {
+                Ok(backups) => backups.into_iter().next(),
+                Err(e) => {
+                    print_error(&format!("Restore of '{}' failed: {}", filename, e));
+                    self.log_event(&format!("Restore of '{}' failed: {}", filename, e), LogStatus::Error, Some(&filename))?;
+                    summary.failed += 1;
+                    continue;
+                }
+            };
+            let Some(newest) = newest else { continue };
+
+            // Keep live edits made after the backup was taken; backup timestamps only have second precision
+            // The original is named relative to the base directory, so it is resolved before it is looked at
+            let live = self.validate_os_path(original.as_os_str())?;
+            if !self.force
+                && let Ok(modified) = fs::metadata(&live).and_then(|m| m.modified())
+                && modified > timestamp::to_system_time(newest.created) + Duration::from_secs(1)
+            {
+                self.report_cancelled(&format!("Skipped '{}': it is newer than its latest backup", filename));
+                self.log_event(&format!("Skipped restore of '{}': file is newer than its latest backup", filename), LogStatus::Cancelled, Some(&filename))?;
+                summary.skipped += 1;
+                continue;
+            }
+
+            match self.restore_os_file(original.as_os_str(), None) {
+                Ok(outcome) => {
+                    if let ActionOutcome::Restored { bytes, .. } = outcome {
+                        summary.bytes += bytes;
+                    }
+                    summary.restored.push(original);
+                }
+                Err(SafeBackupError::DestinationExists(_)) => {
+                    self.report_cancelled(&format!("Skipped '{}': it already exists", filename));
+                    summary.skipped += 1;
+                }
+                Err(e) => {
+                    print_error(&format!("Restore of '{}' failed: {}", filename, e));
+                    self.log_event(&format!("Restore of '{}' failed: {}", filename, e), LogStatus::Error, Some(&filename))?;
+                    summary.failed += 1;
+                }
+            }
+        }
+
+        self.report(&format!(
+            "Restored {} file(s) ({}), skipped {}, failed {}",
+            summary.restored.len(), format_size(summary.bytes), summary.skipped, summary.failed
+        ));
+        self.log_action(&format!(
+            "Restore-all finished: {} restored ({} bytes), {} skipped, {} failed",
+            summary.restored.len(), summary.bytes, summary.skipped, summary.failed
+        ))?;
+        Ok(summary)
+    }
+
+    // Write every stored version of filename into output_dir, each named by its backup timestamp
+    // and keeping the original extension (e.g. 2024-05-01_12-00-00.txt), decompressed and decrypted
+    // Each version is checked against its checksum like a normal restore; the original is not touched
+    pub fn export_versions(&self, filename: &str, output_dir: &str) -> Result<ActionOutcome> {
+        let file_path = self.validate_path(filename)?;
+        let dir = self.validate_path(output_dir)?;
+        let backups = self.list_backups(filename)?;
+        if backups.is_empty() {
+            return Err(SafeBackupError::FileNotFound(format!("No backups of '{}' exist", filename)));
+        }
+        if dir.exists() && !dir.is_dir() {
+            return Err(SafeBackupError::InvalidPath(format!("'{}' is not a directory", output_dir)));
+        }
+        if !self.dry_run {
+            fs::create_dir_all(&dir).map_err(|e| io_error_at(&dir, e))?;
+        }
+
+        let extension = file_path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+        let (mut paths, mut bytes) = (Vec::new(), 0);
+        for backup in &backups {
+            let destination = dir.join(format!("{}{}", backup.version, extension));
+            if let ActionOutcome::Restored { path, bytes: restored, .. } =
+                self.restore_version_to(OsStr::new(filename), Some(&backup.version), destination.as_os_str())?
+            {
+                paths.push(path);
+                bytes += restored;
+            }
+        }
+
+        self.report(&format!("Exported {} version(s) of '{}' to '{}' ({})", paths.len(), filename, output_dir, format_size(bytes)));
+        self.log_event(&format!("Exported {} version(s) of '{}' to '{}'", paths.len(), filename, output_dir), LogStatus::Success, Some(filename))?;
+        Ok(ActionOutcome::ExportedVersions { paths, bytes })
+    }
+
+    // Unified diff from the latest backup of a file to its current contents, empty when they match
+    // Binary contents (invalid UTF-8 or containing NUL) are summarized by size instead
+    pub fn diff_file(&self, filename: &str) -> Result<String> {
+        self.diff_os_file(OsStr::new(filename))
+    }
+
+    // diff_file for a name that need not be UTF-8; the diff headers and the log show it lossily
+    pub fn diff_os_file(&self, os_filename: &OsStr) -> Result<String> {
+        let file_path = self.validate_os_path(os_filename)?;
+        let lossy = os_filename.to_string_lossy();
+        let filename = lossy.as_ref();
+        if !file_path.is_file() {
+            return Err(SafeBackupError::FileNotFound(format!("File '{}' does not exist", filename)));
+        }
+        let Some(latest) = self.list_os_backups(os_filename)?.into_iter().next() else {
+            return Err(SafeBackupError::FileNotFound(format!("No backups of '{}' exist", filename)));
+        };
+
+        let current = fs::read(&file_path).map_err(|e| io_error_at(&file_path, e))?;
+        let mut backup = Vec::new();
+        self.open_backup(&latest.path, latest.compressed, latest.encrypted)?.read_to_end(&mut backup)?;
+
+        let diff = diff_contents(&latest.path.to_string_lossy(), &backup, filename, &current);
+
+        self.log_event(&format!("Compared '{}' with '{}': {}", filename, latest.path.display(), if diff.is_empty() { "identical" } else { "differs" }), LogStatus::Info, Some(filename))?;
+        Ok(diff)
+    }
+
+    // Sizes and the changed lines between a file and its latest backup, without restoring anything
+    pub fn preview_restore(&self, filename: &str) -> Result<RestorePreview> {
+        self.preview_restore_to(filename, filename)
+    }
+
+    fn preview_restore_to(&self, filename: &str, destination: &str) -> Result<RestorePreview> {
+        self.validate_path(filename)?;
+        let file_path = self.validate_path(destination)?;
+        let Some(latest) = self.list_backups(filename)?.into_iter().next() else {
+            return Err(SafeBackupError::FileNotFound(format!("No backups of '{}' exist", filename)));
+        };
+
+        let mut backup = Vec::new();
+        self.open_backup(&latest.path, latest.compressed, latest.encrypted)?.read_to_end(&mut backup)?;
+        let current = match fs::read(&file_path) {
+            Ok(current) => Some(current),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(io_error_at(&file_path, e)),
+        };
+        let diff = diff_contents(destination, current.as_deref().unwrap_or_default(), &latest.path.to_string_lossy(), &backup);
+
+        Ok(RestorePreview {
+            current_size: current.as_ref().map(|current| current.len() as u64),
+            backup_size: backup.len() as u64,
+            summary: summarize_diff(&diff),
+            backup: latest.path,
+            version: latest.version,
+        })
+    }
+
+    // Print what restoring would change and ask before restoring that same version; force skips the question
+    pub fn restore_with_preview(&self, filename: &str, destination: Option<&str>) -> Result<ActionOutcome> {
+        let destination = destination.unwrap_or(filename);
+        let preview = self.preview_restore_to(filename, destination)?;
+
+        println!("Restoring '{}' from {}", destination, preview.backup.display());
+        match preview.current_size {
+            Some(current_size) => println!(
+                "  Size: {} now, {} after the restore ({}{})",
+                format_size(current_size), format_size(preview.backup_size),
+                if preview.backup_size >= current_size { "+" } else { "-" }, format_size(preview.backup_size.abs_diff(current_size))
+            ),
+            None => println!("  Size: file does not exist, {} after the restore", format_size(preview.backup_size)),
+        }
+        for line in preview.summary.lines() {
+            println!("  {}", line);
+        }
+
+        if !self.force {
+            let Some(input) = self.read_confirmation("Restore this version? (yes/no): ")? else {
+                println!();
+                self.report_cancelled(&format!("No answer within {} seconds, restore cancelled.", self.confirm_timeout.as_secs()));
+                self.log_event(&format!("Restore confirmation for '{}' timed out, operation cancelled", destination), LogStatus::Cancelled, Some(filename))?;
+                return Ok(ActionOutcome::Cancelled);
+            };
+            if checked_input(&input, Some("no"))?.to_lowercase() != "yes" {
+                self.report_cancelled("Restore cancelled.");
+                self.log_event(&format!("Restore of '{}' cancelled after the preview", destination), LogStatus::Cancelled, Some(filename))?;
+                return Ok(ActionOutcome::Cancelled);
+            }
+        }
+        self.restore_version_to(OsStr::new(filename), Some(&preview.version), OsStr::new(destination))
+    }
+
+    // Compare a file against its latest backup by SHA-256 without restoring anything
+    pub fn verify_file(&self, filename: &str) -> Result<bool> {
+        self.verify_os_file(OsStr::new(filename))
+    }
+
+    // verify_file for a name that need not be UTF-8; messages and the log show it lossily
+    pub fn verify_os_file(&self, os_filename: &OsStr) -> Result<bool> {
+        let file_path = self.validate_os_path(os_filename)?;
+        let lossy = os_filename.to_string_lossy();
+        let filename = lossy.as_ref();
+        if !file_path.is_file() {
+            return Err(SafeBackupError::FileNotFound(format!("File '{}' does not exist", filename)));
+        }
+        let Some(latest) = self.list_os_backups(os_filename)?.into_iter().next() else {
+            return Err(SafeBackupError::FileNotFound(format!("No backups of '{}' exist", filename)));
+        };
+
+        let source_hash = hash_reader(BufReader::new(fs::File::open(&file_path).map_err(|e| io_error_at(&file_path, e))?), self.hash_algorithm)?;
+        let backup_hash = hash_reader(self.open_backup(&latest.path, latest.compressed, latest.encrypted)?, self.hash_algorithm)?;
+        let matches = source_hash == backup_hash;
+
+        if matches {
+            self.report_success(&format!("'{}' matches its latest backup: {}", filename, latest.path.display()));
+            self.log_event(&format!("Verified '{}' against '{}': identical", filename, latest.path.display()), LogStatus::Success, Some(filename))?;
+        } else {
+            let message = format!("'{}' differs from its latest backup: {}", filename, latest.path.display());
+            println!("{}", message.if_supports_color(Stream::Stdout, |text| text.red()));
+            self.log_event(&format!("Verified '{}' against '{}': differs", filename, latest.path.display()), LogStatus::Error, Some(filename))?;
+        }
+        Ok(matches)
+    }
+
+    // The original files that have at least one backup under scan_root, relative to the working directory
+    fn backed_up_originals(&self, scan_root: PathBuf, relative_dir: PathBuf) -> Result<BTreeSet<PathBuf>> {
+        // Templated layouts do not mirror the original tree, so the manifest is the only index of them
+        if self.path_template.is_some() {
+            let relative_dir = normalized_path(&relative_dir);
+            return Ok(self.load_manifest()?.unwrap_or_default().backups.into_iter()
+                .map(|entry| entry.original)
+                .filter(|original| original.starts_with(&relative_dir))
+                .collect());
+        }
+        let mut originals = BTreeSet::new();
+        for entry in self.storage.list_all(&scan_root).map_err(|e| io_error_at(&scan_root, e))? {
+            let relative = if scan_root == Path::new(".") {
+                entry.name.as_path()
+            } else {
+                let Ok(relative) = entry.name.strip_prefix(&scan_root) else { continue };
+                relative
+            };
+            let Some(raw_name) = relative.file_name() else { continue };
+            let name = raw_name.to_string_lossy();
+            if let Some(original) = parse_backup_name(&name, &self.backup_suffix).and_then(|(original, ..)| raw_original_name(raw_name, &name[original.len()..])) {
+                originals.insert(relative_dir.join(relative.parent().unwrap_or(Path::new(""))).join(original));
+            }
+        }
+        Ok(originals)
+    }
+
+    // Versions, newest backup, disk usage and live-file state for every backed-up file
+    // The live file is compared with the hash recorded at backup time, so encrypted backups need no passphrase
+    pub fn status(&self) -> Result<Vec<FileStatus>> {
+        let mut files = Vec::new();
+        for original in self.backed_up_originals(self.storage_root(), PathBuf::new())? {
+            let backups = self.list_os_backups(original.as_os_str())?;
+            let Some(latest) = backups.first() else { continue };
+
+            let file_path = self.validate_os_path(original.as_os_str())?;
+            let live = if file_path.is_file() {
+                let digest = self.backup_digest(latest)?;
+                let live_hash = hash_reader(BufReader::new(fs::File::open(&file_path).map_err(|e| io_error_at(&file_path, e))?), HashAlgorithm::of(&digest))?;
+                if live_hash == digest { LiveState::Matches } else { LiveState::Differs }
+            } else {
+                LiveState::Missing
+            };
+
+            files.push(FileStatus {
+                original,
+                versions: backups.len(),
+                newest: latest.created,
+                total_size: backups.iter().map(|b| b.size).sum(),
+                live,
+            });
+        }
+        Ok(files)
+    }
+
+    // Print status() as an aligned table
+    pub fn print_status(&self) -> Result<ActionOutcome> {
+        let files = self.status()?;
+        if files.is_empty() {
+            println!("No backups found");
+        } else {
+            let rows: Vec<[String; 5]> = files.iter().map(|file| [
+                file.original.display().to_string(),
+                file.versions.to_string(),
+                timestamp::format_utc(&file.newest),
+                format_size(file.total_size),
+                match file.live {
+                    LiveState::Matches => "matches",
+                    LiveState::Differs => "differs",
+                    LiveState::Missing => "missing",
+                }.to_string(),
+            ]).collect();
+            print_table(["FILE", "VERSIONS", "NEWEST", "SIZE", "LIVE FILE"], &rows);
+        }
+        self.log_action(&format!("Reported the status of {} backed-up file(s)", files.len()))?;
+        Ok(ActionOutcome::Status { files })
+    }
+
+    // Bytes taken by backups: every backup and sidecar in storage plus the dedup store.
+    // A backup directory or archive that does not exist yet uses none
+    pub fn disk_usage(&self) -> Result<u64> {
+        let store = normalized_path(&self.store_dir());
+        let root = self.storage_root();
+        Ok(self.storage.list_all(&root).map_err(|e| io_error_at(&root, e))?.into_iter()
+            .filter(|entry| {
+                normalized_path(&entry.name).starts_with(&store)
+                    || (is_backup_artifact(&entry.name, &self.backup_suffix) && !is_internal_file(&entry.name))
+            })
+            .map(|entry| entry.size)
+            .sum())
+    }
+
+    // Bytes taken by the backups of each backed-up file, with their checksum and metadata sidecars
+    // Dedup backups only count their pointers; the stored contents are shared and counted by disk_usage
+    pub fn usage_by_file(&self) -> Result<Vec<FileUsage>> {
+        let root = self.storage_root();
+        let sizes: BTreeMap<PathBuf, u64> = self.storage.list_all(&root).map_err(|e| io_error_at(&root, e))?.into_iter()
+            .map(|entry| (normalized_path(&entry.name), entry.size))
+            .collect();
+        let size_of = |path: &Path| sizes.get(&normalized_path(path)).copied().unwrap_or(0);
+        let mut files = Vec::new();
+        for original in self.backed_up_originals(self.storage_root(), PathBuf::new())? {
+            let backups = self.list_os_backups(original.as_os_str())?;
+            if backups.is_empty() {
+                continue;
+            }
+            let bytes = backups.iter()
+                .map(|backup| size_of(&backup.path) + size_of(&checksum_path(&backup.path)) + size_of(&metadata_path(&backup.path)))
+                .sum();
+            files.push(FileUsage { original, versions: backups.len(), bytes });
+        }
+        Ok(files)
+    }
+
+    // Print usage_by_file() as a table followed by the total from disk_usage()
+    pub fn print_usage(&self) -> Result<ActionOutcome> {
+        let files = self.usage_by_file()?;
+        let total = self.disk_usage()?;
+        if files.is_empty() {
+            println!("No backups found");
+        } else {
+            let rows: Vec<[String; 3]> = files.iter()
+                .map(|file| [file.original.display().to_string(), file.versions.to_string(), format_size(file.bytes)])
+                .collect();
+            print_table(["FILE", "VERSIONS", "SIZE"], &rows);
+            let listed: u64 = files.iter().map(|file| file.bytes).sum();
+            if total > listed {
+                println!("Shared store and other backup files: {}", format_size(total - listed));
+            }
+        }
+        println!("Total: {}", format_size(total));
+        self.log_action(&format!("Reported disk usage of {} bytes for {} backed-up file(s)", total, files.len()))?;
+        Ok(ActionOutcome::Usage { files, total })
+    }
+
+    // Recompute the SHA-256 of every backup and compare it with its checksum sidecar and manifest entry
+    // Unreadable backups, missing sidecars and manifest entries whose backup is gone are reported too
+    pub fn check_all(&self) -> Result<Vec<IntegrityProblem>> {
+        let manifest = self.load_manifest()?.unwrap_or_default();
+        let mut problems = Vec::new();
+        let mut checked = 0;
+
+        for original in self.backed_up_originals(self.storage_root(), PathBuf::new())? {
+            for backup in self.list_os_backups(original.as_os_str())? {
+                checked += 1;
+                let mut problem = |reason: String| problems.push(IntegrityProblem { path: backup.path.clone(), reason });
+                let sidecar = self.read_sidecar(&checksum_path(&backup.path));
+                let expected = sidecar.as_ref().ok().and_then(|sidecar| sidecar_digest(sidecar));
+                let path = normalized_path(&backup.path);
+                let recorded = manifest.backups.iter().find(|entry| entry.path == path).map(|entry| entry.digest.as_str());
+
+                // Each stored digest is checked with its own algorithm, hashing the contents once per algorithm
+                let mut digests: Vec<(HashAlgorithm, String)> = Vec::new();
+                let mut digest_as = |algorithm: HashAlgorithm| -> Result<String> {
+                    if let Some((_, digest)) = digests.iter().find(|(known, _)| *known == algorithm) {
+                        return Ok(digest.clone());
+                    }
+                    let digest = hash_reader(self.open_backup(&backup.path, backup.compressed, backup.encrypted)?, algorithm)?;
+                    digests.push((algorithm, digest.clone()));
+                    Ok(digest)
+                };
+                let actual = match digest_as(expected.or(recorded).map_or(self.hash_algorithm, HashAlgorithm::of)) {
+                    Ok(actual) => actual,
+                    Err(e) => {
+                        problem(format!("could not be read: {}", e));
+                        continue;
+                    }
+                };
+
+                match (&sidecar, expected) {
+                    (_, Some(expected)) if expected != actual => {
+                        problem(format!("checksum mismatch: sidecar has {}, contents hash to {}", expected, actual));
+                    }
+                    (Ok(_), None) => problem("checksum sidecar is empty".to_string()),
+                    (Err(e), _) if e.kind() == io::ErrorKind::NotFound => problem("checksum sidecar is missing".to_string()),
+                    (Err(e), _) => problem(format!("checksum sidecar could not be read: {}", e)),
+                    _ => {}
+                }
+
+                if let Some(recorded) = recorded {
+                    match digest_as(HashAlgorithm::of(recorded)) {
+                        Ok(actual) if actual != recorded => {
+                            problem(format!("checksum mismatch: manifest has {}, contents hash to {}", recorded, actual));
+                        }
+                        Ok(_) => {}
+                        Err(e) => problem(format!("could not be read: {}", e)),
+                    }
+                }
+            }
+        }
+
+        for entry in manifest.backups.iter().filter(|entry| !self.storage.exists(&entry.path)) {
+            problems.push(IntegrityProblem {
+                path: entry.path.clone(),
+                reason: format!("listed in the manifest for '{}' but missing", entry.original.display()),
+            });
+        }
+
+        for problem in &problems {
+            self.log_event(&format!("Integrity check of '{}' failed: {}", problem.path.display(), problem.reason), LogStatus::Error, None)?;
+        }
+        self.log_action(&format!("Checked {} backup(s): {} problem(s) found", checked, problems.len()))?;
+        Ok(problems)
+    }
+
+    // Print the problems found by check_all, one per line
+    pub fn print_check(&self) -> Result<ActionOutcome> {
+        let problems = self.check_all()?;
+        if problems.is_empty() {
+            self.report_success("All backups passed the integrity check");
+        }
+        for problem in &problems {
+            let message = format!("{}: {}", problem.path.display(), problem.reason);
+            println!("{}", message.if_supports_color(Stream::Stdout, |text| text.red()));
+        }
+        Ok(ActionOutcome::Checked { problems })
+    }
+
+    // Adopt {name}.bak files left by the old single-backup version: each one is renamed to a timestamped
+    // backup named after its mtime, given a checksum sidecar and recorded in the manifest
+    // Migrated files no longer end in a bare .bak, so running it again finds nothing to do
+    pub fn migrate(&self) -> Result<Vec<PathBuf>> {
+        if self.archive.is_some() || self.remote.is_some() {
+            return Err(SafeBackupError::InvalidPath("migrate only works with local backups, not an archive or sftp:// destination".to_string()));
+        }
+        let backup_root = fs::canonicalize(&self.backup_dir).ok().filter(|_| self.backup_dir != Path::new("."));
+        let store_root = fs::canonicalize(self.store_dir()).ok();
+
+        let mut legacy = Vec::new();
+        let mut pending = vec![self.root_dir()];
+        while let Some(dir) = pending.pop() {
+            let canonical = fs::canonicalize(&dir).ok();
+            if canonical.is_some() && (canonical == backup_root || canonical == store_root) {
+                continue;
+            }
+            for entry in fs::read_dir(&dir).map_err(|e| io_error_at(&dir, e))? {
+                let entry = entry?;
+                let path = if dir == Path::new(".") { PathBuf::from(entry.file_name()) } else { entry.path() };
+                let file_type = entry.file_type()?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if file_type.is_dir() {
+                    pending.push(path);
+                } else if file_type.is_file()
+                    && name.len() > ".bak".len()
+                    && name.ends_with(".bak")
+                    && parse_backup_name(&name, ".bak").is_none()
+                {
+                    legacy.push(path);
+                }
+            }
+        }
+        legacy.sort();
+
+        // A failure partway through leaves the earlier files migrated, unless rollback moves them back
+        let mut transaction = Transaction::default();
+        let mut migrated = Vec::new();
+        for legacy_path in legacy {
+            match self.migrate_legacy(&legacy_path, &mut transaction) {
+                Ok(Some(backup_path)) => migrated.push(backup_path),
+                Ok(None) => {}
+                Err(e) if self.rollback && !self.dry_run => {
+                    let undone = transaction.rollback(self)?;
+                    self.report_cancelled(&format!("Rolled back {} migrated backup(s) after '{}' failed", undone, legacy_path.display()));
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if migrated.is_empty() {
+            self.report("No legacy .bak backups to migrate");
+        }
+        self.log_action(&format!("Migration finished: {} legacy backup(s) migrated", migrated.len()))?;
+        Ok(migrated)
+    }
+
+    // Migrate one legacy backup, recording the rename in transaction; None when it was skipped
+    fn migrate_legacy(&self, legacy_path: &Path, transaction: &mut Transaction) -> Result<Option<PathBuf>> {
+        // The name need not be UTF-8, so the paths are built from its bytes and only messages use its lossy text
+        let legacy_name = legacy_path.to_string_lossy().into_owned();
+        self.validate_os_path(legacy_path.as_os_str())?;
+        let legacy_bytes = legacy_path.as_os_str().as_encoded_bytes();
+        let original_bytes = legacy_bytes.strip_suffix(b".bak").unwrap_or(legacy_bytes);
+        // SAFETY: the bytes come from as_encoded_bytes and are only split before the ASCII ".bak"
+        let original = PathBuf::from(unsafe { OsStr::from_encoded_bytes_unchecked(original_bytes) });
+        let metadata = fs::metadata(legacy_path).map_err(|e| io_error_at(legacy_path, e))?;
+        let created = timestamp::from_system_time(metadata.modified()?);
+        let version = timestamp::format_version(&created);
+        let mut backup_name = self.backup_location(&original).into_os_string();
+        backup_name.push(format!(".{}{}", version, self.backup_suffix));
+        let backup_path = self.validate_os_path(&backup_name)?;
+
+        if fs::symlink_metadata(&backup_path).is_ok() {
+            self.report_cancelled(&format!("Skipped '{}': '{}' already exists", legacy_name, backup_path.display()));
+            self.log_event(&format!("Skipped migration of '{}': '{}' already exists", legacy_name, backup_path.display()), LogStatus::Cancelled, None)?;
+            return Ok(None);
+        }
+        if self.dry_run {
+            self.report(&format!("[DRY-RUN] Would migrate '{}' to '{}'", legacy_name, backup_path.display()));
+            return Ok(Some(backup_path));
+        }
+
+        if let Some(parent) = backup_path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent).map_err(|e| io_error_at(parent, e))?;
+        }
+        let _lock = FileLock::try_acquire(&self.backup_lock_path(&original))?;
+        let digest = hash_reader(BufReader::new(fs::File::open(legacy_path).map_err(|e| io_error_at(legacy_path, e))?), self.hash_algorithm)?;
+        rename_across_devices(legacy_path, &backup_path).map_err(|e| io_error_at(legacy_path, e))?;
+        transaction.moved(legacy_path.to_path_buf(), backup_path.clone());
+        write_atomic(&checksum_path(&backup_path), format!("{}\n", digest).as_bytes())?;
+        self.record_backup(ManifestEntry {
+            original: normalized_path(&original),
+            path: normalized_path(&backup_path),
+            version,
+            created,
+            size: metadata.len(),
+            digest,
+            compressed: false,
+            encrypted: false,
+        })?;
+
+        self.report_success(&format!("Migrated '{}' to '{}'", legacy_name, backup_path.display()));
+        self.log_event(&format!("Migrated legacy backup '{}' to '{}'", legacy_name, backup_path.display()), LogStatus::Success, Some(&original.to_string_lossy()))?;
+        Ok(Some(backup_path))
+    }
+
+    // Reapply the permissions and mtime recorded at backup time
+    // Anything that cannot be restored on this platform is logged as a warning instead of failing the restore
+    fn apply_file_metadata(&self, backup_path: &Path, file_path: &Path) -> Result<()> {
+        let sidecar = match self.read_sidecar(&metadata_path(backup_path)) {
+            Ok(sidecar) => sidecar,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.log_warning(&format!("Warning: no metadata found for '{}', permissions and mtime not restored", backup_path.display()), LogStatus::Info, None)?;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut mode = None;
+        let mut readonly = None;
+        let mut mtime = None;
+        for line in sidecar.lines() {
+            match line.split_once('=') {
+                Some(("mode", value)) => mode = u32::from_str_radix(value, 8).ok(),
+                Some(("readonly", value)) => readonly = value.parse::<bool>().ok(),
+                Some(("mtime", value)) => {
+                    let (secs, nanos) = value.split_once('.').unwrap_or((value, "0"));
+                    if let (Ok(secs), Ok(nanos)) = (secs.parse::<i64>(), nanos.parse::<u32>()) {
+                        mtime = Some(FileTime::from_unix_time(secs, nanos));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut permissions = fs::metadata(file_path).map_err(|e| io_error_at(file_path, e))?.permissions();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            match mode {
+                Some(mode) => permissions.set_mode(mode),
+                None => {
+                    if let Some(readonly) = readonly {
+                        permissions.set_readonly(readonly);
+                    }
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            if mode.is_some() {
+                self.log_warning(&format!("Warning: mode bits for '{}' are not supported on this platform", file_path.display()), LogStatus::Info, None)?;
+            }
+            if let Some(readonly) = readonly {
+                permissions.set_readonly(readonly);
+            }
+        }
+
+        // mtime first: a read-only mode may otherwise prevent updating it
+        if let Some(mtime) = mtime
+            && let Err(e) = filetime::set_file_mtime(file_path, mtime)
+        {
+            self.log_warning(&format!("Warning: could not restore mtime of '{}': {}", file_path.display(), e), LogStatus::Info, None)?;
+        }
+        if let Err(e) = fs::set_permissions(file_path, permissions) {
+            self.log_warning(&format!("Warning: could not restore permissions of '{}': {}", file_path.display(), e), LogStatus::Info, None)?;
+        }
+        Ok(())
+    }
+
+    // Back up filename whenever it changes, until the process is interrupted or the watch fails
+    // A backup runs once the file has been quiet for watch_debounce, so a burst of saves makes one backup.
+    // The parent directory is watched, so editors that save by renaming a new file into place are noticed.
+    // on_backup runs after every new backup, for example to prune old ones; returns the number of backups
+    pub fn watch_file(&self, filename: &str, mut on_backup: impl FnMut(&Path) -> Result<()>) -> Result<usize> {
+        use notify::Watcher;
+
+        let file_path = self.validate_path(filename)?;
+        if !file_path.is_file() {
+            return Err(SafeBackupError::FileNotFound(format!("File '{}' does not exist", filename)));
+        }
+        let dir = match file_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let watched_name = file_path.file_name().unwrap_or_default().to_os_string();
+        let watch_error = |e: notify::Error| SafeBackupError::IoError(io::Error::other(format!("Cannot watch '{}': {}", filename, e)));
+
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        }).map_err(watch_error)?;
+        watcher.watch(&dir, notify::RecursiveMode::NonRecursive).map_err(watch_error)?;
+
+        // Reads and events for the backups, log and other files in the directory are ignored
+        let is_change = |event: &notify::Result<notify::Event>| match event {
+            Ok(event) => !event.kind.is_access() && event.paths.iter().any(|path| path.file_name() == Some(&watched_name)),
+            Err(_) => false,
+        };
+
+        self.report(&format!("Watching '{}' for changes, press Ctrl-C to stop", filename));
+        self.log_event(&format!("Started watching '{}'", filename), LogStatus::Info, Some(filename))?;
+        let mut backups = 0;
+        while let Ok(event) = receiver.recv() {
+            if let Err(e) = &event {
+                self.log_warning(&format!("Warning: watch of '{}' reported an error: {}", filename, e), LogStatus::Error, Some(filename))?;
+            }
+            if !is_change(&event) {
+                continue;
+            }
+
+            let mut deadline = Instant::now() + self.watch_debounce;
+            loop {
+                match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(event) if is_change(&event) => deadline = Instant::now() + self.watch_debounce,
+                    Ok(_) => {}
+                    Err(mpsc::RecvTimeoutError::Timeout) => break,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(backups),
+                }
+            }
+
+            // Removed, or replaced and not back yet; the next change triggers another attempt
+            if !file_path.is_file() {
+                continue;
+            }
+            match self.backup_file(filename) {
+                Ok(ActionOutcome::Backed { path, .. }) => {
+                    backups += 1;
+                    self.log_event(&format!("Watch of '{}' made automatic backup '{}'", filename, path.display()), LogStatus::Info, Some(filename))?;
+                    on_backup(&path)?;
+                }
+                Ok(_) => {}
+                // A failed backup does not end the watch, the file may be mid-save
+                Err(e) => {
+                    print_error(&format!("Automatic backup of '{}' failed: {}", filename, e));
+                    self.log_event(&format!("Automatic backup of '{}' failed: {}", filename, e), LogStatus::Error, Some(filename))?;
+                }
+            }
+        }
+        Ok(backups)
+    }
+
+    // Back up the files of one scheduled entry now, then apply its retention to each of them
+    // Failures are logged per file without stopping the others; returns the number of new backups
+    #[cfg(feature = "chrono")]
+    pub fn run_scheduled(&self, job: &ScheduledBackup) -> Result<usize> {
+        let (mut backed_up, mut failed) = (0, 0);
+        for filename in &job.files {
+            let result = if job.recursive {
+                self.backup_directory(filename).and_then(|summary| {
+                    failed += summary.failed;
+                    for file in &summary.backed_up_files {
+                        let file = file.to_string_lossy();
+                        if let Err(e) = self.apply_retention(job, &file) {
+                            failed += 1;
+                            print_error(&format!("Pruning the backups of '{}' failed: {}", file, e));
+                            self.log_event(&format!("Pruning the backups of '{}' failed: {}", file, e), LogStatus::Error, Some(&file))?;
+                        }
+                    }
+                    Ok(summary.backed_up.len())
+                })
+            } else {
+                self.backup_file(filename).and_then(|outcome| {
+                    self.apply_retention(job, filename)?;
+                    Ok(usize::from(matches!(outcome, ActionOutcome::Backed { .. })))
+                })
+            };
+            match result {
+                Ok(count) => backed_up += count,
+                Err(e) => {
+                    failed += 1;
+                    print_error(&format!("Scheduled backup of '{}' failed: {}", filename, e));
+                    self.log_event(&format!("Scheduled backup of '{}' failed: {}", filename, e), LogStatus::Error, Some(filename))?;
+                }
+            }
+        }
+        let status = if failed == 0 { LogStatus::Success } else { LogStatus::Error };
+        self.log_event(&format!(
+            "Scheduled backup '{}' of {} finished: {} backed up, {} failed", job.schedule, job.files.join(", "), backed_up, failed
+        ), status, None)?;
+        Ok(backed_up)
+    }
+
+    // The keep count and maximum age of a scheduled entry, applied to the backups of one of its files
+    #[cfg(feature = "chrono")]
+    fn apply_retention(&self, job: &ScheduledBackup, filename: &str) -> Result<()> {
+        if let Some(keep) = job.keep {
+            self.prune_backups(filename, keep)?;
+        }
+        if let Some(max_age) = job.max_age {
+            self.prune_older_than(filename, max_age)?;
+        }
+        Ok(())
+    }
+
+    // Run every scheduled entry whenever it is due, until the process is interrupted
+    // Entries run one at a time, so overlapping schedules wait their turn instead of backing up the
+    // same files concurrently; an entry still busy past its next time runs once when it is done
+    // An entry that fails is reported and logged, and the daemon keeps running the others
+    #[cfg(feature = "chrono")]
+    pub fn run_daemon(&self, jobs: &[ScheduledBackup]) -> Result<std::convert::Infallible> {
+        if jobs.is_empty() {
+            return Err(SafeBackupError::InvalidPath("The daemon has nothing to run; add [[schedule]] entries to the config".to_string()));
+        }
+        let next_due = |job: &ScheduledBackup| job.schedule.next_after(&chrono::Local::now()).ok_or_else(|| {
+            SafeBackupError::InvalidPath(format!("Schedule '{}' is never due", job.schedule))
+        });
+        let mut due = jobs.iter().map(next_due).collect::<Result<Vec<_>>>()?;
+        self.log_action(&format!("Daemon started with {} schedule(s)", jobs.len()))?;
+        self.report(&format!("Running {} schedule(s) until interrupted with Ctrl-C", jobs.len()));
+
+        loop {
+            let (index, when) = due.iter().copied().enumerate().min_by_key(|(_, when)| *when).expect("there is at least one schedule");
+            // Sleeping in short steps keeps the schedule right across clock changes and suspends
+            while let Ok(remaining) = (when - chrono::Local::now()).to_std() {
+                std::thread::sleep(remaining.min(Duration::from_secs(60)));
+            }
+            self.report_verbose(&format!("Schedule '{}' is due", jobs[index].schedule));
+            if let Err(e) = self.run_scheduled(&jobs[index]) {
+                print_error(&format!("Scheduled backup '{}' failed: {}", jobs[index].schedule, e));
+                // The log itself may be what failed, so this entry cannot stop the daemon either
+                let _ = self.log_event(&format!("Scheduled backup '{}' failed: {}", jobs[index].schedule, e), LogStatus::Error, None);
+            }
+            due[index] = next_due(&jobs[index])?;
+        }
+    }
+
+    // Secure file deletion with confirmation
+    pub fn delete_file(&self, filename: &str) -> Result<ActionOutcome> {
+        self.delete_os_file(OsStr::new(filename))
+    }
+
+    // delete_file for a name that need not be UTF-8; the prompt and the log show it lossily
+    pub fn delete_os_file(&self, os_filename: &OsStr) -> Result<ActionOutcome> {
+        let file_path = self.validate_os_path(os_filename)?;
+        let lossy = os_filename.to_string_lossy();
+        let filename = lossy.as_ref();
+
+        // Check if file exists
+        if !file_path.exists() {
+            return Err(SafeBackupError::FileNotFound(format!("File '{}' does not exist", filename)));
+        }
+
+        if !file_path.is_file() {
+            return Err(SafeBackupError::InvalidPath(format!("'{}' is not a regular file", filename)));
+        }
+
+        if self.dry_run {
+            let size = fs::metadata(&file_path).map_err(|e| io_error_at(&file_path, e))?.len();
+            self.report(&format!("[DRY-RUN] Would delete '{}' ({})", filename, format_size(size)));
+            self.log_event(&format!("Performed delete on '{}'", filename), LogStatus::Success, Some(filename))?;
+            return Ok(ActionOutcome::Deleted);
+        }
+
+        // Forced mode skips the prompt but still leaves an audit trail
+        if self.force {
+            fs::remove_file(&file_path).map_err(|e| io_error_at(&file_path, e))?;
+            self.report_success("File deleted successfully.");
+            self.log_event(&format!("Performed forced delete on '{}' (confirmation skipped)", filename), LogStatus::Success, Some(filename))?;
+            return Ok(ActionOutcome::Deleted);
+        }
+
+        // Secure confirmation prompt; a bare Enter keeps the file
+        let Some(input) = self.read_confirmation(&format!("Are you sure you want to delete '{}'? (yes/no): ", filename))? else {
+            println!();
+            self.report_cancelled(&format!("No answer within {} seconds, file deletion cancelled.", self.confirm_timeout.as_secs()));
+            self.log_event(&format!(
+                "Delete confirmation for '{}' timed out after {} seconds, operation cancelled", filename, self.confirm_timeout.as_secs()
+            ), LogStatus::Cancelled, Some(filename))?;
+            return Ok(ActionOutcome::Cancelled);
+        };
+
+        let confirmation = checked_input(&input, Some("no"))?.to_lowercase();
+
+        if confirmation == "yes" {
+            fs::remove_file(&file_path).map_err(|e| io_error_at(&file_path, e))?;
+            self.report_success("File deleted successfully.");
+            self.log_event(&format!("Performed delete on '{}'", filename), LogStatus::Success, Some(filename))?;
+        } else {
+            self.report_cancelled("File deletion cancelled.");
+            self.log_event(&format!("Delete operation cancelled for '{}'", filename), LogStatus::Cancelled, Some(filename))?;
+            return Ok(ActionOutcome::Cancelled);
+        }
+
+        Ok(ActionOutcome::Deleted)
+    }
+
+    // Delete every file matching a glob pattern after one confirmation covering all of them
+    // Backups and their sidecars only match when include_backups is set, and are then removed together
+    // with their sidecars and manifest entry; the log file and manifest are never matched
+    pub fn delete_pattern(&self, pattern: &str, include_backups: bool) -> Result<ActionOutcome> {
+        let protected = [comparable_path(&self.log_file), comparable_path(&self.manifest_path())];
+        let filenames: Vec<String> = self.expand_glob(pattern, include_backups)?.into_iter()
+            .filter(|filename| !protected.contains(&comparable_path(&self.root.join(filename))))
+            .collect();
+        if filenames.is_empty() {
+            return Err(SafeBackupError::FileNotFound(format!("No files match the pattern '{}'", pattern)));
+        }
+
+        if self.dry_run {
+            for filename in &filenames {
+                self.report(&format!("[DRY-RUN] Would delete '{}'", filename));
+            }
+            self.log_event(&format!("Performed delete of {} file(s) matching '{}'", filenames.len(), pattern), LogStatus::Success, None)?;
+            return Ok(ActionOutcome::DeletedMany { paths: filenames.into_iter().map(PathBuf::from).collect() });
+        }
+
+        if self.force {
+            self.log_event(&format!("Deleting {} file(s) matching '{}' (confirmation skipped)", filenames.len(), pattern), LogStatus::Info, None)?;
+        } else {
+            println!("The following {} file(s) match '{}':", filenames.len(), pattern);
+            for filename in &filenames {
+                println!("  {}", filename);
+            }
+            let Some(input) = self.read_confirmation(&format!("Are you sure you want to delete these {} file(s)? (yes/no): ", filenames.len()))? else {
+                println!();
+                self.report_cancelled(&format!("No answer within {} seconds, file deletion cancelled.", self.confirm_timeout.as_secs()));
+                self.log_event(&format!(
+                    "Delete confirmation for '{}' timed out after {} seconds, operation cancelled", pattern, self.confirm_timeout.as_secs()
+                ), LogStatus::Cancelled, None)?;
+                return Ok(ActionOutcome::Cancelled);
+            };
+            if checked_input(&input, Some("no"))?.to_lowercase() != "yes" {
+                self.report_cancelled("File deletion cancelled.");
+                self.log_event(&format!("Delete operation cancelled for {} file(s) matching '{}'", filenames.len(), pattern), LogStatus::Cancelled, None)?;
+                return Ok(ActionOutcome::Cancelled);
+            }
+        }
+
+        let mut succeeded = Vec::new();
+        let mut failures = Vec::new();
+        for filename in filenames {
+            let path = self.root.join(&filename);
+            // A sidecar may already be gone with the backup it belongs to
+            if !path.is_file() {
+                continue;
+            }
+            let removed = if has_backup_extension(&filename, &self.backup_suffix) {
+                self.remove_backup(&path)
+            } else {
+                fs::remove_file(&path).map_err(|e| io_error_at(&path, e))
+            };
+            match removed {
+                Ok(()) => {
+                    self.log_event(&format!("Performed delete on '{}' (pattern '{}')", filename, pattern), LogStatus::Success, Some(&filename))?;
+                    succeeded.push(path);
+                }
+                Err(e) => {
+                    self.log_event(&format!("Delete of '{}' failed: {}", filename, e), LogStatus::Error, Some(&filename))?;
+                    failures.push((filename, e));
+                }
+            }
+        }
+
+        if !failures.is_empty() {
+            return Err(SafeBackupError::BatchFailed { succeeded, failures });
+        }
+        self.report_success(&format!("Deleted {} file(s).", succeeded.len()));
+        Ok(ActionOutcome::DeletedMany { paths: succeeded })
+    }
+
+    // Answer to a yes/no prompt, or None if none arrives within confirm_timeout
+    // Without a terminal, as in CI, stdin may be closed or empty, so the answer has to come from
+    // SAFE_BACKUP_CONFIRM; when that is unset this fails instead of quietly taking the answer as "no"
+    fn read_confirmation(&self, prompt: &str) -> Result<Option<String>> {
+        if !io::stdin().is_terminal() {
+            return match std::env::var(CONFIRM_ENV) {
+                Ok(answer) => Ok(Some(answer)),
+                Err(_) => Err(SafeBackupError::InvalidPath(format!(
+                    "Cannot ask \"{}\" because stdin is not a terminal; set {}=yes (or pass --yes) to confirm",
+                    prompt.split(" (yes/no)").next().unwrap_or(prompt), CONFIRM_ENV
+                ))),
+            };
+        }
+        print!("{}", prompt_with_default(prompt, Some("no")));
+        io::stdout().flush()?;
+        read_line_timeout(self.confirm_timeout)
+    }
+
+    // Secure input handling
+    // A default is shown in the prompt as "[default]" and returned when the user enters an empty line
+    pub fn get_user_input(prompt: &str, default: Option<&str>) -> Result<String> {
+        print!("{}", prompt_with_default(prompt, default));
+        io::stdout().flush()?;
+
+        let stdin = io::stdin();
+        let mut input = String::new();
+        stdin.read_line(&mut input)?;
+        checked_input(&input, default)
+    }
+}
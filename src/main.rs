@@ -1,243 +1,887 @@
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io::{self, Write};
+use std::io;
 use std::path::{Path, PathBuf};
-use chrono::{Utc};
-
-// Custom error types for better error handling
-#[derive(Debug)]
-enum SafeBackupError {
-    InvalidPath(String),
-    FileNotFound(String),
-    IoError(io::Error),
-    #[allow(dead_code)] //Permission not checked in this assessment
-    PermissionDenied(String),
-}
+use std::sync::Arc;
+use serde::Deserialize;
+use safe_backup::{
+    ActionOutcome, Command, DEFAULT_LOG_FILE, DirectoryBackupSummary, HashAlgorithm, LogFormat, LogLevel, LogStatus, Result, S3Storage, SafeBackup, SafeBackupError, SftpTarget, Timestamp,
+    expand_home, parse_size, parse_std_duration, print_cancelled, print_error,
+};
+#[cfg(feature = "chrono")]
+use safe_backup::{CronSchedule, ScheduledBackup, parse_duration, parse_since};
+
+// Main application logic
+// Uses the command and filename from the command line when given, otherwise prompts for them
+fn run(app: &SafeBackup, cli: &CliArgs) -> Result<ActionOutcome> {
+    let (command, filenames) = match cli.command {
+        Some(command) => (command, cli.filenames.clone()),
+        None => {
+            // Get filename with validation
+            let filename = SafeBackup::get_user_input("Please enter your file name: ", None)?;
+
+            // Validate the filename immediately
+            app.validate_path(&filename)?;
+
+            // Get command with validation; an unknown one is reported and logged, not treated as a failure
+            let input = SafeBackup::get_user_input("Please enter your command (backup, restore, delete, list, verify, diff, prune): ", None)?;
+            match Command::try_from(input.as_str()) {
+                Ok(command) => (command, vec![filename]),
+                Err(_) => {
+                    print_cancelled(&format!("Unknown command: '{}'", input));
+                    app.log_action(&format!("Unknown command attempted: '{}'", input))?;
+                    return Ok(ActionOutcome::UnknownCommand { command: input });
+                }
+            }
+        }
+    };
 
-impl From<io::Error> for SafeBackupError {
-    fn from(error: io::Error) -> Self {
-        SafeBackupError::IoError(error)
+    // With --pattern each argument is a glob expanded relative to the current directory
+    // delete takes one pattern and confirms the whole set of matches at once
+    if cli.pattern && command == Command::Delete {
+        return app.delete_pattern(&filenames[0], cli.include_backups);
     }
-}
-
-impl std::fmt::Display for SafeBackupError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            SafeBackupError::InvalidPath(msg) => write!(f, "Invalid path: {}", msg),
-            SafeBackupError::FileNotFound(msg) => write!(f, "File not found: {}", msg),
-            SafeBackupError::IoError(err) => write!(f, "IO error: {}", err),
-            SafeBackupError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+    if cli.pattern {
+        if command != Command::Backup {
+            return Err(SafeBackupError::InvalidPath("--pattern is only supported for backup and delete".to_string()));
+        }
+        let (mut paths, mut bytes) = (Vec::new(), 0);
+        for pattern in &filenames {
+            if let ActionOutcome::BackedMany { paths: matched, bytes: matched_bytes } = app.backup_pattern(pattern)? {
+                paths.extend(matched);
+                bytes += matched_bytes;
+            }
         }
+        return Ok(ActionOutcome::BackedMany { paths, bytes });
     }
-}
 
-type Result<T> = std::result::Result<T, SafeBackupError>;
+    // With --recursive each argument is a directory whose whole tree is backed up
+    if cli.recursive && command.takes_filename() {
+        if command != Command::Backup {
+            return Err(SafeBackupError::InvalidPath("--recursive is only supported for backup".to_string()));
+        }
+        let mut summary = DirectoryBackupSummary::default();
+        for dirname in &filenames {
+            let dir_summary = app.backup_directory(dirname)?;
+            summary.backed_up.extend(dir_summary.backed_up);
+            summary.backed_up_files.extend(dir_summary.backed_up_files);
+            summary.unchanged += dir_summary.unchanged;
+            summary.failed += dir_summary.failed;
+            summary.skipped_symlinks += dir_summary.skipped_symlinks;
+            summary.excluded += dir_summary.excluded;
+            summary.skipped_hidden += dir_summary.skipped_hidden;
+            summary.skipped_size += dir_summary.skipped_size;
+            summary.skipped_cycles += dir_summary.skipped_cycles;
+            summary.bytes += dir_summary.bytes;
+        }
+        return Ok(ActionOutcome::BackedDirectory { summary });
+    }
 
-struct SafeBackup {
-    log_file: PathBuf,
-}
+    // "backup - <name>" stores stdin under the given logical name
+    if command == Command::Backup && filenames[0] == "-" {
+        return match filenames.get(1) {
+            Some(logical_name) if filenames.len() == 2 => app.backup_stdin(logical_name),
+            _ => Err(SafeBackupError::InvalidPath("Reading from stdin needs exactly one logical name: backup - <name>".to_string())),
+        };
+    }
 
-impl SafeBackup {
-    fn new() -> Result<Self> {
-        let log_file = PathBuf::from("logfile.txt");
-        Ok(SafeBackup { log_file })
+    // Several filenames are only accepted for backup, which handles them as a batch
+    if command == Command::Backup && filenames.len() > 1 {
+        return app.backup_files(&filenames);
+    }
+    let filename = filenames.first().map(String::as_str).unwrap_or_default();
+    // Filenames from the command line may not be UTF-8, so the commands on one file get them as given
+    let os_filename = cli.os_filenames.first().map_or(OsStr::new(filename), OsString::as_os_str);
+    if command.takes_filename() {
+        app.validate_os_path(os_filename)?;
     }
 
-    // Secure path validation - prevents path traversal attacks
-    fn validate_path(&self, filename: &str) -> Result<PathBuf> {
-        // Check for empty filename
-        if filename.trim().is_empty() {
-            return Err(SafeBackupError::InvalidPath("Filename cannot be empty".to_string()));
+    // Execute command with proper error handling
+    match command {
+        Command::Backup => {
+            let outcome = app.backup_os_file(os_filename)?;
+            prune_after_backup(app, cli, filename)?;
+            Ok(outcome)
         }
-
-        // Check for path traversal sequences
-        if filename.contains("..") {
-            return Err(SafeBackupError::InvalidPath("Path traversal sequences are not allowed".to_string()));
+        Command::Watch => {
+            let backups = app.watch_file(filename, |_| prune_after_backup(app, cli, filename))?;
+            Ok(ActionOutcome::Watched { backups })
         }
-
-        // Check for invalid characters (Windows and Unix)
-        let invalid_chars = ['<', '>', ':', '"', '|', '?', '*', '\0'];
-        if filename.chars().any(|c| invalid_chars.contains(&c)) {
-            return Err(SafeBackupError::InvalidPath("Filename contains invalid characters".to_string()));
+        // Every version goes into the --to directory, or <filename>.versions by default
+        Command::Restore if cli.all_versions => {
+            let output_dir = cli.destination.clone().unwrap_or_else(|| format!("{}.versions", filename));
+            app.export_versions(filename, &output_dir)
         }
-
-        // Prevent absolute paths
-        let path = Path::new(filename);
-        if path.is_absolute() {
-            return Err(SafeBackupError::InvalidPath("Absolute paths are not allowed".to_string()));
+        Command::Restore if cli.preview => app.restore_with_preview(filename, cli.destination.as_deref()),
+        Command::Restore if cli.interactive => app.restore_interactive(filename, cli.destination.as_deref()),
+        Command::Restore => match &cli.destination {
+            Some(destination) => app.restore_os_file_to(os_filename, OsStr::new(destination)),
+            None => app.restore_os_file(os_filename, None),
+        },
+        Command::Delete => app.delete_os_file(os_filename),
+        Command::List if cli.output_format == OutputFormat::Json => match cli.since {
+            Some(since) => app.list_backups_since(filename, since),
+            None => app.list_backups(filename),
+        }.map(|backups| ActionOutcome::Listed { backups }),
+        Command::List => app.print_backups(filename, cli.since),
+        Command::Verify => app.verify_os_file(os_filename).map(|matches| ActionOutcome::Verified { matches }),
+        Command::Diff => {
+            let diff = app.diff_os_file(os_filename)?;
+            if diff.is_empty() && fs::metadata(app.validate_os_path(os_filename)?)?.len() == 0 {
+                app.report(&format!("'{}' and its latest backup are both empty", filename));
+            } else if diff.is_empty() {
+                app.report(&format!("'{}' is identical to its latest backup", filename));
+            } else if cli.output_format == OutputFormat::Text {
+                print!("{}", diff);
+            }
+            Ok(ActionOutcome::Diffed { diff })
         }
-
-        // Canonicalize the path to resolve any remaining issues
-        let current_dir = std::env::current_dir()?;
-        let full_path = current_dir.join(path);
-        
-        // Ensure the resolved path is still within the current directory
-        if !full_path.starts_with(&current_dir) {
-            return Err(SafeBackupError::InvalidPath("Path escapes current directory".to_string()));
+        // The keep count is prompted for when prune was chosen interactively
+        Command::Prune => {
+            let keep = match cli.keep {
+                Some(keep) => keep,
+                None => {
+                    let value = SafeBackup::get_user_input("How many backups should be kept? ", None)?;
+                    value.parse().map_err(|_| SafeBackupError::InvalidPath(format!("Invalid keep count: '{}'", value)))?
+                }
+            };
+            let removed = app.prune_backups(filename, keep)?;
+            if removed.is_empty() {
+                app.report(&format!("Nothing to prune: '{}' has at most {} backup(s)", filename, keep));
+            }
+            for path in &removed {
+                app.report(&format!("Pruned old backup: {}", path.display()));
+            }
+            Ok(ActionOutcome::Pruned { removed })
+        }
+        // status summarizes every backed-up file and takes no filename
+        Command::Status if cli.output_format == OutputFormat::Json => app.status().map(|files| ActionOutcome::Status { files }),
+        Command::Status => app.print_status(),
+        // usage reports the disk space of the backups, per file and in total
+        Command::Usage if cli.output_format == OutputFormat::Json => {
+            let files = app.usage_by_file()?;
+            app.disk_usage().map(|total| ActionOutcome::Usage { files, total })
+        }
+        Command::Usage => app.print_usage(),
+        // check sweeps every backup and takes no filename either
+        Command::Check if cli.output_format == OutputFormat::Json => app.check_all().map(|problems| ActionOutcome::Checked { problems }),
+        Command::Check => app.print_check(),
+        // verify-log checks the HMAC chain of a log written with --sign-log
+        Command::VerifyLog => app.verify_log().map(|entries| ActionOutcome::LogVerified { entries }),
+        // daemon runs the config's schedules and only returns when one cannot be used
+        #[cfg(feature = "chrono")]
+        Command::Daemon => {
+            let jobs = cli.schedules.iter().map(ScheduleConfig::to_scheduled_backup).collect::<Result<Vec<_>>>()?;
+            match app.run_daemon(&jobs)? {}
+        }
+        #[cfg(not(feature = "chrono"))]
+        Command::Daemon => Err(SafeBackupError::InvalidPath(without_chrono("daemon"))),
+        // migrate converts legacy {name}.bak files found under the base directory
+        Command::Migrate => app.migrate().map(|paths| ActionOutcome::Migrated { paths }),
+        // restore-all takes an optional directory instead of a filename
+        Command::RestoreAll => {
+            let dir = filenames.first().map(Path::new);
+            app.restore_all(dir).map(|summary| ActionOutcome::RestoredAll { summary })
         }
-
-        Ok(PathBuf::from(filename))
-    }
-
-    // Secure logging with proper error handling
-    fn log_action(&self, action: &str) -> Result<()> {
-        let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-        let log_entry = format!("[{}] {}\n", timestamp, action);
-        
-        let mut file = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.log_file)?;
-        
-        file.write_all(log_entry.as_bytes())?;
-        file.flush()?;
-        Ok(())
     }
+}
 
-    // Secure file backup with comprehensive error handling
-    fn backup_file(&self, filename: &str) -> Result<()> {
-        let file_path = self.validate_path(filename)?;
-        
-        // Check if source file exists and is readable
-        if !file_path.exists() {
-            return Err(SafeBackupError::FileNotFound(format!("Source file '{}' does not exist", filename)));
+// Apply --keep and --max-age to the backups of filename after a new one was made
+fn prune_after_backup(app: &SafeBackup, cli: &CliArgs, filename: &str) -> Result<()> {
+    if let Some(keep) = cli.keep {
+        for path in app.prune_backups(filename, keep)? {
+            app.report(&format!("Pruned old backup: {}", path.display()));
         }
-
-        if !file_path.is_file() {
-            return Err(SafeBackupError::InvalidPath(format!("'{}' is not a regular file", filename)));
+    }
+    #[cfg(feature = "chrono")]
+    if let Some(max_age) = cli.max_age {
+        for path in app.prune_older_than(filename, max_age)? {
+            app.report(&format!("Pruned old backup: {}", path.display()));
         }
+    }
+    Ok(())
+}
 
-        let backup_name = format!("{}.bak", filename);
-        let backup_path = self.validate_path(&backup_name)?;
-
-        // Read source file contents securely
-        let contents = fs::read(&file_path)
-            .map_err(|e| SafeBackupError::IoError(e))?;
+// Error for the options and commands that are left out of builds without the chrono feature
+#[cfg(not(feature = "chrono"))]
+fn without_chrono(what: &str) -> String {
+    format!("{} needs safe_backup built with the chrono feature", what)
+}
 
-        // Write backup file atomically
-        fs::write(&backup_path, contents)?;
+const USAGE: &str = "Usage: safe_backup [options] <command> <filename>
+
+Commands:
+  backup    Create a timestamped backup of the file (accepts several filenames;
+            use \"backup - <name>\" to back up stdin under <name>)
+  restore   Restore the file from its most recent backup
+  delete    Delete the file after confirmation
+  list      List the existing backups of the file
+  verify    Check that the file still matches its latest backup
+  diff      Show a unified diff from the latest backup to the current file
+  prune <filename> <keep>  Delete all but the <keep> most recent backups of the file
+  restore-all [dir]  Restore every backed-up file, skipping files newer than their backup unless --yes
+  status    Show versions, newest backup, size and live-file state of every backed-up file
+  usage     Show the disk space taken by the backups of each file and by all backups together
+  watch     Back up the file every time it changes until interrupted with Ctrl-C; rapid saves
+            are debounced into one backup, and --keep and --max-age apply after each backup
+  check     Recompute the SHA-256 of every backup and report mismatched or missing checksums
+            and manifest entries (exits with 6 when any are found)
+  migrate   Turn <name>.bak files from the old single-backup version into timestamped backups
+            dated by their modification time and record them in the manifest
+  verify-log  Check the HMAC chain of a log written with --sign-log and report the first
+            line that was changed (exits with 6); the key is read from SAFE_BACKUP_LOG_KEY
+  daemon    Keep running and back up the [[schedule]] entries of the config (cron, files,
+            recursive, keep, max_age) whenever they are due, until interrupted
+
+Options:
+  --compress  Gzip-compress the backup
+  --compression-level <0-9>  Gzip level for --compress: 1 is fastest, 9 smallest, 0 stores
+                      without compressing (default 6)
+  --encrypt   Encrypt the backup with AES-256-GCM; the passphrase is read from
+              SAFE_BACKUP_PASSPHRASE or prompted for, and restore decrypts automatically
+  --dedup     Store contents once under store/<sha256> in the backup directory, so identical
+              files share one copy; the per-file backups become small .ptr pointer files
+  --read-only-backups  Make new backups read-only so they cannot be edited in place; prune and
+              delete still remove them
+  --content-names  Name new backups <name>.<digest prefix>.bak instead of by time, so backing
+              up contents that already have a backup makes no new copy and marks it as the latest
+  --delta     Keep only the newest backup of a text file in full and store older ones as
+              reverse deltas against it; binary files are always stored in full
+  --yes       Delete without asking for confirmation, and let restore-all overwrite newer files;
+              without a terminal, confirmations are answered by SAFE_BACKUP_CONFIRM=yes/no instead
+  --confirm-timeout <time>  Cancel a delete whose confirmation is not answered in time (default 30s)
+  --debounce <time>   How long watch waits for a changed file to settle before backing it up (default 1s)
+  --dry-run   Report what would happen without changing any files
+  --quiet     Only print errors; the log file is still written
+  --verbose   Print resolved paths, byte counts and the log location to stderr
+  --follow-symlinks   Back up and restore through symlinks whose target stays in the current directory;
+                      --recursive backups walk into linked directories, skipping symlink cycles
+  --log-format <text|json>  Write log entries as text lines (default) or JSON lines
+  --log-level <error|warn|info|debug>  Only log entries at least this severe (default info);
+              debug also logs the details printed by --verbose
+  --output-format <text|json>  Print the command result (and errors, on stderr) as a JSON object
+  --hash <sha256|blake3>  Digest recorded for new backups (default sha256); blake3 is much faster
+              for big backups, and existing backups are checked with the digest they were recorded with
+  --pattern   Treat the filenames given to backup as glob patterns (e.g. \"*.txt\"); delete takes
+              one pattern and asks once before deleting every match
+  --include-backups  Let delete --pattern match backups and their sidecars, which it skips otherwise
+  --recursive Treat the filenames given to backup as directories and back up their whole tree
+  --exclude <pattern> Skip paths matching this glob during --recursive backups (repeatable,
+                      e.g. --exclude \"*.log\" --exclude node_modules --exclude .git)
+  --exclude-larger-than <size>   Skip files larger than size during --recursive backups (e.g. 1GB)
+  --exclude-smaller-than <size>  Skip files smaller than size during --recursive backups (e.g. 1K)
+  --include-hidden    Also back up dotfiles and dot-directories (e.g. .env, .ssh) during --recursive
+                      backups; they are skipped by default
+  --archive <file.zip> Add backups to this zip archive (created if missing) instead of .bak files;
+                      restore, list and verify read from it
+  --backup-dir <dir>  Store backups in this directory instead of next to the file
+  --path-template <template>  Lay out new backups under the backup directory by this template,
+                      using {year} {month} {day} {timestamp} {filename} {name} {ext}
+                      (e.g. \"{year}/{month}/{filename}\"; the .<timestamp>.bak suffix is appended)
+  --backup-suffix <suffix>  End backup names with this instead of .bak (e.g. \"~\" or .backup);
+                      list, restore and prune only see backups made with the same suffix
+  --log-file <path>   Write the log here instead of logfile.txt
+  --sign-log          Add an HMAC-SHA256 to every log line, chained to the line before it, with the
+                      key from SAFE_BACKUP_LOG_KEY, so verify-log can detect edits
+  --jobs <n>          Back up up to n files of a batch at the same time (default 1)
+  --rollback  When a file of a batch backup or migrate fails, remove the backups already written
+              (or move migrated files back) before exiting, so no half-done batch is left
+  --keep <n>          After a backup, delete all but the n most recent backups of the file
+  --max-age <age>     After a backup, delete backups of the file older than age (e.g. 12h, 7d, 2w)
+  --since <when>      Only list backups created since a date or age (e.g. 2024-01-01, 7d)
+  --to <path>         Restore into this file instead of over the original
+  --snapshot          Read files from a volume snapshot so files open in other programs are copied
+                      consistently (reserved for shadow copy support; not available on any platform yet)
+  --no-clobber        Make restore fail instead of overwriting an existing file (--yes overrides)
+  --interactive       Make restore list the backups as a numbered menu and restore the one picked
+  --all-versions      Make restore write every stored version of the file into the --to directory
+                      (default <filename>.versions), each named by its timestamp
+  --preview           Make restore show the size change and changed lines first and ask before
+                      restoring (--yes skips the question)
+  --max-file-size <size>  Refuse to back up files larger than size (e.g. 512K, 100MB, 2GB)
+  --config <path>     Read defaults from this file instead of safe_backup.toml
+  --relative-to <dir> Resolve filenames, the backup directory and safe_backup.toml against dir
+                      instead of the current directory; paths may not leave dir
+  --remote <url>      Keep backups on sftp://user@host[:port]/path instead of storing them locally
+                      (needs the sftp build feature; the host must be in ~/.ssh/known_hosts)
+  --ssh-key <path>    Private key used to authenticate with --remote
+  --s3        Keep backups in the S3-compatible bucket given by SAFE_BACKUP_S3_ENDPOINT (https:// or http://)
+              and SAFE_BACKUP_S3_BUCKET, with AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and AWS_REGION
+  --help      Show this message
+
+Run without a command and filename to be prompted for them.
+
+safe_backup.toml in the working directory may set defaults for backup_dir, keep,
+compress, compression_level, log_file, log_level, quiet, remote, ssh_key, path_template, backup_suffix, exclude (a list of patterns)
+and schedule (the entries run by daemon);
+command-line options take precedence.
+
+Exit codes:
+  0  success
+  1  verify found a difference
+  2  invalid path or arguments
+  3  file not found
+  4  IO error
+  5  permission denied
+  6  integrity check failed (or check found problems)
+  7  some files in a batch failed
+  8  an encrypted backup could not be decrypted (wrong passphrase or corrupt)
+  9  the file is larger than --max-file-size
+  10 restore refused to overwrite an existing file (--no-clobber)
+  11 the file is locked by another program";
+
+// Config file read from the working directory unless --config names another one
+const DEFAULT_CONFIG_FILE: &str = "safe_backup.toml";
+
+// Key for --sign-log and verify-log
+const LOG_KEY_ENV: &str = "SAFE_BACKUP_LOG_KEY";
+
+// How command results and errors are written: human text, or one JSON object per run
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
 
-        println!("Backup created: {}", backup_name);
-        self.log_action(&format!("Performed backup of '{}'", filename))?;
-        Ok(())
+// Report a failure on stderr; in JSON mode as {"error": kind, "message": ..., "exit_code": n}
+fn print_failure(format: OutputFormat, kind: &str, message: &str, exit_code: i32) {
+    match format {
+        OutputFormat::Text => print_error(&format!("Error: {}", message)),
+        OutputFormat::Json => eprintln!("{}", serde_json::json!({ "error": kind, "message": message, "exit_code": exit_code })),
     }
+}
 
-    // Secure file restoration with validation
-    fn restore_file(&self, filename: &str) -> Result<()> {
-        let file_path = self.validate_path(filename)?;
-        let backup_name = format!("{}.bak", filename);
-        let backup_path = self.validate_path(&backup_name)?;
+// Defaults loaded from the config file; command-line flags take precedence over every field
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    backup_dir: Option<String>,
+    keep: Option<usize>,
+    compress: Option<bool>,
+    compression_level: Option<u32>,
+    log_file: Option<PathBuf>,
+    log_level: Option<LogLevel>,
+    quiet: Option<bool>,
+    remote: Option<String>,
+    ssh_key: Option<PathBuf>,
+    exclude: Option<Vec<String>>,
+    path_template: Option<String>,
+    backup_suffix: Option<String>,
+    #[cfg(feature = "chrono")]
+    schedule: Option<Vec<ScheduleConfig>>,
+}
 
-        // Check if backup file exists
-        if !backup_path.exists() {
-            return Err(SafeBackupError::FileNotFound(format!("Backup file '{}' does not exist", backup_name)));
-        }
+// One [[schedule]] entry run by the daemon command
+#[cfg(feature = "chrono")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleConfig {
+    cron: String,
+    files: Vec<String>,
+    #[serde(default)]
+    recursive: bool,
+    keep: Option<usize>,
+    max_age: Option<String>,
+}
 
-        if !backup_path.is_file() {
-            return Err(SafeBackupError::InvalidPath(format!("'{}' is not a regular file", backup_name)));
+#[cfg(feature = "chrono")]
+impl ScheduleConfig {
+    fn to_scheduled_backup(&self) -> Result<ScheduledBackup> {
+        if self.files.is_empty() {
+            return Err(SafeBackupError::InvalidPath(format!("Schedule '{}' has no files to back up", self.cron)));
         }
+        let max_age = match &self.max_age {
+            Some(value) => Some(parse_duration(value).ok_or_else(|| {
+                SafeBackupError::InvalidPath(format!("Invalid max_age '{}' in schedule '{}' (e.g. 12h, 7d, 2w)", value, self.cron))
+            })?),
+            None => None,
+        };
+        Ok(ScheduledBackup {
+            schedule: CronSchedule::parse(&self.cron)?,
+            files: self.files.clone(),
+            recursive: self.recursive,
+            keep: self.keep,
+            max_age,
+        })
+    }
+}
 
-        // Read backup contents and restore
-        let contents = fs::read(&backup_path)?;
-        fs::write(&file_path, contents)?;
-
-        println!("File restored from: {}", backup_name);
-        self.log_action(&format!("Performed restore to '{}'", filename))?;
-        Ok(())
+impl Config {
+    // Load the config at `path`, or safe_backup.toml when none is given
+    // A missing default config yields the built-in defaults; a missing explicit --config is an error
+    // Without --config, safe_backup.toml is looked for in base_dir
+    fn load(path: Option<&Path>, base_dir: &Path) -> std::result::Result<Self, String> {
+        let default_path = base_dir.join(DEFAULT_CONFIG_FILE);
+        let config_path = path.unwrap_or(&default_path);
+        let contents = match fs::read_to_string(config_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && path.is_none() => return Ok(Config::default()),
+            Err(e) => return Err(format!("Cannot read config '{}': {}", config_path.display(), e)),
+        };
+        toml::from_str(&contents).map_err(|e| format!("Invalid config '{}': {}", config_path.display(), e))
     }
+}
 
-    // Secure file deletion with confirmation
-    fn delete_file(&self, filename: &str) -> Result<()> {
-        let file_path = self.validate_path(filename)?;
+// Parsed command-line arguments
+#[derive(Default)]
+struct CliArgs {
+    help: bool,
+    compress: bool,
+    force: bool,
+    dry_run: bool,
+    quiet: bool,
+    verbose: bool,
+    follow_symlinks: bool,
+    encrypt: bool,
+    dedup: bool,
+    delta: bool,
+    no_clobber: bool,
+    snapshot: bool,
+    log_format: Option<LogFormat>,
+    log_level: Option<LogLevel>,
+    output_format: OutputFormat,
+    hash_algorithm: HashAlgorithm,
+    pattern: bool,
+    include_backups: bool,
+    include_hidden: bool,
+    read_only_backups: bool,
+    content_names: bool,
+    rollback: bool,
+    s3: bool,
+    recursive: bool,
+    backup_dir: Option<String>,
+    log_file: Option<PathBuf>,
+    keep: Option<usize>,
+    compression_level: Option<u32>,
+    #[cfg(feature = "chrono")]
+    max_age: Option<chrono::Duration>,
+    max_file_size: Option<u64>,
+    exclude: Vec<String>,
+    exclude_larger_than: Option<u64>,
+    exclude_smaller_than: Option<u64>,
+    confirm_timeout: Option<std::time::Duration>,
+    debounce: Option<std::time::Duration>,
+    jobs: Option<usize>,
+    archive: Option<String>,
+    path_template: Option<String>,
+    backup_suffix: Option<String>,
+    since: Option<Timestamp>,
+    config: Option<PathBuf>,
+    relative_to: Option<PathBuf>,
+    remote: Option<String>,
+    ssh_key: Option<PathBuf>,
+    destination: Option<String>,
+    interactive: bool,
+    preview: bool,
+    all_versions: bool,
+    sign_log: bool,
+    #[cfg(feature = "chrono")]
+    schedules: Vec<ScheduleConfig>,
+    command: Option<Command>,
+    filenames: Vec<String>,
+    // The filenames exactly as given, which need not be UTF-8; filenames holds their lossy text
+    os_filenames: Vec<OsString>,
+}
 
-        // Check if file exists
-        if !file_path.exists() {
-            return Err(SafeBackupError::FileNotFound(format!("File '{}' does not exist", filename)));
+impl CliArgs {
+    // Parse the arguments after the program name; positionals are optional for the interactive flow
+    // Options and commands are matched as text, while positionals are also kept as given
+    fn parse(raw_args: &[OsString]) -> std::result::Result<Self, String> {
+        let mut cli = CliArgs::default();
+        let mut positional = Vec::new();
+
+        let lossy_args: Vec<String> = raw_args.iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        let mut args = lossy_args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => cli.help = true,
+                "--compress" => cli.compress = true,
+                "--yes" => cli.force = true,
+                "--dry-run" => cli.dry_run = true,
+                "--quiet" | "-q" => cli.quiet = true,
+                "--verbose" | "-v" => cli.verbose = true,
+                "--follow-symlinks" => cli.follow_symlinks = true,
+                "--encrypt" => cli.encrypt = true,
+                "--dedup" => cli.dedup = true,
+                "--delta" => cli.delta = true,
+                "--no-clobber" => cli.no_clobber = true,
+                "--snapshot" => cli.snapshot = true,
+                "--log-format" => match Self::value(&mut args, arg)?.as_str() {
+                    "text" => cli.log_format = Some(LogFormat::Text),
+                    "json" => cli.log_format = Some(LogFormat::Json),
+                    other => return Err(format!("Unknown log format: '{}' (expected text or json)", other)),
+                },
+                "--output-format" => match Self::value(&mut args, arg)?.as_str() {
+                    "text" => cli.output_format = OutputFormat::Text,
+                    "json" => cli.output_format = OutputFormat::Json,
+                    other => return Err(format!("Unknown output format: '{}' (expected text or json)", other)),
+                },
+                "--log-level" => match Self::value(&mut args, arg)?.as_str() {
+                    "error" => cli.log_level = Some(LogLevel::Error),
+                    "warn" => cli.log_level = Some(LogLevel::Warn),
+                    "info" => cli.log_level = Some(LogLevel::Info),
+                    "debug" => cli.log_level = Some(LogLevel::Debug),
+                    other => return Err(format!("Unknown log level: '{}' (expected error, warn, info or debug)", other)),
+                },
+                "--hash" => match Self::value(&mut args, arg)?.as_str() {
+                    "sha256" => cli.hash_algorithm = HashAlgorithm::Sha256,
+                    "blake3" => cli.hash_algorithm = HashAlgorithm::Blake3,
+                    other => return Err(format!("Unknown hash algorithm: '{}' (expected sha256 or blake3)", other)),
+                },
+                "--pattern" => cli.pattern = true,
+                "--include-backups" => cli.include_backups = true,
+                "--include-hidden" => cli.include_hidden = true,
+                "--read-only-backups" => cli.read_only_backups = true,
+                "--content-names" => cli.content_names = true,
+                "--rollback" => cli.rollback = true,
+                "--s3" => cli.s3 = true,
+                "--recursive" | "-r" => cli.recursive = true,
+                "--backup-dir" => cli.backup_dir = Some(Self::value(&mut args, arg)?),
+                "--jobs" => {
+                    let value = Self::value(&mut args, arg)?;
+                    let jobs = value.parse().ok().filter(|jobs| *jobs > 0)
+                        .ok_or_else(|| format!("Invalid value for --jobs: '{}' (expected a number above 0)", value))?;
+                    cli.jobs = Some(jobs);
+                }
+                "--keep" => {
+                    let value = Self::value(&mut args, arg)?;
+                    let keep = value.parse().map_err(|_| format!("Invalid value for --keep: '{}'", value))?;
+                    cli.keep = Some(keep);
+                }
+                "--compression-level" => {
+                    let value = Self::value(&mut args, arg)?;
+                    let level = value.parse().map_err(|_| format!("Invalid value for --compression-level: '{}' (expected 0 to 9)", value))?;
+                    cli.compression_level = Some(level);
+                }
+                #[cfg(feature = "chrono")]
+                "--max-age" => {
+                    let value = Self::value(&mut args, arg)?;
+                    let max_age = parse_duration(&value).ok_or_else(|| format!("Invalid value for --max-age: '{}' (e.g. 12h, 7d, 2w)", value))?;
+                    cli.max_age = Some(max_age);
+                }
+                "--max-file-size" => {
+                    let value = Self::value(&mut args, arg)?;
+                    let max_file_size = parse_size(&value).ok_or_else(|| format!("Invalid value for --max-file-size: '{}' (e.g. 512K, 100MB, 2GB)", value))?;
+                    cli.max_file_size = Some(max_file_size);
+                }
+                "--exclude-larger-than" | "--exclude-smaller-than" => {
+                    let value = Self::value(&mut args, arg)?;
+                    let size = parse_size(&value).ok_or_else(|| format!("Invalid value for {}: '{}' (e.g. 512K, 100MB, 2GB)", arg, value))?;
+                    if arg == "--exclude-larger-than" {
+                        cli.exclude_larger_than = Some(size);
+                    } else {
+                        cli.exclude_smaller_than = Some(size);
+                    }
+                }
+                "--confirm-timeout" => {
+                    let value = Self::value(&mut args, arg)?;
+                    let timeout = parse_std_duration(&value)
+                        .ok_or_else(|| format!("Invalid value for --confirm-timeout: '{}' (e.g. 30s, 2m)", value))?;
+                    cli.confirm_timeout = Some(timeout);
+                }
+                "--debounce" => {
+                    let value = Self::value(&mut args, arg)?;
+                    let debounce = parse_std_duration(&value)
+                        .ok_or_else(|| format!("Invalid value for --debounce: '{}' (e.g. 2s, 1m)", value))?;
+                    cli.debounce = Some(debounce);
+                }
+                #[cfg(feature = "chrono")]
+                "--since" => {
+                    let value = Self::value(&mut args, arg)?;
+                    let since = parse_since(&value).ok_or_else(|| format!("Invalid value for --since: '{}' (e.g. 2024-01-01 or 7d)", value))?;
+                    cli.since = Some(since);
+                }
+                #[cfg(not(feature = "chrono"))]
+                "--since" | "--max-age" => return Err(without_chrono(arg)),
+                "--archive" => cli.archive = Some(Self::value(&mut args, arg)?),
+                "--path-template" => cli.path_template = Some(Self::value(&mut args, arg)?),
+                "--backup-suffix" => cli.backup_suffix = Some(Self::value(&mut args, arg)?),
+                "--exclude" => cli.exclude.push(Self::value(&mut args, arg)?),
+                "--log-file" => cli.log_file = Some(PathBuf::from(Self::value(&mut args, arg)?)),
+                "--config" => cli.config = Some(PathBuf::from(Self::value(&mut args, arg)?)),
+                "--relative-to" => cli.relative_to = Some(PathBuf::from(Self::value(&mut args, arg)?)),
+                "--to" => cli.destination = Some(Self::value(&mut args, arg)?),
+                "--interactive" => cli.interactive = true,
+                "--preview" => cli.preview = true,
+                "--all-versions" => cli.all_versions = true,
+                "--sign-log" => cli.sign_log = true,
+                "--remote" => cli.remote = Some(Self::value(&mut args, arg)?),
+                "--ssh-key" => cli.ssh_key = Some(PathBuf::from(Self::value(&mut args, arg)?)),
+                flag if flag.starts_with("--") => return Err(format!("Unknown option: '{}'", flag)),
+                _ => positional.push(raw_args[lossy_args.len() - args.len() - 1].clone()),
+            }
         }
 
-        if !file_path.is_file() {
-            return Err(SafeBackupError::InvalidPath(format!("'{}' is not a regular file", filename)));
-        }
+        if !positional.is_empty() {
+            let name = positional.remove(0).to_string_lossy().into_owned();
+            let command = Command::try_from(name.as_str()).map_err(|_| format!("Unknown command: '{}'", name))?;
 
-        // Secure confirmation prompt
-        print!("Are you sure you want to delete '{}'? (yes/no): ", filename);
-        io::stdout().flush()?;
+            // prune takes the number of backups to keep after the filename, or from --keep
+            if command == Command::Prune && positional.len() == 2 {
+                let value = positional.pop().unwrap_or_default().to_string_lossy().into_owned();
+                let keep = value.parse().map_err(|_| format!("Invalid keep count for prune: '{}' (usage: prune <filename> <keep>)", value))?;
+                cli.keep = Some(keep);
+            }
+            if command == Command::Prune && positional.len() == 1 && cli.keep.is_none() {
+                return Err("prune needs the number of backups to keep: prune <filename> <keep>".to_string());
+            }
 
-        let stdin = io::stdin();
-        let mut input = String::new();
-        stdin.read_line(&mut input)?;
-        
-        let confirmation = input.trim().to_lowercase();
-        
-        if confirmation == "yes" {
-            fs::remove_file(&file_path)?;
-            println!("File deleted successfully.");
-            self.log_action(&format!("Performed delete on '{}'", filename))?;
-        } else {
-            println!("File deletion cancelled.");
-            self.log_action(&format!("Delete operation cancelled for '{}'", filename))?;
+            let no_filename = !command.takes_filename() && command != Command::RestoreAll;
+            if no_filename && !positional.is_empty() {
+                return Err(format!("{} does not take a filename", command));
+            }
+            let expected_single = command != Command::Backup;
+            if (positional.is_empty() && command.takes_filename()) || (expected_single && positional.len() > 1) {
+                return Err("Expected a command followed by a filename".to_string());
+            }
+            cli.command = Some(command);
+            cli.filenames = positional.iter().map(|name| name.to_string_lossy().into_owned()).collect();
+            cli.os_filenames = positional;
         }
 
+        Ok(cli)
+    }
+
+    // Expand a leading ~ in the paths that configure the run; filenames to back up are left alone
+    fn expand_home_paths(&mut self) -> std::result::Result<(), String> {
+        for path in [&mut self.config, &mut self.log_file, &mut self.ssh_key, &mut self.relative_to].into_iter().flatten() {
+            *path = expand_home(path).map_err(|e| e.to_string())?;
+        }
+        if let Some(backup_dir) = &mut self.backup_dir {
+            *backup_dir = expand_home(Path::new(backup_dir)).map_err(|e| e.to_string())?.to_string_lossy().into_owned();
+        }
         Ok(())
     }
 
-    // Secure input handling
-    fn get_user_input(prompt: &str) -> Result<String> {
-        print!("{}", prompt);
-        io::stdout().flush()?;
+    // Check --relative-to, which the builder resolves and validates filenames against; paths of the
+    // invocation's own files given on the command line stay relative to where it was run
+    fn check_base_dir(&mut self) -> std::result::Result<(), String> {
+        let Some(base) = &self.relative_to else { return Ok(()) };
+        let base = fs::canonicalize(base).map_err(|e| format!("Cannot use '{}' as --relative-to: {}", base.display(), e))?;
+        if !base.is_dir() {
+            return Err(format!("--relative-to '{}' is not a directory", base.display()));
+        }
+        let current_dir = std::env::current_dir().map_err(|e| format!("Cannot read the current directory: {}", e))?;
+        for path in [&mut self.config, &mut self.log_file, &mut self.ssh_key].into_iter().flatten() {
+            *path = current_dir.join(&*path);
+        }
+        self.relative_to = Some(base);
+        Ok(())
+    }
 
-        let stdin = io::stdin();
-        let mut input = String::new();
-        stdin.read_line(&mut input)?;
+    // The config file and the default log live in the --relative-to directory, like the files backed up
+    fn base_dir_paths(&mut self) {
+        let Some(base) = &self.relative_to else { return };
+        let log_file = self.log_file.get_or_insert_with(|| PathBuf::from(DEFAULT_LOG_FILE));
+        for path in [Some(log_file), self.ssh_key.as_mut()].into_iter().flatten() {
+            *path = base.join(&*path);
+        }
+    }
 
-        // Trim whitespace and validate input length
-        let trimmed_input = input.trim();
-        if trimmed_input.len() > 255 {
-            return Err(SafeBackupError::InvalidPath("Input too long".to_string()));
+    // Fill in settings not given on the command line from the config file
+    fn apply_config(&mut self, config: Config) {
+        self.backup_dir = self.backup_dir.take().or(config.backup_dir);
+        self.keep = self.keep.or(config.keep);
+        self.compression_level = self.compression_level.or(config.compression_level);
+        self.log_file = self.log_file.take().or(config.log_file);
+        self.log_level = self.log_level.or(config.log_level);
+        self.remote = self.remote.take().or(config.remote);
+        self.ssh_key = self.ssh_key.take().or(config.ssh_key);
+        self.path_template = self.path_template.take().or(config.path_template);
+        self.backup_suffix = self.backup_suffix.take().or(config.backup_suffix);
+        if self.exclude.is_empty() {
+            self.exclude = config.exclude.unwrap_or_default();
         }
+        self.compress |= config.compress.unwrap_or(false);
+        self.quiet |= config.quiet.unwrap_or(false);
+        #[cfg(feature = "chrono")]
+        {
+            self.schedules = config.schedule.unwrap_or_default();
+        }
+    }
 
-        Ok(trimmed_input.to_string())
+    // The value following an option that takes one
+    fn value<'a>(args: &mut impl Iterator<Item = &'a String>, flag: &str) -> std::result::Result<String, String> {
+        args.next().cloned().ok_or_else(|| format!("Option '{}' requires a value", flag))
     }
+}
 
-    // Main application logic
-    fn run(&self) -> Result<()> {
-        // Get filename with validation
-        let filename = Self::get_user_input("Please enter your file name: ")?;
-        
-        // Validate the filename immediately
-        self.validate_path(&filename)?;
+fn main() {
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    let cli = match CliArgs::parse(&args).and_then(|mut cli| {
+        cli.expand_home_paths()?;
+        cli.check_base_dir()?;
+        let base_dir = cli.relative_to.clone().unwrap_or_default();
+        cli.apply_config(Config::load(cli.config.as_deref(), &base_dir)?);
+        cli.expand_home_paths()?;
+        cli.base_dir_paths();
+        Ok(cli)
+    }) {
+        Ok(cli) => cli,
+        Err(msg) => {
+            // The arguments did not parse, so look for the output format directly
+            if args.windows(2).any(|pair| pair[0] == "--output-format" && pair[1] == "json") {
+                print_failure(OutputFormat::Json, "invalid_arguments", &msg, 2);
+            } else {
+                print_error(&format!("Error: {}", msg));
+                eprintln!("{}", USAGE);
+            }
+            if let Ok(log_app) = SafeBackup::new() {
+                let _ = log_app.log_action(&format!("Invalid arguments: {}", msg));
+            }
+            std::process::exit(2);
+        }
+    };
 
-        // Get command with validation
-        let command = Self::get_user_input("Please enter your command (backup, restore, delete): ")?;
+    if cli.help {
+        println!("{}", USAGE);
+        return;
+    }
 
-        // Execute command with proper error handling
-        match command.to_lowercase().as_str() {
-            "backup" => self.backup_file(&filename),
-            "restore" => self.restore_file(&filename),
-            "delete" => self.delete_file(&filename),
+    let mut builder = SafeBackup::builder()
+        .compress(cli.compress)
+        .force(cli.force)
+        .dry_run(cli.dry_run)
+        // JSON output replaces the human messages on stdout
+        .quiet(cli.quiet || cli.output_format == OutputFormat::Json)
+        .verbose(cli.verbose)
+        .follow_symlinks(cli.follow_symlinks)
+        .encrypt(cli.encrypt)
+        .dedup(cli.dedup)
+        .include_hidden(cli.include_hidden)
+        .read_only_backups(cli.read_only_backups)
+        .content_names(cli.content_names)
+        .rollback(cli.rollback)
+        .delta(cli.delta)
+        .no_clobber(cli.no_clobber)
+        .snapshot(cli.snapshot)
+        .hash_algorithm(cli.hash_algorithm);
+    if let Some(base_dir) = &cli.relative_to {
+        builder = builder.base_dir(base_dir);
+    }
+    if let Some(compression_level) = cli.compression_level {
+        builder = builder.compression_level(compression_level);
+    }
+    if let Some(log_file) = &cli.log_file {
+        builder = builder.log_file(log_file);
+    }
+    if let Some(log_format) = cli.log_format {
+        builder = builder.log_format(log_format);
+    }
+    if let Some(log_level) = cli.log_level {
+        builder = builder.log_level(log_level);
+    }
+    if let Some(backup_dir) = &cli.backup_dir {
+        builder = builder.backup_dir(backup_dir);
+    }
+    if !cli.exclude.is_empty() {
+        builder = builder.exclude(cli.exclude.clone());
+    }
+    if let Some(bytes) = cli.exclude_larger_than {
+        builder = builder.exclude_larger_than(bytes);
+    }
+    if let Some(bytes) = cli.exclude_smaller_than {
+        builder = builder.exclude_smaller_than(bytes);
+    }
+    if let Some(archive) = &cli.archive {
+        builder = builder.archive(archive);
+    }
+    if let Some(path_template) = &cli.path_template {
+        builder = builder.path_template(path_template);
+    }
+    if let Some(backup_suffix) = &cli.backup_suffix {
+        builder = builder.backup_suffix(backup_suffix);
+    }
+    if let Some(confirm_timeout) = cli.confirm_timeout {
+        builder = builder.confirm_timeout(confirm_timeout);
+    }
+    if let Some(debounce) = cli.debounce {
+        builder = builder.watch_debounce(debounce);
+    }
+    if let Some(jobs) = cli.jobs {
+        builder = builder.jobs(jobs);
+    }
+    if let Some(max_file_size) = cli.max_file_size {
+        builder = builder.max_file_size(max_file_size);
+    }
+    let verify_log = cli.command == Some(Command::VerifyLog);
+    if cli.sign_log || verify_log {
+        match std::env::var(LOG_KEY_ENV) {
+            Ok(key) if !key.is_empty() => builder = builder.log_key(key),
             _ => {
-                println!("Unknown command: '{}'", command);
-                self.log_action(&format!("Unknown command attempted: '{}'", command))?;
-                Ok(())
+                let e = SafeBackupError::InvalidPath(format!("Signing or verifying the log needs the key in {}", LOG_KEY_ENV));
+                print_failure(cli.output_format, e.kind(), &e.to_string(), e.exit_code());
+                std::process::exit(e.exit_code());
             }
         }
     }
-}
-
-fn main() {
-    match SafeBackup::new() {
+    if cli.s3 {
+        match S3Storage::from_env() {
+            Ok(storage) => builder = builder.storage(storage),
+            Err(e) => {
+                print_failure(cli.output_format, e.kind(), &e.to_string(), e.exit_code());
+                std::process::exit(e.exit_code());
+            }
+        }
+    }
+    if let Some(url) = &cli.remote {
+        let remote = match &cli.ssh_key {
+            Some(ssh_key) => SftpTarget::parse(url, ssh_key),
+            None => Err(SafeBackupError::InvalidPath("--remote needs --ssh-key <path> to authenticate".to_string())),
+        };
+        match remote {
+            Ok(remote) => builder = builder.remote(remote),
+            Err(e) => {
+                print_failure(cli.output_format, e.kind(), &e.to_string(), e.exit_code());
+                std::process::exit(e.exit_code());
+            }
+        }
+    }
+    match builder.build() {
         Ok(app) => {
-            if let Err(e) = app.run() {
-                eprintln!("Error: {}", e);
-                // Log the error if possible
-                if let Ok(log_app) = SafeBackup::new() {
-                    let _ = log_app.log_action(&format!("Error occurred: {}", e));
+            // Ctrl-C removes half-written temp files and logs the abort instead of leaving them behind
+            let app = Arc::new(app);
+            let handler_app = Arc::clone(&app);
+            if let Err(e) = ctrlc::set_handler(move || handler_app.exit_interrupted()) {
+                app.report_verbose(&format!("Could not install the Ctrl-C handler: {}", e));
+            }
+
+            let log_file = fs::canonicalize(app.log_file()).unwrap_or_else(|_| app.log_file().to_path_buf());
+            app.report_verbose(&format!("Logging to {}", log_file.display()));
+            match run(&app, &cli) {
+                Ok(outcome) => {
+                    if cli.output_format == OutputFormat::Json {
+                        match serde_json::to_string(&outcome) {
+                            Ok(json) => println!("{}", json),
+                            Err(e) => print_failure(OutputFormat::Json, "io_error", &e.to_string(), 4),
+                        }
+                    }
+                    // A failed verification is reported through the exit code for scripted audits
+                    if let ActionOutcome::Verified { matches: false } = outcome {
+                        std::process::exit(1);
+                    }
+                    if let ActionOutcome::Checked { problems } = &outcome
+                        && !problems.is_empty()
+                    {
+                        std::process::exit(6);
+                    }
+                }
+                Err(e) => {
+                    print_failure(cli.output_format, e.kind(), &e.to_string(), e.exit_code());
+                    // Log the error if possible
+                    let _ = app.log_event(&format!("Error occurred: {}", e), LogStatus::Error, None);
+                    std::process::exit(e.exit_code());
                 }
-                std::process::exit(1);
             }
         }
         Err(e) => {
-            eprintln!("Failed to initialize application: {}", e);
-            std::process::exit(1);
+            let message = format!("Failed to initialize application: {}", e);
+            match cli.output_format {
+                OutputFormat::Text => print_error(&message),
+                OutputFormat::Json => print_failure(OutputFormat::Json, e.kind(), &message, e.exit_code()),
+            }
+            std::process::exit(e.exit_code());
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,443 @@
+// Backups in an S3-compatible bucket (AWS S3, MinIO, Ceph, ...), one object per backup name
+// Requests are HTTP/1.1 signed with AWS Signature Version 4, over TLS for https:// endpoints.
+// TLS needs the tls feature on Unix; without it only http:// endpoints are accepted
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::timestamp::{self, Timestamp};
+use crate::{Result, SafeBackupError, StorageBackend, StorageEntry, archive_entry_name};
+
+// Environment variables the bucket and credentials are read from
+const ENDPOINT_ENV: &str = "SAFE_BACKUP_S3_ENDPOINT";
+const BUCKET_ENV: &str = "SAFE_BACKUP_S3_BUCKET";
+const REGION_ENV: &str = "AWS_REGION";
+const ACCESS_KEY_ENV: &str = "AWS_ACCESS_KEY_ID";
+const SECRET_KEY_ENV: &str = "AWS_SECRET_ACCESS_KEY";
+const SESSION_TOKEN_ENV: &str = "AWS_SESSION_TOKEN";
+
+// Objects of at least this size are uploaded in parts of it; S3 rejects parts under 5 MiB
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+// How long a request may wait on the endpoint before it fails
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub struct S3Storage {
+    // host[:port] as sent in the Host header, and the address connected to
+    host: String,
+    address: String,
+    // The host name the certificate must be valid for, for https:// endpoints
+    tls_name: Option<String>,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+// The secret key and session token are never printed
+impl std::fmt::Debug for S3Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Storage")
+            .field("host", &self.host)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("access_key", &self.access_key)
+            .finish_non_exhaustive()
+    }
+}
+
+// The connection a request is sent over: a plain socket, or TLS on top of one
+trait Connection: Read + Write {}
+
+impl<T: Read + Write> Connection for T {}
+
+// A response with its status, headers (names lowercased) and body
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+impl S3Storage {
+    // A bucket at an https://host[:port] or http://host[:port] endpoint, addressed path-style as /{bucket}/{key}
+    pub fn new(
+        endpoint: &str,
+        bucket: impl Into<String>,
+        region: impl Into<String>,
+        access_key: impl Into<String>,
+        secret_key: impl Into<String>,
+    ) -> Result<Self> {
+        let invalid = || SafeBackupError::InvalidPath(format!("'{}' is not a valid https://host[:port] S3 endpoint", endpoint));
+        let (host, tls) = match endpoint.strip_prefix("https://") {
+            Some(host) => (host, true),
+            None => (endpoint.strip_prefix("http://").ok_or_else(invalid)?, false),
+        };
+        if tls && !cfg!(all(feature = "tls", unix)) {
+            return Err(SafeBackupError::InvalidPath(format!(
+                "S3 endpoint '{}' needs TLS, which needs safe_backup built with the tls feature on Unix; use an http:// endpoint, such as a local TLS proxy",
+                endpoint
+            )));
+        }
+        let host = host.trim_end_matches('/');
+        if host.is_empty() || host.contains('/') {
+            return Err(invalid());
+        }
+        let (name, address) = match host.rsplit_once(':') {
+            Some((name, port)) if port.parse::<u16>().is_ok() => (name, host.to_string()),
+            _ => (host, format!("{}:{}", host, if tls { 443 } else { 80 })),
+        };
+        let bucket = bucket.into();
+        if bucket.is_empty() || bucket.contains('/') {
+            return Err(SafeBackupError::InvalidPath(format!("'{}' is not a valid S3 bucket name", bucket)));
+        }
+        Ok(S3Storage {
+            host: host.to_string(),
+            address,
+            // An IPv6 address is written in brackets in the URL but not in the certificate
+            tls_name: tls.then(|| name.trim_start_matches('[').trim_end_matches(']').to_string()),
+            bucket,
+            region: region.into(),
+            access_key: access_key.into(),
+            secret_key: secret_key.into(),
+            session_token: None,
+        })
+    }
+
+    // The bucket from SAFE_BACKUP_S3_ENDPOINT and SAFE_BACKUP_S3_BUCKET, with the credentials in
+    // AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and (optionally) AWS_SESSION_TOKEN; AWS_REGION defaults to us-east-1
+    pub fn from_env() -> Result<Self> {
+        let var = |name: &str| std::env::var(name).map_err(|_| SafeBackupError::InvalidPath(format!("{} is not set", name)));
+        let storage = S3Storage::new(
+            &var(ENDPOINT_ENV)?,
+            var(BUCKET_ENV)?,
+            std::env::var(REGION_ENV).unwrap_or_else(|_| "us-east-1".to_string()),
+            var(ACCESS_KEY_ENV)?,
+            var(SECRET_KEY_ENV)?,
+        )?;
+        Ok(match std::env::var(SESSION_TOKEN_ENV) {
+            Ok(token) => storage.session_token(token),
+            Err(_) => storage,
+        })
+    }
+
+    // Temporary credentials also need the session token they were issued with
+    pub fn session_token(mut self, session_token: impl Into<String>) -> Self {
+        self.session_token = Some(session_token.into());
+        self
+    }
+
+    // Object key of a backup name: its components joined with '/', as in the archive
+    fn key(name: &Path) -> String {
+        archive_entry_name(name)
+    }
+
+    // Send one signed request and read the whole response; only failures to talk to the endpoint are errors here
+    fn send(&self, method: &str, key: &str, query: &[(&str, &str)], body: &[u8]) -> io::Result<Response> {
+        let path = match key {
+            "" => format!("/{}", encode(&self.bucket, false)),
+            key => format!("/{}/{}", encode(&self.bucket, false), encode(key, true)),
+        };
+        let mut query: Vec<(String, String)> = query.iter().map(|(name, value)| (encode(name, false), encode(value, false))).collect();
+        query.sort();
+        let query = query.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("&");
+
+        let now = timestamp::now();
+        let mut headers = vec![
+            ("host".to_string(), self.host.clone()),
+            ("x-amz-content-sha256".to_string(), format!("{:x}", Sha256::digest(body))),
+            ("x-amz-date".to_string(), amz_date(&now)),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        let authorization = self.authorization(method, &path, &query, &headers, now);
+
+        let socket = TcpStream::connect(&self.address)
+            .map_err(|e| io::Error::new(e.kind(), format!("Cannot reach the S3 endpoint {}: {}", self.address, e)))?;
+        socket.set_read_timeout(Some(TIMEOUT))?;
+        socket.set_write_timeout(Some(TIMEOUT))?;
+        let mut stream: Box<dyn Connection> = match &self.tls_name {
+            #[cfg(all(feature = "tls", unix))]
+            Some(name) => Box::new(crate::tls::TlsStream::connect(socket, name)
+                .map_err(|e| io::Error::new(e.kind(), format!("TLS connection to the S3 endpoint {} failed: {}", self.address, e)))?),
+            _ => Box::new(socket),
+        };
+        let target = if query.is_empty() { path } else { format!("{}?{}", path, query) };
+        let mut request = format!("{} {} HTTP/1.1\r\n", method, target);
+        for (name, value) in &headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str(&format!("authorization: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n", authorization, body.len()));
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(body)?;
+        stream.flush()?;
+        read_response(&mut BufReader::new(stream), method == "HEAD")
+            .map_err(|e| io::Error::new(e.kind(), format!("Invalid response from the S3 endpoint {}: {}", self.address, e)))
+    }
+
+    // Signature Version 4 Authorization header for a request with these (lowercase, sorted) headers
+    fn authorization(&self, method: &str, path: &str, query: &str, headers: &[(String, String)], now: Timestamp) -> String {
+        let amz_date = amz_date(&now);
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let signed_headers = headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+        let payload_hash = headers.iter().find(|(name, _)| name == "x-amz-content-sha256").map_or("", |(_, value)| value.as_str());
+        let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}", method, path, query, canonical_headers, signed_headers, payload_hash);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            amz_date, scope, Sha256::digest(canonical_request.as_bytes())
+        );
+
+        let mut key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), date.as_bytes());
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part.as_bytes());
+        }
+        let signature: String = hmac(&key, string_to_sign.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", self.access_key, scope, signed_headers, signature)
+    }
+
+    // A request that must succeed, with failures turned into errors saying what was attempted
+    fn request(&self, what: &str, method: &str, key: &str, query: &[(&str, &str)], body: &[u8]) -> io::Result<Response> {
+        let response = self.send(method, key, query, body)?;
+        if (200..300).contains(&response.status) {
+            return Ok(response);
+        }
+        Err(self.failure(what, &response))
+    }
+
+    // The error for a failed request, with the code and message the bucket sent
+    fn failure(&self, what: &str, response: &Response) -> io::Error {
+        let body = String::from_utf8_lossy(&response.body);
+        let reason = match (xml_value(&body, "Code"), xml_value(&body, "Message")) {
+            (Some(code), Some(message)) => format!("{}: {}", code, message),
+            (Some(code), None) => code,
+            _ => format!("HTTP {}", response.status),
+        };
+        let kind = match response.status {
+            404 => io::ErrorKind::NotFound,
+            401 | 403 => io::ErrorKind::PermissionDenied,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, format!("S3 {} in bucket '{}' failed ({})", what, self.bucket, reason))
+    }
+
+    // Objects whose keys start with dir's, only those directly inside it unless nested are wanted too
+    fn list_objects(&self, dir: &Path, nested: bool) -> io::Result<Vec<StorageEntry>> {
+        let prefix = match Self::key(dir) {
+            prefix if prefix.is_empty() => prefix,
+            prefix => format!("{}/", prefix),
+        };
+        let mut entries = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+            if !nested {
+                query.push(("delimiter", "/"));
+            }
+            if let Some(token) = continuation.as_deref() {
+                query.push(("continuation-token", token));
+            }
+            let response = self.request(&format!("listing of '{}'", prefix), "GET", "", &query, &[])?;
+            let body = String::from_utf8_lossy(&response.body).into_owned();
+            for object in xml_blocks(&body, "Contents") {
+                let Some(key) = xml_value(object, "Key") else { continue };
+                entries.push(StorageEntry {
+                    name: PathBuf::from(key),
+                    size: xml_value(object, "Size").and_then(|size| size.parse().ok()).unwrap_or(0),
+                    modified: xml_value(object, "LastModified").and_then(|modified| timestamp::parse_rfc3339(&modified)),
+                });
+            }
+            continuation = xml_value(&body, "NextContinuationToken");
+            if xml_value(&body, "IsTruncated").as_deref() != Some("true") || continuation.is_none() {
+                return Ok(entries);
+            }
+        }
+    }
+
+    // Upload in parts once the contents reach PART_SIZE; a failed upload is aborted so no parts are left behind
+    fn store_multipart(&self, key: &str, first_part: Vec<u8>, reader: &mut dyn Read) -> io::Result<u64> {
+        let created = self.request(&format!("upload of '{}'", key), "POST", key, &[("uploads", "")], &[])?;
+        let upload_id = xml_value(&String::from_utf8_lossy(&created.body), "UploadId")
+            .ok_or_else(|| io::Error::other(format!("S3 upload of '{}' was not given an upload id", key)))?;
+
+        let upload = || -> io::Result<u64> {
+            let mut parts = Vec::new();
+            let mut total = 0;
+            let mut part = first_part;
+            while !part.is_empty() {
+                let number = (parts.len() + 1).to_string();
+                let response = self.request(
+                    &format!("upload of part {} of '{}'", number, key), "PUT", key, &[("partNumber", &number), ("uploadId", &upload_id)], &part,
+                )?;
+                let etag = response.header("etag").unwrap_or_default().to_string();
+                parts.push(format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", number, escape_xml(&etag)));
+                total += part.len() as u64;
+                part = read_part(reader)?;
+            }
+            let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts.concat());
+            let what = format!("upload of '{}'", key);
+            let completed = self.request(&what, "POST", key, &[("uploadId", &upload_id)], body.as_bytes())?;
+            // Completing can still fail after the status line was already sent as 200
+            if String::from_utf8_lossy(&completed.body).contains("<Error>") {
+                return Err(self.failure(&what, &completed));
+            }
+            Ok(total)
+        };
+        upload().inspect_err(|_| {
+            let _ = self.send("DELETE", key, &[("uploadId", &upload_id)], &[]);
+        })
+    }
+}
+
+impl StorageBackend for S3Storage {
+    fn store(&self, name: &Path, reader: &mut dyn Read) -> io::Result<u64> {
+        let key = Self::key(name);
+        let first_part = read_part(reader)?;
+        if first_part.len() < PART_SIZE {
+            self.request(&format!("upload of '{}'", key), "PUT", &key, &[], &first_part)?;
+            return Ok(first_part.len() as u64);
+        }
+        self.store_multipart(&key, first_part, reader)
+    }
+
+    fn load(&self, name: &Path) -> io::Result<Box<dyn Read>> {
+        let key = Self::key(name);
+        let response = self.request(&format!("download of '{}'", key), "GET", &key, &[], &[])?;
+        Ok(Box::new(io::Cursor::new(response.body)))
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+        self.list_objects(dir, false)
+    }
+
+    fn list_all(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+        self.list_objects(dir, true)
+    }
+
+    fn remove(&self, name: &Path) -> io::Result<()> {
+        let key = Self::key(name);
+        self.request(&format!("removal of '{}'", key), "DELETE", &key, &[], &[])?;
+        Ok(())
+    }
+
+    fn exists(&self, name: &Path) -> bool {
+        self.send("HEAD", &Self::key(name), &[], &[]).is_ok_and(|response| response.status == 200)
+    }
+}
+
+// The request time as signed, such as 20240501T133000Z; its first 8 characters are the date of the scope
+fn amz_date(now: &Timestamp) -> String {
+    let p = timestamp::parts(now);
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", p.year, p.month, p.day, p.hour, p.minute, p.second)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Percent-encode everything but RFC 3986 unreserved characters, and '/' too when it separates a key's parts
+fn encode(value: &str, keep_slashes: bool) -> String {
+    value.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+        b'/' if keep_slashes => "/".to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
+
+// Up to PART_SIZE bytes, fewer only at the end of the reader
+fn read_part(reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let mut part = Vec::with_capacity(PART_SIZE);
+    reader.take(PART_SIZE as u64).read_to_end(&mut part)?;
+    Ok(part)
+}
+
+// Status, headers and body of an HTTP/1.1 response, with Content-Length or chunked bodies
+fn read_response(reader: &mut impl BufRead, head: bool) -> io::Result<Response> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line.split_whitespace().nth(1).and_then(|status| status.parse().ok()).ok_or_else(|| invalid("no HTTP status line"))?;
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("connection closed in the headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    let mut response = Response { status, headers, body: Vec::new() };
+    if head || status == 204 || status == 304 {
+        return Ok(response);
+    }
+
+    if response.header("transfer-encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked")) {
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size = usize::from_str_radix(line.trim().split(';').next().unwrap_or_default(), 16).map_err(|_| invalid("bad chunk size"))?;
+            if size == 0 {
+                break;
+            }
+            let start = response.body.len();
+            response.body.resize(start + size, 0);
+            reader.read_exact(&mut response.body[start..])?;
+            line.clear();
+            reader.read_line(&mut line)?;
+        }
+    } else if let Some(length) = response.header("content-length") {
+        let length = length.parse().map_err(|_| invalid("bad Content-Length"))?;
+        response.body.resize(length, 0);
+        reader.read_exact(&mut response.body)?;
+    } else {
+        reader.read_to_end(&mut response.body)?;
+    }
+    Ok(response)
+}
+
+// Inner text of each <tag>...</tag> element, in order
+fn xml_blocks<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut blocks = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let inner = &rest[start + open.len()..];
+        let Some(end) = inner.find(&close) else { break };
+        blocks.push(&inner[..end]);
+        rest = &inner[end + close.len()..];
+    }
+    blocks
+}
+
+// Text of the first <tag> element, with XML escapes decoded
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    xml_blocks(xml, tag).first().map(|value| {
+        value.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+    })
+}
+
+fn escape_xml(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
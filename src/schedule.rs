@@ -0,0 +1,169 @@
+// Cron expressions for the daemon command: minute, hour, day of month, month and day of week,
+// each a *, a number, a range (1-5), a list (1,15) or a step (*/15, 0-30/10), or one of the
+// @hourly, @daily, @weekly, @monthly and @yearly shorthands
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+
+use crate::{Result, SafeBackupError};
+
+// How far ahead next_after looks; a schedule with no time in this span (such as February 30th) never fires
+const SEARCH_YEARS: i32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    expression: String,
+    // Bit n is set when value n matches
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Cron fires on either day field when both are restricted, instead of on both; as in vixie
+    // cron, a field starting with * (such as */2) is not restricted
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(invalid(expression, "expected 5 fields: minute hour day-of-month month day-of-week"));
+        };
+
+        // Sunday is both 0 and 7
+        let mut weekday_bits = parse_field(expression, weekdays, 0, 7)?;
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits = (weekday_bits | 1) & !(1 << 7);
+        }
+        Ok(CronSchedule {
+            expression: expression.trim().to_string(),
+            minutes: parse_field(expression, minutes, 0, 59)?,
+            hours: parse_field(expression, hours, 0, 23)?,
+            days: parse_field(expression, days, 1, 31)?,
+            months: parse_field(expression, months, 1, 12)?,
+            weekdays: weekday_bits,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+
+    // The first matching minute after `after`, or None when there is none within SEARCH_YEARS
+    // Local times skipped by a daylight saving change are not matched
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let timezone = after.timezone();
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let end = NaiveDate::from_ymd_opt(start.year() + SEARCH_YEARS, 1, 1)?.and_hms_opt(0, 0, 0)?;
+
+        let mut time = start;
+        while time < end {
+            // Skip whole months, days and hours that cannot match before trying single minutes
+            if !has(self.months, time.month()) {
+                time = start_of_next_month(time)?;
+            } else if !self.matches_day(time.date()) {
+                time = time.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !has(self.hours, time.hour()) {
+                time = time.with_minute(0)? + Duration::hours(1);
+            } else if !has(self.minutes, time.minute()) {
+                time += Duration::minutes(1);
+            } else if let Some(next) = timezone.from_local_datetime(&time).earliest() {
+                return Some(next);
+            } else {
+                time += Duration::minutes(1);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        if self.days_restricted && self.weekdays_restricted { day || weekday } else { day && weekday }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = SafeBackupError;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        CronSchedule::parse(expression)
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+fn invalid(expression: &str, reason: &str) -> SafeBackupError {
+    SafeBackupError::InvalidPath(format!("Invalid schedule '{}': {}", expression, reason))
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+fn start_of_next_month(time: NaiveDateTime) -> Option<NaiveDateTime> {
+    let (year, month) = if time.month() == 12 { (time.year() + 1, 1) } else { (time.year(), time.month() + 1) };
+    NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)
+}
+
+// One comma-separated field as a bit set of the values between min and max it matches
+fn parse_field(expression: &str, field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().ok().filter(|step| *step > 0)
+                    .ok_or_else(|| invalid(expression, &format!("'{}' has an invalid step", part)))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let value = |text: &str| -> Result<u32> {
+            text.parse().ok().filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| invalid(expression, &format!("'{}' is not a number from {} to {}", text, min, max)))
+        };
+        let (first, last) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((first, last)) => (value(first)?, value(last)?),
+                // A single value with a step runs from it to the end, as in 5/15
+                None if part.contains('/') => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if first > last {
+            return Err(invalid(expression, &format!("'{}' is an empty range", part)));
+        }
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+// Files backed up together whenever schedule is due, with the retention applied afterwards
+#[derive(Debug, Clone)]
+pub struct ScheduledBackup {
+    pub schedule: CronSchedule,
+    pub files: Vec<String>,
+    // Back up each of files as a directory tree, as with --recursive
+    pub recursive: bool,
+    // Like --keep and --max-age, applied to each file after its backup, and to each file of a
+    // directory tree that got a new backup
+    pub keep: Option<usize>,
+    pub max_age: Option<Duration>,
+}
@@ -0,0 +1,263 @@
+// Offsite backup destination reached over SFTP, given as sftp://user@host[:port]/path
+// Connecting and the storage backend need the `sftp` cargo feature; parsing is always available
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::{Result, SafeBackupError};
+
+#[derive(Debug, Clone)]
+pub struct SftpTarget {
+    user: String,
+    host: String,
+    port: u16,
+    dir: PathBuf,
+    #[cfg_attr(not(feature = "sftp"), allow(dead_code))]
+    key_path: PathBuf,
+}
+
+impl SftpTarget {
+    // Parse an sftp:// URL; the path is taken as absolute on the server, and the key is the
+    // private key used for public key authentication
+    pub fn parse(url: &str, key_path: impl Into<PathBuf>) -> Result<Self> {
+        let invalid = || SafeBackupError::InvalidPath(format!("'{}' is not a valid sftp://user@host/path URL", url));
+        let rest = url.strip_prefix("sftp://").ok_or_else(invalid)?;
+        let (authority, dir) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user, host_port) = authority.split_once('@').ok_or_else(invalid)?;
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (host_port, 22),
+        };
+        if user.is_empty() || host.is_empty() {
+            return Err(invalid());
+        }
+
+        let dir = Path::new("/").join(dir);
+        if dir.components().any(|c| matches!(c, Component::ParentDir)) {
+            return Err(SafeBackupError::InvalidPath("Path traversal sequences are not allowed in the remote path".to_string()));
+        }
+
+        Ok(SftpTarget {
+            user: user.to_string(),
+            host: host.to_string(),
+            port,
+            dir,
+            key_path: key_path.into(),
+        })
+    }
+
+    // Where a backup with this relative name is stored on the server, for messages and the log
+    pub fn describe(&self, relative: &Path) -> String {
+        format!("sftp://{}@{}:{}{}", self.user, self.host, self.port, self.dir.join(relative).display())
+    }
+}
+
+#[cfg(feature = "sftp")]
+pub(crate) use session::SftpStorage;
+
+#[cfg(feature = "sftp")]
+mod session {
+    use std::io::{self, Read};
+    use std::net::TcpStream;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use ssh2::{CheckResult, FileStat, KnownHostFileKind, RenameFlags, Session, Sftp};
+
+    use super::SftpTarget;
+    use crate::storage::{StorageBackend, StorageEntry, into_io_error};
+    use crate::{Result, SafeBackupError, normalized_path, timestamp};
+
+    // Connection failures are reported as IoError with the host and the step that failed
+    fn connection_error(target: &SftpTarget, step: &str, error: impl std::fmt::Display) -> SafeBackupError {
+        SafeBackupError::IoError(io::Error::other(format!(
+            "SFTP connection to {}:{} failed while {}: {}", target.host, target.port, step, error
+        )))
+    }
+
+    // An authenticated SFTP session rooted at the target's remote directory
+    pub(crate) struct SftpSession {
+        sftp: Sftp,
+        dir: PathBuf,
+    }
+
+    impl SftpTarget {
+        // Connect and authenticate, refusing servers whose host key is not in ~/.ssh/known_hosts
+        pub(crate) fn connect(&self) -> Result<SftpSession> {
+            let tcp = TcpStream::connect((self.host.as_str(), self.port))
+                .map_err(|e| connection_error(self, "connecting", e))?;
+            let mut session = Session::new().map_err(|e| connection_error(self, "starting the session", e))?;
+            session.set_tcp_stream(tcp);
+            session.handshake().map_err(|e| connection_error(self, "negotiating", e))?;
+
+            self.check_host_key(&session)?;
+
+            session.userauth_pubkey_file(&self.user, None, &self.key_path, None)
+                .map_err(|e| connection_error(self, &format!("authenticating with '{}'", self.key_path.display()), e))?;
+            let sftp = session.sftp().map_err(|e| connection_error(self, "opening SFTP", e))?;
+            Ok(SftpSession { sftp, dir: self.dir.clone() })
+        }
+
+        fn check_host_key(&self, session: &Session) -> Result<()> {
+            let home = crate::home_dir().ok_or_else(|| connection_error(self, "locating known_hosts", "no home directory"))?;
+            let known_hosts_path = home.join(".ssh").join("known_hosts");
+
+            let mut known_hosts = session.known_hosts().map_err(|e| connection_error(self, "reading known_hosts", e))?;
+            known_hosts.read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+                .map_err(|e| connection_error(self, &format!("reading '{}'", known_hosts_path.display()), e))?;
+            let (key, _) = session.host_key()
+                .ok_or_else(|| connection_error(self, "checking the host key", "server sent no host key"))?;
+
+            match known_hosts.check_port(&self.host, self.port, key) {
+                CheckResult::Match => Ok(()),
+                CheckResult::NotFound => Err(connection_error(self, "checking the host key", "host is not in known_hosts")),
+                CheckResult::Mismatch => Err(connection_error(self, "checking the host key", "host key does not match known_hosts")),
+                CheckResult::Failure => Err(connection_error(self, "checking the host key", "known_hosts check failed")),
+            }
+        }
+    }
+
+    impl SftpSession {
+        // Where a name relative to the remote directory is on the server
+        fn remote_path(&self, relative: &Path) -> PathBuf {
+            self.dir.join(normalized_path(relative))
+        }
+
+        // Upload to a path relative to the remote directory, creating parent directories as needed
+        // Data goes to a temp name first and is renamed into place, so partial uploads never look complete
+        pub(crate) fn upload(&self, relative: &Path, contents: &mut dyn Read) -> io::Result<u64> {
+            let path = self.remote_path(relative);
+            if let Some(parent) = path.parent() {
+                self.create_dir_all(parent)?;
+            }
+            let temp = crate::temp_path(&path);
+
+            let mut remote = self.sftp.create(&temp)?;
+            let bytes = io::copy(contents, &mut remote)?;
+            drop(remote);
+            self.sftp.rename(&temp, &path, Some(RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE))?;
+            Ok(bytes)
+        }
+
+        pub(crate) fn open(&self, relative: &Path) -> io::Result<ssh2::File> {
+            Ok(self.sftp.open(self.remote_path(relative))?)
+        }
+
+        // The entries of a directory relative to the remote directory, or none when it does not exist
+        pub(crate) fn read_dir(&self, relative: &Path) -> io::Result<Vec<(PathBuf, FileStat)>> {
+            match self.sftp.readdir(self.remote_path(relative)) {
+                Ok(entries) => Ok(entries),
+                Err(e) => match io::Error::from(e) {
+                    e if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+                    e => Err(e),
+                },
+            }
+        }
+
+        pub(crate) fn stat(&self, relative: &Path) -> io::Result<FileStat> {
+            Ok(self.sftp.stat(&self.remote_path(relative))?)
+        }
+
+        pub(crate) fn remove(&self, relative: &Path) -> io::Result<()> {
+            Ok(self.sftp.unlink(&self.remote_path(relative))?)
+        }
+
+        fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+            let mut missing = Vec::new();
+            for ancestor in dir.ancestors() {
+                if self.sftp.stat(ancestor).is_ok() {
+                    break;
+                }
+                missing.push(ancestor);
+            }
+            for dir in missing.into_iter().rev() {
+                self.sftp.mkdir(dir, 0o755)?;
+            }
+            Ok(())
+        }
+    }
+
+    // Backups kept on the SFTP server, named as they would be under the base directory
+    // The connection is made on first use and shared by every operation after it
+    pub(crate) struct SftpStorage {
+        target: SftpTarget,
+        session: Mutex<Option<Arc<SftpSession>>>,
+    }
+
+    impl SftpStorage {
+        pub(crate) fn new(target: SftpTarget) -> Self {
+            SftpStorage { target, session: Mutex::new(None) }
+        }
+
+        fn session(&self) -> io::Result<Arc<SftpSession>> {
+            let mut session = self.session.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some(session) = &*session {
+                return Ok(Arc::clone(session));
+            }
+            let connected = Arc::new(self.target.connect().map_err(into_io_error)?);
+            *session = Some(Arc::clone(&connected));
+            Ok(connected)
+        }
+
+        // The files directly inside dir, named like dir; subdirectories are added to pending when given
+        fn list_into(&self, dir: &Path, listed: &mut Vec<StorageEntry>, mut pending: Option<&mut Vec<PathBuf>>) -> io::Result<()> {
+            for (path, stat) in self.session()?.read_dir(dir)? {
+                let Some(file_name) = path.file_name() else { continue };
+                // Names in the working directory are kept free of a leading "./"
+                let name = if dir == Path::new(".") { PathBuf::from(file_name) } else { dir.join(file_name) };
+                if stat.is_dir() {
+                    if let Some(pending) = pending.as_deref_mut() {
+                        pending.push(name);
+                    }
+                } else if stat.is_file() {
+                    let modified = stat.mtime.map(|mtime| timestamp::from_system_time(UNIX_EPOCH + Duration::from_secs(mtime)));
+                    listed.push(StorageEntry { name, size: stat.size.unwrap_or(0), modified });
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl std::fmt::Debug for SftpStorage {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("SftpStorage").field("target", &self.target).finish_non_exhaustive()
+        }
+    }
+
+    impl StorageBackend for SftpStorage {
+        fn store(&self, name: &Path, reader: &mut dyn Read) -> io::Result<u64> {
+            self.session()?.upload(name, reader)
+        }
+
+        fn load(&self, name: &Path) -> io::Result<Box<dyn Read>> {
+            Ok(Box::new(self.session()?.open(name)?))
+        }
+
+        fn list(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+            let mut listed = Vec::new();
+            self.list_into(dir, &mut listed, None)?;
+            Ok(listed)
+        }
+
+        fn list_all(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+            let mut listed = Vec::new();
+            let mut pending = vec![dir.to_path_buf()];
+            while let Some(dir) = pending.pop() {
+                self.list_into(&dir, &mut listed, Some(&mut pending))?;
+            }
+            Ok(listed)
+        }
+
+        fn remove(&self, name: &Path) -> io::Result<()> {
+            self.session()?.remove(name)
+        }
+
+        fn exists(&self, name: &Path) -> bool {
+            self.session().and_then(|session| session.stat(name)).is_ok_and(|stat| stat.is_file())
+        }
+
+        fn describe(&self, name: &Path) -> String {
+            self.target.describe(&normalized_path(name))
+        }
+    }
+}
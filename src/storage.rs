@@ -0,0 +1,341 @@
+// Where backups and their sidecars are kept: the local filesystem by default, or a zip archive
+// Names are paths as the rest of the crate uses them, relative to the working directory, such as
+// backups/notes.txt.{timestamp}.bak or, inside an archive, backups.zip/notes.txt.{timestamp}.bak
+
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use zip::write::SimpleFileOptions;
+
+use crate::timestamp::{self, Timestamp};
+use crate::{AtomicFile, FileLock, SafeBackupError, archive_entry_name, normalized_path, remove_backup_file, rename_across_devices};
+
+// Operations backups, restores and listings go through; a new destination only has to implement these
+pub trait StorageBackend: Send + Sync + std::fmt::Debug {
+    // Write name with everything read from reader, replacing any previous contents; returns the bytes written
+    fn store(&self, name: &Path, reader: &mut dyn Read) -> io::Result<u64>;
+
+    // Contents of name; a missing name is a NotFound error
+    fn load(&self, name: &Path) -> io::Result<Box<dyn Read>>;
+
+    // The names directly inside dir, or none when dir does not exist
+    fn list(&self, dir: &Path) -> io::Result<Vec<StorageEntry>>;
+
+    // The names anywhere under dir, for status, usage and restoring everything; backends without
+    // subdirectories only have the names directly inside it
+    fn list_all(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+        self.list(dir)
+    }
+
+    fn remove(&self, name: &Path) -> io::Result<()>;
+
+    fn exists(&self, name: &Path) -> bool {
+        self.load(name).is_ok()
+    }
+
+    // Move a complete, already verified local file into place as name; backends that cannot
+    // take the file over copy it, and the caller removes the staged file afterwards
+    fn store_file(&self, name: &Path, staged: &Path) -> io::Result<u64> {
+        self.store(name, &mut BufReader::new(fs::File::open(staged)?))
+    }
+
+    // The path every backup is named under, for a backend that keeps them in one place such as an
+    // archive; None names them after the backup directory's layout
+    fn root(&self) -> Option<&Path> {
+        None
+    }
+
+    // Where name is kept, as shown in messages and the log
+    fn describe(&self, name: &Path) -> String {
+        name.display().to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageEntry {
+    pub name: PathBuf,
+    pub size: u64,
+    // When the backend records it; content-named backups are ordered by it
+    pub modified: Option<Timestamp>,
+}
+
+// The crate's helpers report SafeBackupError, while backends speak io::Error like Read and Write do
+pub(crate) fn into_io_error(error: SafeBackupError) -> io::Error {
+    match error {
+        SafeBackupError::IoError(e) | SafeBackupError::ContextError { source: e, .. } => e,
+        SafeBackupError::PermissionDenied(message) => io::Error::new(io::ErrorKind::PermissionDenied, message),
+        other => io::Error::other(other.to_string()),
+    }
+}
+
+// Backups as plain files, next to the originals or under the backup directory
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalFs;
+
+impl StorageBackend for LocalFs {
+    fn store(&self, name: &Path, reader: &mut dyn Read) -> io::Result<u64> {
+        let (mut temp, mut file) = AtomicFile::create(name).map_err(into_io_error)?;
+        let bytes = io::copy(reader, &mut file)?;
+        file.sync_all()?;
+        drop(file);
+        temp.persist().map_err(into_io_error)?;
+        Ok(bytes)
+    }
+
+    fn load(&self, name: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(BufReader::new(fs::File::open(name)?)))
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut listed = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            // Temp files of concurrent backups may be renamed away between listing and reading them
+            let metadata = match fs::metadata(entry.path()) {
+                Ok(metadata) if metadata.is_file() => metadata,
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            // Names in the working directory are kept free of a leading "./"
+            let name = if dir == Path::new(".") { PathBuf::from(entry.file_name()) } else { entry.path() };
+            listed.push(StorageEntry { name, size: metadata.len(), modified: metadata.modified().ok().map(timestamp::from_system_time) });
+        }
+        Ok(listed)
+    }
+
+    // Symlinks are not followed, so the walk cannot leave the directory
+    fn list_all(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+        let mut listed = Vec::new();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let name = if dir == Path::new(".") { PathBuf::from(entry.file_name()) } else { entry.path() };
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    pending.push(name);
+                } else if file_type.is_file() {
+                    let metadata = match entry.metadata() {
+                        Ok(metadata) => metadata,
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                        Err(e) => return Err(e),
+                    };
+                    listed.push(StorageEntry { name, size: metadata.len(), modified: metadata.modified().ok().map(timestamp::from_system_time) });
+                }
+            }
+        }
+        Ok(listed)
+    }
+
+    fn remove(&self, name: &Path) -> io::Result<()> {
+        remove_backup_file(name)
+    }
+
+    fn exists(&self, name: &Path) -> bool {
+        name.is_file()
+    }
+
+    // The staged file is already next to name, so it is renamed instead of copied
+    fn store_file(&self, name: &Path, staged: &Path) -> io::Result<u64> {
+        let size = fs::metadata(staged)?.len();
+        rename_across_devices(staged, name)?;
+        Ok(size)
+    }
+}
+
+// Backups as entries of one zip archive, created on first use and appended to afterwards
+// Entries cannot be removed individually, so pruning and deleting archived backups fail
+#[derive(Debug, Clone)]
+pub struct ZipStorage {
+    archive: PathBuf,
+}
+
+impl ZipStorage {
+    pub fn new(archive: impl Into<PathBuf>) -> Self {
+        ZipStorage { archive: archive.into() }
+    }
+
+    pub fn archive(&self) -> &Path {
+        &self.archive
+    }
+
+    // The entry name for a path under the archive, such as notes.txt.{timestamp}.bak
+    pub(crate) fn entry_name(&self, name: &Path) -> Option<String> {
+        let relative = normalized_path(name).strip_prefix(normalized_path(&self.archive)).ok()?.to_path_buf();
+        Some(archive_entry_name(&relative))
+    }
+
+    // Names and sizes of all entries, or none when the archive does not exist yet
+    pub(crate) fn entries(&self) -> io::Result<Vec<(String, u64)>> {
+        let file = match fs::File::open(&self.archive) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut zip = ZipArchive::new(BufReader::new(file))?;
+        let mut entries = Vec::new();
+        for index in 0..zip.len() {
+            let entry = zip.by_index_raw(index)?;
+            entries.push((entry.name()?.into_owned(), entry.size()));
+        }
+        Ok(entries)
+    }
+
+    fn not_in_archive(&self, name: &Path) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, format!("'{}' is not inside the archive '{}'", name.display(), self.archive.display()))
+    }
+}
+
+impl StorageBackend for ZipStorage {
+    // The archive is locked while appending, so concurrent runs add their entries one after another
+    // Contents are already gzipped or encrypted as configured, so entries are stored as is
+    fn store(&self, name: &Path, reader: &mut dyn Read) -> io::Result<u64> {
+        let entry = self.entry_name(name).ok_or_else(|| self.not_in_archive(name))?;
+        let _lock = FileLock::acquire(&self.archive).map_err(into_io_error)?;
+        let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&self.archive)?;
+        let mut writer = if file.metadata()?.len() == 0 {
+            ZipWriter::new(file)
+        } else {
+            ZipWriter::new_append(file)?
+        };
+
+        let mut options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored).large_file(true);
+        let now = timestamp::parts(&timestamp::now());
+        if let Ok(modified) = zip::DateTime::from_date_and_time(
+            now.year as u16, now.month as u8, now.day as u8, now.hour as u8, now.minute as u8, now.second as u8,
+        ) {
+            options = options.last_modified_time(modified);
+        }
+        writer.start_file(entry.as_str(), options)?;
+        let bytes = io::copy(reader, &mut writer)?;
+        writer.finish()?.sync_all()?;
+        Ok(bytes)
+    }
+
+    fn load(&self, name: &Path) -> io::Result<Box<dyn Read>> {
+        let entry = self.entry_name(name).ok_or_else(|| self.not_in_archive(name))?;
+        let mut zip = ZipArchive::new(BufReader::new(fs::File::open(&self.archive)?))?;
+        let mut file = zip.by_name(&entry)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        Ok(Box::new(io::Cursor::new(contents)))
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+        let Some(prefix) = self.entry_name(dir) else { return Ok(Vec::new()) };
+        Ok(self.entries()?.into_iter()
+            .filter(|(entry, _)| entry.rsplit_once('/').map_or("", |(dir, _)| dir) == prefix)
+            .map(|(entry, size)| StorageEntry { name: self.archive.join(entry), size, modified: None })
+            .collect())
+    }
+
+    fn list_all(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+        let Some(prefix) = self.entry_name(dir) else { return Ok(Vec::new()) };
+        Ok(self.entries()?.into_iter()
+            .filter(|(entry, _)| {
+                let dir = entry.rsplit_once('/').map_or("", |(dir, _)| dir);
+                prefix.is_empty() || dir == prefix || dir.starts_with(&format!("{}/", prefix))
+            })
+            .map(|(entry, size)| StorageEntry { name: self.archive.join(entry), size, modified: None })
+            .collect())
+    }
+
+    fn remove(&self, name: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("'{}' is inside a zip archive, whose entries cannot be removed individually", name.display()),
+        ))
+    }
+
+    fn exists(&self, name: &Path) -> bool {
+        let Some(entry) = self.entry_name(name) else { return false };
+        self.entries().is_ok_and(|entries| entries.iter().any(|(known, _)| *known == entry))
+    }
+
+    fn root(&self) -> Option<&Path> {
+        Some(&self.archive)
+    }
+}
+
+// A backend set on the builder, such as S3, with names relative to a base directory other than the
+// working directory, so its keys are the same however the base directory is reached
+#[derive(Debug)]
+pub(crate) struct RelativeNames {
+    root: PathBuf,
+    inner: Arc<dyn StorageBackend>,
+}
+
+impl RelativeNames {
+    // The storage names go through: inner itself when root is empty, as names are already relative
+    pub(crate) fn under(root: &Path, inner: Arc<dyn StorageBackend>) -> Arc<dyn StorageBackend> {
+        if root.as_os_str().is_empty() {
+            inner
+        } else {
+            Arc::new(RelativeNames { root: root.to_path_buf(), inner })
+        }
+    }
+
+    fn relative<'a>(&self, name: &'a Path) -> &'a Path {
+        match name.strip_prefix(&self.root) {
+            Ok(relative) if relative.as_os_str().is_empty() => Path::new("."),
+            Ok(relative) => relative,
+            Err(_) => name,
+        }
+    }
+}
+
+impl StorageBackend for RelativeNames {
+    fn store(&self, name: &Path, reader: &mut dyn Read) -> io::Result<u64> {
+        self.inner.store(self.relative(name), reader)
+    }
+
+    fn load(&self, name: &Path) -> io::Result<Box<dyn Read>> {
+        self.inner.load(self.relative(name))
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+        let mut entries = self.inner.list(self.relative(dir))?;
+        for entry in &mut entries {
+            entry.name = self.root.join(&entry.name);
+        }
+        Ok(entries)
+    }
+
+    fn list_all(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+        let mut entries = self.inner.list_all(self.relative(dir))?;
+        for entry in &mut entries {
+            entry.name = self.root.join(&entry.name);
+        }
+        Ok(entries)
+    }
+
+    fn remove(&self, name: &Path) -> io::Result<()> {
+        self.inner.remove(self.relative(name))
+    }
+
+    fn exists(&self, name: &Path) -> bool {
+        self.inner.exists(self.relative(name))
+    }
+
+    fn store_file(&self, name: &Path, staged: &Path) -> io::Result<u64> {
+        self.inner.store_file(self.relative(name), staged)
+    }
+
+    fn describe(&self, name: &Path) -> String {
+        self.inner.describe(self.relative(name))
+    }
+}
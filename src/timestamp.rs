@@ -0,0 +1,242 @@
+// Points in time as the rest of the crate uses them: chrono's DateTime<Utc> with the chrono feature,
+// and std's SystemTime without it, broken into UTC calendar fields by hand for formatting
+
+use std::time::SystemTime;
+#[cfg(not(feature = "chrono"))]
+use std::time::{Duration, UNIX_EPOCH};
+
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc};
+
+#[cfg(feature = "chrono")]
+pub type Timestamp = DateTime<Utc>;
+#[cfg(not(feature = "chrono"))]
+pub type Timestamp = SystemTime;
+
+// The UTC calendar date and time of a timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Parts {
+    pub(crate) year: i64,
+    pub(crate) month: u32,
+    pub(crate) day: u32,
+    pub(crate) hour: u32,
+    pub(crate) minute: u32,
+    pub(crate) second: u32,
+    pub(crate) nanosecond: u32,
+}
+
+pub(crate) fn now() -> Timestamp {
+    from_system_time(SystemTime::now())
+}
+
+#[cfg(feature = "chrono")]
+pub(crate) fn from_system_time(time: SystemTime) -> Timestamp {
+    DateTime::from(time)
+}
+
+#[cfg(not(feature = "chrono"))]
+pub(crate) fn from_system_time(time: SystemTime) -> Timestamp {
+    time
+}
+
+#[cfg(feature = "chrono")]
+pub(crate) fn to_system_time(timestamp: Timestamp) -> SystemTime {
+    SystemTime::from(timestamp)
+}
+
+#[cfg(not(feature = "chrono"))]
+pub(crate) fn to_system_time(timestamp: Timestamp) -> SystemTime {
+    timestamp
+}
+
+#[cfg(feature = "chrono")]
+pub(crate) fn parts(timestamp: &Timestamp) -> Parts {
+    Parts {
+        year: i64::from(timestamp.year()),
+        month: timestamp.month(),
+        day: timestamp.day(),
+        hour: timestamp.hour(),
+        minute: timestamp.minute(),
+        second: timestamp.second(),
+        // chrono counts a leap second as nanoseconds past 1_000_000_000
+        nanosecond: timestamp.nanosecond() % 1_000_000_000,
+    }
+}
+
+#[cfg(not(feature = "chrono"))]
+pub(crate) fn parts(timestamp: &Timestamp) -> Parts {
+    // Times before 1970 are counted back from the epoch
+    let (seconds, nanosecond) = match timestamp.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(e) => {
+            let before = e.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    };
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let time = seconds.rem_euclid(86_400) as u32;
+    Parts { year, month, day, hour: time / 3600, minute: time / 60 % 60, second: time % 60, nanosecond }
+}
+
+// The timestamp of these fields, or None when they do not name a valid UTC time
+#[cfg(feature = "chrono")]
+pub(crate) fn from_parts(parts: Parts) -> Option<Timestamp> {
+    let date = NaiveDate::from_ymd_opt(i32::try_from(parts.year).ok()?, parts.month, parts.day)?;
+    Some(date.and_hms_nano_opt(parts.hour, parts.minute, parts.second, parts.nanosecond)?.and_utc())
+}
+
+#[cfg(not(feature = "chrono"))]
+pub(crate) fn from_parts(parts: Parts) -> Option<Timestamp> {
+    if parts.day == 0 || parts.day > days_in_month(parts.year, parts.month)? || parts.hour > 23 || parts.minute > 59
+        || parts.second > 59 || parts.nanosecond > 999_999_999
+    {
+        return None;
+    }
+    let seconds = days_from_civil(parts.year, parts.month, parts.day) * 86_400
+        + i64::from(parts.hour * 3600 + parts.minute * 60 + parts.second);
+    let offset = Duration::new(seconds.unsigned_abs(), 0);
+    let time = if seconds >= 0 { UNIX_EPOCH.checked_add(offset)? } else { UNIX_EPOCH.checked_sub(offset)? };
+    time.checked_add(Duration::from_nanos(u64::from(parts.nanosecond)))
+}
+
+#[cfg(not(feature = "chrono"))]
+fn days_in_month(year: i64, month: u32) -> Option<u32> {
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    Some(match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    })
+}
+
+// Year, month and day of a day count since 1970-01-01, and back; Howard Hinnant's algorithms for the
+// proleptic Gregorian calendar, which count in 400-year eras of 146097 days starting on March 1st
+#[cfg(not(feature = "chrono"))]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(not(feature = "chrono"))]
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = i64::from((month + 9) % 12);
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// As in log lines and messages: 2024-05-01 13:30:00 UTC
+pub(crate) fn format_utc(timestamp: &Timestamp) -> String {
+    let p = parts(timestamp);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", p.year, p.month, p.day, p.hour, p.minute, p.second)
+}
+
+// As in backup names, where colons are not allowed: 2024-05-01_13-30-00
+pub(crate) fn format_version(timestamp: &Timestamp) -> String {
+    let p = parts(timestamp);
+    format!("{:04}-{:02}-{:02}_{:02}-{:02}-{:02}", p.year, p.month, p.day, p.hour, p.minute, p.second)
+}
+
+// The timestamp of a backup name's version as written by format_version
+pub(crate) fn parse_version(version: &str) -> Option<Timestamp> {
+    let bytes = version.as_bytes();
+    if bytes.len() != 19 || [4, 7, 13, 16].iter().any(|&i| bytes[i] != b'-') || bytes[10] != b'_' {
+        return None;
+    }
+    let field = |start: usize, len: usize| digits(version.get(start..start + len)?, len);
+    from_parts(Parts {
+        year: i64::from(field(0, 4)?),
+        month: field(5, 2)?,
+        day: field(8, 2)?,
+        hour: field(11, 2)?,
+        minute: field(14, 2)?,
+        second: field(17, 2)?,
+        nanosecond: 0,
+    })
+}
+
+// RFC 3339 with the fraction of a second in groups of three digits, as chrono writes it:
+// 2024-05-01T13:30:00.250+00:00, or with Z as the offset
+#[cfg(not(feature = "chrono"))]
+pub(crate) fn format_rfc3339(timestamp: &Timestamp, offset: &str) -> String {
+    let p = parts(timestamp);
+    let fraction = match p.nanosecond {
+        0 => String::new(),
+        nanos if nanos % 1_000_000 == 0 => format!(".{:03}", nanos / 1_000_000),
+        nanos if nanos % 1000 == 0 => format!(".{:06}", nanos / 1000),
+        nanos => format!(".{:09}", nanos),
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{}",
+        p.year, p.month, p.day, p.hour, p.minute, p.second, fraction, offset
+    )
+}
+
+// Timestamps in JSON output are RFC 3339 strings with either feature
+#[cfg(not(feature = "chrono"))]
+pub(crate) fn serialize<S: serde::Serializer>(timestamp: &Timestamp, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_rfc3339(timestamp, "Z"))
+}
+
+// The UTC time of an RFC 3339 timestamp such as S3's 2024-05-01T13:30:00.000Z
+pub(crate) fn parse_rfc3339(value: &str) -> Option<Timestamp> {
+    let (date, time) = value.split_once(['T', 't'])?;
+    let mut date_fields = date.splitn(3, '-');
+    let (year, month, day) = (date_fields.next()?, date_fields.next()?, date_fields.next()?);
+
+    let (time, offset_seconds) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let sign_at = time.rfind(['+', '-'])?;
+        let (time, offset) = time.split_at(sign_at);
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        let magnitude = i64::from(digits(hours, 2)? * 3600 + digits(minutes, 2)? * 60);
+        (time, if offset.starts_with('-') { -magnitude } else { magnitude })
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time_fields = time.splitn(3, ':');
+    let (hour, minute, second) = (time_fields.next()?, time_fields.next()?, time_fields.next()?);
+    let nanosecond = match fraction {
+        "" => 0,
+        fraction if fraction.len() <= 9 => digits(fraction, fraction.len())? * 10u32.pow(9 - fraction.len() as u32),
+        _ => return None,
+    };
+
+    let local = from_parts(Parts {
+        year: i64::from(digits(year, 4)?),
+        month: digits(month, 2)?,
+        day: digits(day, 2)?,
+        hour: digits(hour, 2)?,
+        minute: digits(minute, 2)?,
+        second: digits(second, 2)?,
+        nanosecond,
+    })?;
+    let offset = std::time::Duration::from_secs(offset_seconds.unsigned_abs());
+    let utc = if offset_seconds >= 0 {
+        to_system_time(local).checked_sub(offset)?
+    } else {
+        to_system_time(local).checked_add(offset)?
+    };
+    Some(from_system_time(utc))
+}
+
+// A number written with exactly len ASCII digits
+fn digits(text: &str, len: usize) -> Option<u32> {
+    if text.len() == len && text.bytes().all(|b| b.is_ascii_digit()) { text.parse().ok() } else { None }
+}
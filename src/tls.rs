@@ -0,0 +1,158 @@
+// TLS client connections for https:// S3 endpoints, through the system's OpenSSL
+// The server's certificate must be trusted by the system (or SSL_CERT_FILE and SSL_CERT_DIR) and
+// valid for the endpoint's host name or IP address; there is no option to skip these checks
+
+use std::ffi::{CStr, CString, c_int, c_long, c_void};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::os::fd::AsRawFd;
+
+use openssl_sys as ffi;
+
+// OpenSSL's BIO_NOCLOSE: the socket stays owned by the TcpStream, which closes it
+const BIO_NOCLOSE: c_int = 0;
+
+pub(crate) struct TlsStream {
+    ssl: *mut ffi::SSL,
+    // The socket the SSL reads and writes through, closed once the SSL is freed
+    _socket: TcpStream,
+}
+
+// The SSL belongs to this stream alone and is only used through &mut self
+unsafe impl Send for TlsStream {}
+
+impl TlsStream {
+    // Negotiate TLS over a connected socket, checking the server's certificate against host
+    pub(crate) fn connect(socket: TcpStream, host: &str) -> io::Result<TlsStream> {
+        let name = CString::new(host)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a valid host name", host)))?;
+        ffi::init();
+        // SAFETY: every pointer is checked before use, and the SSL is owned by the returned stream,
+        // whose Drop frees it; the context and BIO are owned by the SSL once it holds them
+        unsafe {
+            ffi::ERR_clear_error();
+            let context = ffi::SSL_CTX_new(ffi::TLS_client_method());
+            if context.is_null() {
+                return Err(openssl_error("creating the TLS context"));
+            }
+            ffi::SSL_CTX_set_verify(context, ffi::SSL_VERIFY_PEER, None);
+            let ssl = if ffi::SSL_CTX_set_default_verify_paths(context) == 1 { ffi::SSL_new(context) } else { std::ptr::null_mut() };
+            ffi::SSL_CTX_free(context);
+            if ssl.is_null() {
+                return Err(openssl_error("loading the trusted certificates"));
+            }
+            let stream = TlsStream { ssl, _socket: socket };
+
+            let param = ffi::SSL_get0_param(stream.ssl);
+            let checked = match host.parse::<IpAddr>() {
+                Ok(IpAddr::V4(ip)) => ffi::X509_VERIFY_PARAM_set1_ip(param, ip.octets().as_ptr(), 4),
+                Ok(IpAddr::V6(ip)) => ffi::X509_VERIFY_PARAM_set1_ip(param, ip.octets().as_ptr(), 16),
+                // Servers hosting several names pick the certificate by the name sent in the handshake
+                Err(_) if ffi::SSL_set_tlsext_host_name(stream.ssl, name.as_ptr().cast_mut()) == 1 => {
+                    ffi::X509_VERIFY_PARAM_set1_host(param, name.as_ptr(), host.len())
+                }
+                Err(_) => 0,
+            };
+            if checked != 1 {
+                return Err(openssl_error("setting the host name to verify"));
+            }
+
+            let bio = ffi::BIO_new_socket(stream._socket.as_raw_fd(), BIO_NOCLOSE);
+            if bio.is_null() {
+                return Err(openssl_error("attaching the socket"));
+            }
+            ffi::SSL_set_bio(stream.ssl, bio, bio);
+
+            let connected = ffi::SSL_connect(stream.ssl);
+            if connected != 1 {
+                let verified = ffi::SSL_get_verify_result(stream.ssl);
+                if verified != c_long::from(ffi::X509_V_OK) {
+                    let reason = CStr::from_ptr(ffi::X509_verify_cert_error_string(verified)).to_string_lossy();
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the server's certificate was rejected: {}", reason)));
+                }
+                return Err(stream.failure(connected, "negotiating TLS"));
+            }
+            Ok(stream)
+        }
+    }
+
+    // The error of an SSL call that returned ret; the caller has just made that call
+    unsafe fn failure(&self, ret: c_int, what: &str) -> io::Error {
+        // SAFETY: self.ssl is valid for the life of the stream
+        match unsafe { ffi::SSL_get_error(self.ssl, ret) } {
+            ffi::SSL_ERROR_ZERO_RETURN => io::Error::new(io::ErrorKind::UnexpectedEof, format!("connection closed while {}", what)),
+            // Blocking sockets only ask to retry once their timeout has passed
+            ffi::SSL_ERROR_WANT_READ | ffi::SSL_ERROR_WANT_WRITE => io::Error::new(io::ErrorKind::TimedOut, format!("timed out while {}", what)),
+            ffi::SSL_ERROR_SYSCALL if unsafe { ffi::ERR_peek_last_error() } == 0 => match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(0) => io::Error::new(io::ErrorKind::UnexpectedEof, format!("connection closed while {}", what)),
+                e => e,
+            },
+            _ => openssl_error(what),
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = c_int::try_from(buf.len()).unwrap_or(c_int::MAX);
+        // SAFETY: SSL_read writes at most len bytes into buf
+        unsafe {
+            ffi::ERR_clear_error();
+            let read = ffi::SSL_read(self.ssl, buf.as_mut_ptr().cast::<c_void>(), len);
+            if read > 0 {
+                return Ok(read as usize);
+            }
+            // A close_notify from the server ends the stream cleanly
+            if ffi::SSL_get_error(self.ssl, read) == ffi::SSL_ERROR_ZERO_RETURN {
+                return Ok(0);
+            }
+            Err(self.failure(read, "receiving"))
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = c_int::try_from(buf.len()).unwrap_or(c_int::MAX);
+        // SAFETY: SSL_write reads at most len bytes from buf
+        unsafe {
+            ffi::ERR_clear_error();
+            let written = ffi::SSL_write(self.ssl, buf.as_ptr().cast::<c_void>(), len);
+            if written > 0 {
+                return Ok(written as usize);
+            }
+            Err(self.failure(written, "sending"))
+        }
+    }
+
+    // Records go straight to the socket, so nothing is buffered here
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        // SAFETY: the SSL is freed once, together with its BIO; the socket closes afterwards
+        unsafe { ffi::SSL_free(self.ssl) }
+    }
+}
+
+// The oldest error OpenSSL queued on this thread, which the queue is then cleared of
+fn openssl_error(what: &str) -> io::Error {
+    // SAFETY: the error queue is per thread, and reason strings are static
+    let reason = unsafe {
+        let code = ffi::ERR_get_error();
+        let reason = ffi::ERR_reason_error_string(code);
+        ffi::ERR_clear_error();
+        match (code, reason.is_null()) {
+            (0, _) => "no details from OpenSSL".to_string(),
+            (code, true) => format!("OpenSSL error {:#x}", code),
+            (_, false) => CStr::from_ptr(reason).to_string_lossy().into_owned(),
+        }
+    };
+    io::Error::other(format!("{} failed: {}", what, reason))
+}
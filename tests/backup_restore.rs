@@ -0,0 +1,1119 @@
+mod common;
+
+use std::fs;
+
+use safe_backup::{ActionOutcome, HashAlgorithm, LiveState, LogFormat, LogLevel, SafeBackup, SafeBackupBuilder, SafeBackupError, format_size, parse_size};
+#[cfg(feature = "chrono")]
+use safe_backup::parse_since;
+
+fn quiet() -> SafeBackupBuilder {
+    SafeBackup::builder().quiet(true)
+}
+
+#[test]
+fn restores_the_latest_backup() {
+    common::in_temp_dir(|_| {
+        let app = quiet().force(true).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let ActionOutcome::Backed { path, bytes } = app.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert!(path.is_file());
+        assert_eq!(bytes, 8);
+
+        fs::write("notes.txt", "changed").unwrap();
+        let ActionOutcome::Restored { bytes, .. } = app.restore_file("notes.txt", None).unwrap() else {
+            panic!("expected a restore");
+        };
+        assert_eq!(bytes, 8);
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "original");
+    });
+}
+
+#[test]
+fn restores_to_an_alternate_path() {
+    common::in_temp_dir(|_| {
+        let app = quiet().build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        app.backup_file("notes.txt").unwrap();
+
+        fs::write("notes.txt", "changed").unwrap();
+        app.restore_file_to("notes.txt", "notes.restored.txt").unwrap();
+        assert_eq!(fs::read_to_string("notes.restored.txt").unwrap(), "original");
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "changed");
+
+        assert!(matches!(app.restore_file_to("notes.txt", "../escaped.txt"), Err(SafeBackupError::InvalidPath(_))));
+    });
+}
+
+#[test]
+fn backs_up_and_restores_inside_an_injected_base_dir() {
+    common::in_temp_dir(|working_dir| {
+        let base = tempfile::tempdir().unwrap();
+        fs::create_dir(base.path().join("docs")).unwrap();
+        fs::write(base.path().join("docs/notes.txt"), "original").unwrap();
+        let app = quiet().base_dir(base.path()).backup_dir("backups").log_file(base.path().join("log.txt")).build().unwrap();
+
+        let ActionOutcome::Backed { path, .. } = app.backup_file("docs/notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert!(path.is_absolute() && path.is_file());
+        assert_eq!(app.list_backups("docs/notes.txt").unwrap().len(), 1);
+
+        fs::write(base.path().join("docs/notes.txt"), "changed").unwrap();
+        app.restore_file("docs/notes.txt", None).unwrap();
+        assert_eq!(fs::read_to_string(base.path().join("docs/notes.txt")).unwrap(), "original");
+        app.restore_file_to("docs/notes.txt", "docs/copy.txt").unwrap();
+        assert_eq!(fs::read_to_string(base.path().join("docs/copy.txt")).unwrap(), "original");
+        assert_eq!(app.status().unwrap()[0].original.to_str(), Some("docs/notes.txt"));
+        if cfg!(feature = "chrono") {
+            let manifest = fs::read_to_string(base.path().join("backups/manifest.json")).unwrap();
+            assert!(manifest.contains("\"original\": \"docs/notes.txt\""), "{}", manifest);
+        }
+
+        // Nothing is read from or written to the working directory
+        assert!(fs::read_dir(working_dir).unwrap().next().is_none());
+        fs::write("notes.txt", "elsewhere").unwrap();
+        assert!(matches!(app.backup_file("notes.txt"), Err(SafeBackupError::FileNotFound(_))));
+    });
+}
+
+#[test]
+fn skips_unchanged_files() {
+    common::in_temp_dir(|_| {
+        let app = quiet().build().unwrap();
+        fs::write("notes.txt", "same").unwrap();
+        app.backup_file("notes.txt").unwrap();
+        assert!(matches!(app.backup_file("notes.txt").unwrap(), ActionOutcome::Unchanged { .. }));
+        assert_eq!(app.list_backups("notes.txt").unwrap().len(), 1);
+    });
+}
+
+#[test]
+fn backups_within_the_same_second_get_distinct_names() {
+    common::in_temp_dir(|_| {
+        let app = quiet().force(true).build().unwrap();
+        let mut paths = Vec::new();
+        for contents in ["first", "second", "third"] {
+            fs::write("notes.txt", contents).unwrap();
+            let ActionOutcome::Backed { path, .. } = app.backup_file("notes.txt").unwrap() else {
+                panic!("expected a new backup");
+            };
+            paths.push(path);
+        }
+        assert!(paths.iter().all(|path| path.is_file()));
+
+        let backups = app.list_backups("notes.txt").unwrap();
+        assert_eq!(backups.len(), 3);
+        for (backup, contents) in backups.iter().zip(["third", "second", "first"]) {
+            app.restore_file("notes.txt", Some(&backup.version)).unwrap();
+            assert_eq!(fs::read_to_string("notes.txt").unwrap(), contents);
+        }
+        assert!(app.check_all().unwrap().is_empty());
+    });
+}
+
+#[test]
+fn compressed_backups_round_trip() {
+    common::in_temp_dir(|_| {
+        let app = quiet().compress(true).build().unwrap();
+        let contents = "line\n".repeat(1000);
+        fs::write("data.txt", &contents).unwrap();
+        app.backup_file("data.txt").unwrap();
+        assert!(app.verify_file("data.txt").unwrap());
+
+        fs::write("data.txt", "corrupted").unwrap();
+        assert!(!app.verify_file("data.txt").unwrap());
+        app.restore_file("data.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("data.txt").unwrap(), contents);
+    });
+}
+
+#[test]
+fn compression_level_trades_speed_for_size() {
+    common::in_temp_dir(|_| {
+        let contents: String = (0..5000).map(|i| format!("line {} of {}\n", i % 97, i % 13)).collect();
+        fs::write("data.txt", &contents).unwrap();
+        let sizes: Vec<u64> = [0, 9].into_iter().map(|level| {
+            let app = quiet().compress(true).compression_level(level).build().unwrap();
+            let ActionOutcome::Backed { path, .. } = app.backup_file("data.txt").unwrap() else {
+                panic!("expected a new backup");
+            };
+            fs::write("data.txt", "changed").unwrap();
+            app.restore_file("data.txt", None).unwrap();
+            assert_eq!(fs::read_to_string("data.txt").unwrap(), contents);
+            let size = fs::metadata(&path).unwrap().len();
+            fs::remove_file(path).unwrap();
+            size
+        }).collect();
+        assert!(sizes[0] > contents.len() as u64);
+        assert!(sizes[1] < sizes[0] / 4);
+
+        let result = quiet().compress(true).compression_level(10).build();
+        assert!(matches!(result, Err(SafeBackupError::InvalidPath(message)) if message.contains("out of range")));
+    });
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    common::in_temp_dir(|_| {
+        let app = quiet().dry_run(true).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        app.backup_file("notes.txt").unwrap();
+        assert!(app.list_backups("notes.txt").unwrap().is_empty());
+    });
+}
+
+#[test]
+fn builder_rejects_unusable_options() {
+    common::in_temp_dir(|dir| {
+        fs::write("taken", "not a directory").unwrap();
+        assert!(quiet().backup_dir("taken").build().is_err());
+        assert!(quiet().backup_dir("../elsewhere").build().is_err());
+        assert!(quiet().log_file(dir.join("missing/dir/log.txt")).build().is_err());
+        assert!(quiet().dry_run(true).force(true).build().is_ok());
+    });
+}
+
+#[test]
+fn backups_go_to_the_configured_directory() {
+    common::in_temp_dir(|_| {
+        let app = quiet().backup_dir("backups").build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let ActionOutcome::Backed { path, .. } = app.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert!(path.starts_with("backups"));
+    });
+}
+
+#[test]
+fn refuses_to_back_up_a_backup_file() {
+    common::in_temp_dir(|_| {
+        let app = quiet().build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let ActionOutcome::Backed { path, .. } = app.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+
+        let result = app.backup_file(&path.to_string_lossy());
+        assert!(matches!(result, Err(SafeBackupError::InvalidPath(_))));
+        assert_eq!(app.list_backups("notes.txt").unwrap().len(), 1);
+        assert!(app.list_backups(&path.to_string_lossy()).unwrap().is_empty());
+    });
+}
+
+#[test]
+fn backup_log_entries_record_size_and_hash() {
+    common::in_temp_dir(|_| {
+        let app = quiet().log_format(LogFormat::Json).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        app.backup_file("notes.txt").unwrap();
+
+        let log = fs::read_to_string(app.log_file()).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(log.lines().last().unwrap()).unwrap();
+        assert_eq!(entry["size"], 8);
+        assert_eq!(entry["sha256"], "0682c5f2076f099c");
+    });
+}
+
+#[test]
+fn text_log_lines_are_stamped_in_utc() {
+    common::in_temp_dir(|_| {
+        let app = quiet().build().unwrap();
+        app.log_action("Routine entry").unwrap();
+        let line = fs::read_to_string(app.log_file()).unwrap();
+        assert!(line.ends_with(" Routine entry\n"));
+        // Stamped as [2024-05-01 13:30:00 UTC] with or without the chrono feature
+        let stamp = &line[1..24];
+        assert!(line.starts_with('[') && stamp.ends_with(" UTC") && line[24..].starts_with(']'));
+        assert!(stamp.bytes().enumerate().all(|(i, b)| match i {
+            4 | 7 => b == b'-',
+            10 | 19 => b == b' ',
+            13 | 16 => b == b':',
+            20.. => true,
+            _ => b.is_ascii_digit(),
+        }));
+    });
+}
+
+#[test]
+fn log_level_filters_less_severe_entries() {
+    common::in_temp_dir(|_| {
+        let app = quiet().log_format(LogFormat::Json).log_level(LogLevel::Warn).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        app.backup_file("notes.txt").unwrap();
+        app.log_action("Routine entry").unwrap();
+        assert_eq!(fs::read_to_string(app.log_file()).unwrap_or_default(), "");
+
+        fs::write("notes.txt", "changed").unwrap();
+        assert!(!app.verify_file("notes.txt").unwrap());
+        let log = fs::read_to_string(app.log_file()).unwrap();
+        assert_eq!(log.lines().count(), 1);
+        let entry: serde_json::Value = serde_json::from_str(log.lines().next().unwrap()).unwrap();
+        assert_eq!(entry["level"], "error");
+
+        // The default keeps info entries and tags every text line with its level
+        let app = quiet().log_file("default.log").build().unwrap();
+        app.log_action("Routine entry").unwrap();
+        assert!(fs::read_to_string("default.log").unwrap().contains(" INFO Routine entry"));
+    });
+}
+
+#[test]
+fn snapshot_backups_are_refused_until_supported() {
+    common::in_temp_dir(|_| {
+        let app = quiet().snapshot(true).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let error = app.backup_file("notes.txt").unwrap_err();
+        assert!(matches!(&error, SafeBackupError::InvalidPath(message) if message.contains("--snapshot")), "{}", error);
+        assert!(app.list_backups("notes.txt").unwrap().is_empty());
+    });
+}
+
+#[test]
+fn refuses_files_over_the_size_limit() {
+    common::in_temp_dir(|_| {
+        let app = quiet().max_file_size(4).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        assert!(matches!(
+            app.backup_file("notes.txt"),
+            Err(SafeBackupError::FileTooLarge { size: 8, limit: 4 })
+        ));
+        assert!(app.list_backups("notes.txt").unwrap().is_empty());
+    });
+}
+
+#[test]
+fn parses_human_readable_sizes() {
+    assert_eq!(parse_size("4096"), Some(4096));
+    assert_eq!(parse_size("512K"), Some(512 * 1024));
+    assert_eq!(parse_size("100MB"), Some(100 * 1024 * 1024));
+    assert_eq!(parse_size("2gib"), Some(2 * 1024 * 1024 * 1024));
+    assert_eq!(parse_size("MB"), None);
+    assert_eq!(parse_size("10XB"), None);
+}
+
+#[test]
+fn formats_sizes_for_messages() {
+    assert_eq!(format_size(0), "0 bytes");
+    assert_eq!(format_size(1), "1 byte");
+    assert_eq!(format_size(1023), "1023 bytes");
+    assert_eq!(format_size(1536), "1.5 KB");
+    assert_eq!(format_size(100 * 1024 * 1024), "100.0 MB");
+    assert_eq!(format_size(2 * 1024 * 1024 * 1024), "2.0 GB");
+    assert_eq!(format_size(u64::MAX), "16777216.0 TB");
+}
+
+#[test]
+fn status_reports_each_backed_up_file() {
+    common::in_temp_dir(|_| {
+        let app = quiet().backup_dir("backups").build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        fs::write("todo.txt", "todo").unwrap();
+        app.backup_file("notes.txt").unwrap();
+        app.backup_file("todo.txt").unwrap();
+        fs::write("notes.txt", "changed").unwrap();
+        fs::remove_file("todo.txt").unwrap();
+
+        let files = app.status().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].original.to_str(), Some("notes.txt"));
+        assert_eq!(files[0].versions, 1);
+        assert_eq!(files[0].total_size, 8);
+        assert_eq!(files[0].live, LiveState::Differs);
+        assert_eq!(files[1].live, LiveState::Missing);
+    });
+}
+
+#[test]
+fn directory_backups_skip_excluded_paths() {
+    common::in_temp_dir(|_| {
+        let app = quiet().backup_dir("backups").exclude(vec!["*.log".to_string(), "node_modules".to_string()]).build().unwrap();
+        fs::create_dir_all("project/node_modules/dep").unwrap();
+        fs::create_dir_all("project/src").unwrap();
+        fs::write("project/src/main.rs", "fn main() {}").unwrap();
+        fs::write("project/src/debug.log", "noise").unwrap();
+        fs::write("project/node_modules/dep/index.js", "module").unwrap();
+
+        let summary = app.backup_directory("project").unwrap();
+        assert_eq!(summary.backed_up.len(), 1);
+        assert_eq!(summary.excluded, 2);
+        assert!(app.list_backups("project/src/debug.log").unwrap().is_empty());
+    });
+}
+
+#[test]
+fn directory_backups_skip_hidden_entries_unless_included() {
+    common::in_temp_dir(|_| {
+        fs::create_dir_all("project/.ssh").unwrap();
+        fs::write("project/.ssh/id_ed25519", "key").unwrap();
+        fs::write("project/.env", "SECRET=1").unwrap();
+        fs::write("project/main.rs", "fn main() {}").unwrap();
+
+        let summary = quiet().backup_dir("backups").build().unwrap().backup_directory("project").unwrap();
+        assert_eq!((summary.backed_up.len(), summary.skipped_hidden), (1, 2));
+
+        let summary = quiet().backup_dir("backups").include_hidden(true).build().unwrap().backup_directory("project").unwrap();
+        assert_eq!((summary.backed_up.len(), summary.unchanged, summary.skipped_hidden), (2, 1, 0));
+    });
+}
+
+#[test]
+fn directory_backups_skip_files_outside_the_size_limits() {
+    common::in_temp_dir(|_| {
+        fs::create_dir_all("project/media").unwrap();
+        fs::write("project/empty.lock", "").unwrap();
+        fs::write("project/notes.txt", "x".repeat(100)).unwrap();
+        fs::write("project/media/video.mp4", "x".repeat(5000)).unwrap();
+
+        let app = quiet().backup_dir("backups").exclude_smaller_than(1).exclude_larger_than(1000).build().unwrap();
+        let summary = app.backup_directory("project").unwrap();
+        assert_eq!((summary.backed_up.len(), summary.skipped_size), (1, 2));
+        assert_eq!(app.list_backups("project/notes.txt").unwrap().len(), 1);
+        assert!(app.list_backups("project/media/video.mp4").unwrap().is_empty());
+
+        let result = quiet().exclude_smaller_than(2000).exclude_larger_than(1000).build();
+        assert!(matches!(result, Err(SafeBackupError::InvalidPath(_))));
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn directory_backups_following_symlinks_stop_at_cycles() {
+    use std::os::unix::fs::symlink;
+
+    common::in_temp_dir(|dir| {
+        fs::create_dir_all("project/sub").unwrap();
+        fs::create_dir("shared").unwrap();
+        fs::write("project/main.rs", "fn main() {}").unwrap();
+        fs::write("project/sub/lib.rs", "pub fn lib() {}").unwrap();
+        fs::write("shared/common.rs", "pub fn common() {}").unwrap();
+        // sub/loop leads back to project, and escape leaves the working directory
+        symlink("..", "project/sub/loop").unwrap();
+        symlink(dir.join("shared"), "project/shared").unwrap();
+        symlink(dir.parent().unwrap(), "project/escape").unwrap();
+
+        let summary = quiet().backup_dir("backups").build().unwrap().backup_directory("project").unwrap();
+        assert_eq!((summary.backed_up.len(), summary.skipped_symlinks, summary.skipped_cycles), (2, 3, 0));
+
+        let app = quiet().backup_dir("backups").follow_symlinks(true).build().unwrap();
+        let summary = app.backup_directory("project").unwrap();
+        assert_eq!((summary.backed_up.len(), summary.unchanged), (1, 2));
+        assert_eq!((summary.skipped_symlinks, summary.skipped_cycles), (1, 1));
+        assert_eq!(app.list_backups("project/shared/common.rs").unwrap().len(), 1);
+        assert!(fs::read_to_string(app.log_file()).unwrap().contains("symlink cycle, already walked as 'project'"));
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn read_only_backup_directory_reports_permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    common::in_temp_dir(|_| {
+        fs::create_dir("backups").unwrap();
+        fs::set_permissions("backups", fs::Permissions::from_mode(0o555)).unwrap();
+        // Permission bits do not apply to root, so there is nothing to check there
+        if fs::write("backups/probe", "").is_ok() {
+            return;
+        }
+
+        let app = quiet().backup_dir("backups").build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let result = app.backup_file("notes.txt");
+        fs::set_permissions("backups", fs::Permissions::from_mode(0o755)).unwrap();
+
+        let Err(error @ SafeBackupError::PermissionDenied(_)) = result else {
+            panic!("expected PermissionDenied, got {:?}", result);
+        };
+        assert_eq!(error.exit_code(), 5);
+    });
+}
+
+#[test]
+fn outcomes_serialize_with_their_action() {
+    common::in_temp_dir(|_| {
+        let app = quiet().build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let outcome = serde_json::to_value(app.backup_file("notes.txt").unwrap()).unwrap();
+        assert_eq!(outcome["action"], "backup");
+        assert!(outcome["backup_path"].as_str().unwrap().starts_with("notes.txt."));
+
+        let outcome = serde_json::to_value(app.backup_file("notes.txt").unwrap()).unwrap();
+        assert_eq!(outcome["action"], "unchanged");
+    });
+}
+
+#[test]
+fn dedup_stores_identical_contents_once() {
+    common::in_temp_dir(|_| {
+        let app = quiet().force(true).backup_dir("backups").dedup(true).build().unwrap();
+        fs::write("a.txt", "same contents").unwrap();
+        fs::write("b.txt", "same contents").unwrap();
+        app.backup_file("a.txt").unwrap();
+        app.backup_file("b.txt").unwrap();
+        assert_eq!(fs::read_dir("backups/store").unwrap().filter(|e| {
+            !e.as_ref().unwrap().file_name().to_string_lossy().starts_with('.')
+        }).count(), 2, "one blob and its reference count");
+
+        // Pruning one pointer keeps the blob the other still refers to
+        app.prune_backups("a.txt", 0).unwrap();
+        fs::write("b.txt", "edited").unwrap();
+        app.restore_file("b.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("b.txt").unwrap(), "same contents");
+
+        app.prune_backups("b.txt", 0).unwrap();
+        assert!(fs::read_dir("backups/store").unwrap().all(|e| e.unwrap().file_name().to_string_lossy().starts_with('.')));
+    });
+}
+
+#[test]
+fn backups_accumulate_in_a_zip_archive() {
+    common::in_temp_dir(|_| {
+        let app = quiet().force(true).archive("backups.zip").build().unwrap();
+        fs::create_dir("docs").unwrap();
+        fs::write("docs/notes.txt", "first").unwrap();
+        fs::write("todo.txt", "todo").unwrap();
+        app.backup_file("docs/notes.txt").unwrap();
+        app.backup_file("todo.txt").unwrap();
+        assert!(fs::metadata("backups.zip").unwrap().is_file());
+        assert!(!fs::read_dir("docs").unwrap().any(|e| e.unwrap().file_name().to_string_lossy().contains(".bak")));
+
+        let backups = app.list_backups("docs/notes.txt").unwrap();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].path.starts_with("backups.zip/docs"));
+
+        fs::write("docs/notes.txt", "edited").unwrap();
+        assert!(!app.verify_file("docs/notes.txt").unwrap());
+        app.restore_file("docs/notes.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("docs/notes.txt").unwrap(), "first");
+        assert_eq!(app.status().unwrap().len(), 2);
+
+        // Usage and restoring everything read the archive's entries like any other storage
+        let usage = app.usage_by_file().unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage.iter().map(|file| file.bytes).sum::<u64>(), app.disk_usage().unwrap());
+        fs::remove_file("todo.txt").unwrap();
+        assert_eq!(app.restore_all(None).unwrap().restored.len(), 2);
+        assert_eq!(fs::read_to_string("todo.txt").unwrap(), "todo");
+
+        // Archive entries cannot be removed one by one, which is reported as an invalid request
+        assert!(matches!(app.prune_backups("todo.txt", 0), Err(SafeBackupError::InvalidPath(_))));
+    });
+}
+
+#[test]
+#[cfg(feature = "chrono")]
+fn lists_only_backups_since_a_point_in_time() {
+    common::in_temp_dir(|_| {
+        let app = quiet().build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        app.backup_file("notes.txt").unwrap();
+
+        assert_eq!(app.list_backups_since("notes.txt", parse_since("1h").unwrap()).unwrap().len(), 1);
+        assert!(app.list_backups_since("notes.txt", parse_since("2999-01-01").unwrap()).unwrap().is_empty());
+        assert!(parse_since("2024-01-01T12:00:00Z").is_some());
+        assert!(parse_since("yesterday").is_none());
+    });
+}
+
+#[test]
+fn check_reports_corrupted_and_unverifiable_backups() {
+    common::in_temp_dir(|_| {
+        let app = quiet().backup_dir("backups").build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        fs::write("todo.txt", "todo").unwrap();
+        let ActionOutcome::Backed { path: notes, .. } = app.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        let ActionOutcome::Backed { path: todo, .. } = app.backup_file("todo.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert!(app.check_all().unwrap().is_empty());
+
+        fs::write(&notes, "tampered").unwrap();
+        let mut sidecar = todo.into_os_string();
+        sidecar.push(".sha256");
+        fs::remove_file(sidecar).unwrap();
+
+        let problems = app.check_all().unwrap();
+        // The manifest's digest of notes.txt is checked too, and there is no manifest without chrono
+        assert_eq!(problems.len(), if cfg!(feature = "chrono") { 3 } else { 2 }, "{:?}", problems);
+        assert!(problems.iter().filter(|p| p.path == notes).all(|p| p.reason.starts_with("checksum mismatch")));
+        assert!(problems.iter().any(|p| p.reason == "checksum sidecar is missing"));
+    });
+}
+
+#[test]
+fn delta_backups_reconstruct_older_versions_exactly() {
+    common::in_temp_dir(|_| {
+        let app = quiet().backup_dir("backups").delta(true).build().unwrap();
+        let original: String = (0..200).map(|i| format!("line {}\n", i)).collect();
+        let versions = [
+            original.clone(),
+            original.replace("line 100\n", "line 100, edited\n"),
+            original.replace("line 50\n", "") + "no trailing newline",
+        ];
+        for contents in &versions {
+            fs::write("notes.txt", contents).unwrap();
+            app.backup_file("notes.txt").unwrap();
+        }
+
+        let backups = app.list_backups("notes.txt").unwrap();
+        assert_eq!(backups.len(), 3);
+        assert!(!backups[0].path.to_string_lossy().ends_with(".delta"));
+        assert!(backups[1..].iter().all(|b| b.path.to_string_lossy().ends_with(".bak.delta")));
+        let problems = app.check_all().unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+
+        for (backup, contents) in backups.iter().zip(versions.iter().rev()) {
+            app.restore_file("notes.txt", Some(&backup.version)).unwrap();
+            assert_eq!(&fs::read_to_string("notes.txt").unwrap(), contents);
+        }
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn dedup_store_on_another_filesystem_falls_back_to_copying() {
+    use std::os::unix::fs::MetadataExt;
+
+    common::in_temp_dir(|dir| {
+        // /dev/shm is usually a separate tmpfs; without one there is no second filesystem to move to
+        let Ok(other) = tempfile::tempdir_in("/dev/shm") else { return };
+        if fs::metadata(other.path()).unwrap().dev() == fs::metadata(dir).unwrap().dev() {
+            return;
+        }
+        fs::create_dir("backups").unwrap();
+        std::os::unix::fs::symlink(other.path(), "backups/store").unwrap();
+
+        let app = quiet().backup_dir("backups").dedup(true).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        app.backup_file("notes.txt").unwrap();
+        let blob = fs::read_dir(other.path()).unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|path| path.extension().is_none() && !path.file_name().unwrap().to_string_lossy().starts_with('.'))
+            .expect("the blob was moved into the store");
+        assert_eq!(fs::read_to_string(blob).unwrap(), "original");
+        assert!(!fs::read_dir("backups").unwrap().any(|e| e.unwrap().file_name().to_string_lossy().ends_with(".tmp")));
+    });
+}
+
+#[test]
+fn no_clobber_restores_refuse_to_overwrite_unless_forced() {
+    common::in_temp_dir(|_| {
+        fs::write("notes.txt", "original").unwrap();
+        quiet().build().unwrap().backup_file("notes.txt").unwrap();
+        fs::write("notes.txt", "live edits").unwrap();
+
+        let result = quiet().no_clobber(true).build().unwrap().restore_file("notes.txt", None);
+        let Err(error @ SafeBackupError::DestinationExists(_)) = result else {
+            panic!("expected DestinationExists, got {:?}", result);
+        };
+        assert_eq!(error.exit_code(), 10);
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "live edits");
+
+        let app = quiet().no_clobber(true).build().unwrap();
+        app.restore_file_to("notes.txt", "notes.restored.txt").unwrap();
+        assert_eq!(fs::read_to_string("notes.restored.txt").unwrap(), "original");
+
+        quiet().no_clobber(true).force(true).build().unwrap().restore_file("notes.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "original");
+    });
+}
+
+#[test]
+fn backups_hashed_with_either_algorithm_verify_side_by_side() {
+    common::in_temp_dir(|_| {
+        let sha256 = quiet().backup_dir("backups").build().unwrap();
+        let blake3 = quiet().backup_dir("backups").hash_algorithm(HashAlgorithm::Blake3).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        sha256.backup_file("notes.txt").unwrap();
+        assert!(matches!(blake3.backup_file("notes.txt").unwrap(), ActionOutcome::Unchanged { .. }));
+
+        fs::write("notes.txt", "changed").unwrap();
+        let ActionOutcome::Backed { path, .. } = blake3.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        let mut sidecar = path.into_os_string();
+        sidecar.push(".sha256");
+        assert!(fs::read_to_string(sidecar).unwrap().starts_with("blake3:"));
+
+        for app in [&sha256, &blake3] {
+            assert!(app.check_all().unwrap().is_empty());
+            assert!(app.verify_file("notes.txt").unwrap());
+            assert_eq!(app.status().unwrap()[0].live, LiveState::Matches);
+        }
+        let oldest = sha256.list_backups("notes.txt").unwrap().pop().unwrap();
+        blake3.restore_file("notes.txt", Some(&oldest.version)).unwrap();
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "original");
+    });
+}
+
+#[test]
+fn watch_backs_up_a_burst_of_saves_once() {
+    common::in_temp_dir(|_| {
+        let app = quiet().backup_dir("backups").watch_debounce(std::time::Duration::from_millis(300)).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+
+        std::thread::scope(|scope| {
+            // Failing the after-backup hook is the only way to end the watch from inside the process
+            let watch = scope.spawn(|| app.watch_file("notes.txt", |path| Err(SafeBackupError::InvalidPath(path.display().to_string()))));
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            for i in 0..5 {
+                fs::write("notes.txt", format!("edit {}", i)).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+            assert!(matches!(watch.join().unwrap(), Err(SafeBackupError::InvalidPath(_))));
+        });
+
+        let backups = app.list_backups("notes.txt").unwrap();
+        assert_eq!(backups.len(), 1);
+        fs::write("notes.txt", "changed").unwrap();
+        app.restore_file("notes.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "edit 4");
+    });
+}
+
+#[test]
+#[cfg(feature = "chrono")]
+fn path_templates_lay_out_backups_by_date() {
+    common::in_temp_dir(|_| {
+        let app = quiet().backup_dir("backups").path_template("{year}/{month}/{name}-{ext}/{filename}").build().unwrap();
+        fs::create_dir("docs").unwrap();
+        fs::write("docs/notes.txt", "original").unwrap();
+        let ActionOutcome::Backed { path, .. } = app.backup_file("docs/notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        let now = chrono::Utc::now();
+        assert!(path.starts_with(format!("backups/{}/{}/notes-txt", now.format("%Y"), now.format("%m"))));
+        assert!(path.file_name().unwrap().to_string_lossy().starts_with("notes.txt."));
+
+        assert!(matches!(app.backup_file("docs/notes.txt").unwrap(), ActionOutcome::Unchanged { .. }));
+        fs::write("docs/notes.txt", "changed").unwrap();
+        app.restore_file("docs/notes.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("docs/notes.txt").unwrap(), "original");
+        assert_eq!(app.status().unwrap()[0].original.to_str(), Some("docs/notes.txt"));
+
+        let escaping = quiet().backup_dir("backups").path_template("../{filename}").build().unwrap();
+        assert!(matches!(escaping.backup_file("docs/notes.txt"), Err(SafeBackupError::InvalidPath(_))));
+        assert!(quiet().path_template("{year}/{hour}/{filename}").build().is_err());
+    });
+}
+
+#[test]
+#[cfg(not(feature = "chrono"))]
+fn path_templates_need_the_chrono_feature() {
+    assert!(matches!(quiet().path_template("{year}/{filename}").build(), Err(SafeBackupError::InvalidPath(_))));
+}
+
+#[test]
+fn delete_pattern_skips_backups_unless_included() {
+    common::in_temp_dir(|_| {
+        let app = quiet().force(true).build().unwrap();
+        fs::write("a.tmp", "a").unwrap();
+        fs::write("b.tmp", "b").unwrap();
+        fs::write("notes.txt", "keep").unwrap();
+        fs::write("old.tmp.2024-01-01_00-00-00.bak", "backup").unwrap();
+        app.backup_file("a.tmp").unwrap();
+
+        let ActionOutcome::DeletedMany { paths } = app.delete_pattern("*.tmp*", false).unwrap() else {
+            panic!("expected the matches to be deleted");
+        };
+        assert_eq!(paths.len(), 2);
+        assert!(!fs::exists("a.tmp").unwrap() && !fs::exists("b.tmp").unwrap());
+        assert!(fs::exists("notes.txt").unwrap());
+        assert!(fs::exists("old.tmp.2024-01-01_00-00-00.bak").unwrap());
+        assert_eq!(app.list_backups("a.tmp").unwrap().len(), 1);
+
+        app.delete_pattern("*.bak", true).unwrap();
+        assert!(!fs::exists("old.tmp.2024-01-01_00-00-00.bak").unwrap());
+        assert!(app.list_backups("a.tmp").unwrap().is_empty());
+        assert!(matches!(app.delete_pattern("*.tmp", false), Err(SafeBackupError::FileNotFound(_))));
+    });
+}
+
+#[test]
+fn batch_backup_reports_total_bytes() {
+    common::in_temp_dir(|_| {
+        let app = quiet().build().unwrap();
+        fs::write("a.txt", "12345").unwrap();
+        fs::write("b.txt", "123").unwrap();
+        let ActionOutcome::BackedMany { paths, bytes } = app.backup_files(&["a.txt".to_string(), "b.txt".to_string()]).unwrap() else {
+            panic!("expected a batch backup");
+        };
+        assert_eq!((paths.len(), bytes), (2, 8));
+
+        fs::write("a.txt", "changed").unwrap();
+        let summary = app.restore_all(None).unwrap();
+        assert_eq!((summary.restored.len(), summary.bytes), (2, 8));
+    });
+}
+
+#[test]
+fn empty_files_back_up_verify_restore_and_diff() {
+    common::in_temp_dir(|_| {
+        for app in [quiet().force(true).build().unwrap(), quiet().force(true).compress(true).build().unwrap(), quiet().force(true).dedup(true).build().unwrap()] {
+            fs::write("empty.txt", "").unwrap();
+            let ActionOutcome::Backed { path, bytes } = app.backup_file("empty.txt").unwrap() else {
+                panic!("expected a new backup");
+            };
+            assert_eq!(bytes, 0);
+            assert!(app.verify_file("empty.txt").unwrap());
+            assert_eq!(app.diff_file("empty.txt").unwrap(), "");
+
+            fs::write("empty.txt", "not empty any more").unwrap();
+            assert!(!app.diff_file("empty.txt").unwrap().is_empty());
+            app.restore_file("empty.txt", None).unwrap();
+            assert_eq!(fs::read("empty.txt").unwrap(), b"");
+            assert!(app.check_all().unwrap().is_empty());
+
+            app.prune_backups("empty.txt", 0).unwrap();
+            assert!(!path.exists());
+        }
+
+        // A truncated sidecar is reported instead of being compared as an empty digest
+        let app = quiet().force(true).build().unwrap();
+        let ActionOutcome::Backed { path, .. } = app.backup_file("empty.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        fs::write(format!("{}.sha256", path.display()), "").unwrap();
+        assert!(matches!(app.restore_file("empty.txt", None), Err(SafeBackupError::IntegrityError(_))));
+        assert_eq!(app.check_all().unwrap()[0].reason, "checksum sidecar is empty");
+    });
+}
+
+#[test]
+fn backup_suffix_is_used_for_every_operation() {
+    common::in_temp_dir(|_| {
+        let app = quiet().force(true).backup_suffix("~").build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let ActionOutcome::Backed { path, .. } = app.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert!(path.to_string_lossy().ends_with('~'));
+        fs::write("notes.txt", "changed").unwrap();
+        app.backup_file("notes.txt").unwrap();
+
+        // Backups with the default suffix are a separate set
+        assert!(quiet().build().unwrap().list_backups("notes.txt").unwrap().is_empty());
+
+        let backups = app.list_backups("notes.txt").unwrap();
+        assert_eq!(backups.len(), 2);
+        app.restore_file("notes.txt", Some(&backups[1].version)).unwrap();
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "original");
+        assert_eq!(app.prune_backups("notes.txt", 1).unwrap(), vec![path]);
+
+        for suffix in ["", "/bak", ".b*k", ".bak.gz", ".bak."] {
+            assert!(matches!(quiet().backup_suffix(suffix).build(), Err(SafeBackupError::InvalidPath(_))), "{:?}", suffix);
+        }
+    });
+}
+
+#[test]
+fn io_errors_name_the_path_that_failed() {
+    common::in_temp_dir(|_| {
+        let app = quiet().backup_dir("backups").dedup(true).build().unwrap();
+        fs::create_dir("backups").unwrap();
+        fs::write("backups/store", "in the way").unwrap();
+        fs::write("notes.txt", "original").unwrap();
+
+        let error = app.backup_file("notes.txt").unwrap_err();
+        assert!(matches!(&error, SafeBackupError::ContextError { path, .. } if path.ends_with("store")), "{:?}", error);
+        assert!(error.to_string().contains("store"));
+        assert_eq!((error.kind(), error.exit_code()), ("io_error", 4));
+    });
+}
+
+#[test]
+fn migrate_adopts_legacy_bak_files_once() {
+    common::in_temp_dir(|_| {
+        let app = quiet().force(true).backup_dir("backups").build().unwrap();
+        fs::create_dir("docs").unwrap();
+        fs::write("notes.txt.bak", "legacy notes").unwrap();
+        fs::write("docs/todo.txt.bak", "legacy todo").unwrap();
+        fs::write("notes.txt", "current").unwrap();
+
+        let migrated = app.migrate().unwrap();
+        assert_eq!(migrated.len(), 2);
+        assert!(!fs::exists("notes.txt.bak").unwrap());
+        let backups = app.list_backups("notes.txt").unwrap();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].path.starts_with("backups"));
+
+        app.restore_file("notes.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "legacy notes");
+        assert!(app.check_all().unwrap().is_empty());
+        assert!(app.migrate().unwrap().is_empty());
+
+        // Legacy backups are looked for under the base directory, not the working directory
+        let base = tempfile::tempdir().unwrap();
+        fs::write(base.path().join("todo.txt.bak"), "legacy base todo").unwrap();
+        fs::write("todo.txt.bak", "legacy working todo").unwrap();
+        let app = quiet().base_dir(base.path()).log_file(base.path().join("log.txt")).build().unwrap();
+        assert_eq!(app.migrate().unwrap().len(), 1);
+        assert!(!base.path().join("todo.txt.bak").exists());
+        assert!(fs::exists("todo.txt.bak").unwrap());
+        app.restore_file("todo.txt", None).unwrap();
+        assert_eq!(fs::read_to_string(base.path().join("todo.txt")).unwrap(), "legacy base todo");
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn migrate_keeps_the_bytes_of_names_that_are_not_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    common::in_temp_dir(|_| {
+        let app = quiet().force(true).backup_dir("backups").build().unwrap();
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        fs::write(OsStr::from_bytes(b"caf\xe9.txt.bak"), "legacy").unwrap();
+
+        let migrated = app.migrate().unwrap();
+        assert_eq!(migrated.len(), 1);
+        assert!(migrated[0].as_os_str().as_bytes().starts_with(b"backups/caf\xe9.txt."));
+        let backups = app.list_os_backups(name).unwrap();
+        assert_eq!(backups.len(), 1);
+        app.restore_os_file(name, None).unwrap();
+        assert_eq!(fs::read(name).unwrap(), b"legacy");
+        // The checksum sidecar sits next to the migrated backup, so nothing is reported missing
+        assert!(app.check_all().unwrap().is_empty());
+    });
+}
+
+#[test]
+fn restore_all_keeps_newer_files_inside_an_injected_base_dir() {
+    common::in_temp_dir(|_| {
+        let base = tempfile::tempdir().unwrap();
+        let live = base.path().join("x.txt");
+        fs::write(&live, "v1").unwrap();
+        let app = quiet().base_dir(base.path()).log_file(base.path().join("log.txt")).build().unwrap();
+        app.backup_file("x.txt").unwrap();
+
+        // An edit made well after the backup, without waiting for the clock
+        fs::write(&live, "live edit").unwrap();
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        filetime::set_file_mtime(&live, filetime::FileTime::from_system_time(later)).unwrap();
+
+        let summary = app.restore_all(None).unwrap();
+        assert!(summary.restored.is_empty());
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(fs::read_to_string(&live).unwrap(), "live edit");
+    });
+}
+
+#[test]
+fn rollback_undoes_a_failed_batch_and_migration() {
+    common::in_temp_dir(|_| {
+        let app = quiet().rollback(true).backup_dir("backups").build().unwrap();
+        fs::write("notes.txt", "notes").unwrap();
+        let error = app.backup_files(&["notes.txt".to_string(), "missing.txt".to_string()]).unwrap_err();
+        assert!(matches!(error, SafeBackupError::BatchFailed { .. }), "{}", error);
+        assert!(app.list_backups("notes.txt").unwrap().is_empty());
+
+        // The second legacy backup cannot be moved under backups/zz, which is a file
+        fs::create_dir("zz").unwrap();
+        fs::write("notes.txt.bak", "legacy notes").unwrap();
+        fs::write("zz/todo.txt.bak", "legacy todo").unwrap();
+        fs::write("backups/zz", "in the way").unwrap();
+        app.migrate().unwrap_err();
+        assert_eq!(fs::read_to_string("notes.txt.bak").unwrap(), "legacy notes");
+        assert!(app.list_backups("notes.txt").unwrap().is_empty());
+    });
+}
+
+#[test]
+fn preview_restore_summarizes_the_change() {
+    common::in_temp_dir(|_| {
+        let app = quiet().build().unwrap();
+        let original: String = (1..=30).map(|n| format!("line {}\n", n)).collect();
+        fs::write("notes.txt", &original).unwrap();
+        app.backup_file("notes.txt").unwrap();
+
+        fs::write("notes.txt", original.replace("line 2\n", "line two\n")).unwrap();
+        let preview = app.preview_restore("notes.txt").unwrap();
+        assert_eq!((preview.current_size, preview.backup_size), (Some(original.len() as u64 + 2), original.len() as u64));
+        assert_eq!(preview.summary, "-line two\n+line 2");
+
+        fs::write("notes.txt", "").unwrap();
+        let summary = app.preview_restore("notes.txt").unwrap().summary;
+        assert!(summary.starts_with("+line 1\n") && summary.contains("... 20 more changed line(s) ...") && summary.ends_with("+line 30"));
+
+        fs::remove_file("notes.txt").unwrap();
+        assert_eq!(app.preview_restore("notes.txt").unwrap().current_size, None);
+        assert!(fs::read_to_string("logfile.txt").unwrap().lines().all(|line| !line.contains("Performed restore")));
+    });
+}
+
+#[test]
+fn batch_backups_run_concurrently() {
+    common::in_temp_dir(|_| {
+        let app = quiet().backup_dir("backups").jobs(8).build().unwrap();
+        let filenames: Vec<String> = (0..40).map(|n| format!("file{}.txt", n)).collect();
+        for (n, filename) in filenames.iter().enumerate() {
+            fs::write(filename, format!("contents of file {}\n", n).repeat(n + 1)).unwrap();
+        }
+
+        let ActionOutcome::BackedMany { paths, .. } = app.backup_files(&filenames).unwrap() else {
+            panic!("expected a batch backup");
+        };
+        assert_eq!(paths.len(), filenames.len());
+        assert!(paths.iter().zip(&filenames).all(|(path, filename)| path.to_string_lossy().starts_with(&format!("{}.", std::path::Path::new("backups").join(filename).display()))));
+        assert!(app.check_all().unwrap().is_empty());
+        for filename in &filenames {
+            assert_eq!(app.list_backups(filename).unwrap().len(), 1);
+        }
+
+        // Every log line is whole: one per backup, each starting with a timestamp
+        let log = fs::read_to_string("logfile.txt").unwrap();
+        assert!(log.lines().all(|line| line.starts_with('[')));
+        assert_eq!(log.lines().filter(|line| line.contains("Performed backup")).count(), filenames.len());
+    });
+}
+
+#[test]
+fn signed_log_detects_tampering() {
+    common::in_temp_dir(|_| {
+        fs::write("logfile.txt", "[2024-01-01 00:00:00 UTC] written before signing\n").unwrap();
+        let app = quiet().log_key("secret").build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        app.backup_file("notes.txt").unwrap();
+        app.list_backups("notes.txt").unwrap();
+        app.log_action("Second\nline").unwrap();
+        assert_eq!(app.verify_log().unwrap(), 2);
+        let log = fs::read_to_string("logfile.txt").unwrap();
+
+        // The wrong key fails on the first signed line
+        let error = quiet().log_key("other").build().unwrap().verify_log().unwrap_err();
+        assert!(matches!(&error, SafeBackupError::IntegrityError(message) if message.starts_with("line 2 ")), "{}", error);
+
+        let lines: Vec<&str> = log.lines().collect();
+        fs::write("logfile.txt", format!("{}\n{}\n", lines[0], lines[2..].join("\n"))).unwrap();
+        let error = app.verify_log().unwrap_err();
+        assert!(matches!(&error, SafeBackupError::IntegrityError(message) if message.starts_with("line 2 ")), "{}", error);
+
+        fs::write("logfile.txt", format!("{}\nunsigned\n", log.trim_end())).unwrap();
+        let error = app.verify_log().unwrap_err();
+        assert!(error.to_string().contains("is not signed"), "{}", error);
+    });
+}
+
+#[test]
+fn signed_log_chains_entries_longer_than_4_kib() {
+    common::in_temp_dir(|_| {
+        for (format, log_file) in [(LogFormat::Json, "log.json"), (LogFormat::Text, "log.txt")] {
+            let app = quiet().log_key("secret").log_format(format).log_file(log_file).build().unwrap();
+            app.log_action(&"long entry ".repeat(500)).unwrap();
+            assert!(fs::read_to_string(log_file).unwrap().len() > 4096);
+            app.log_action("After the long entry").unwrap();
+            app.log_action("And one more").unwrap();
+            assert_eq!(app.verify_log().unwrap(), 3, "{}", log_file);
+        }
+    });
+}
+
+#[test]
+fn read_only_backups_can_still_be_pruned() {
+    common::in_temp_dir(|_| {
+        let app = quiet().read_only_backups(true).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let ActionOutcome::Backed { path, .. } = app.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert!(fs::metadata(&path).unwrap().permissions().readonly());
+
+        app.restore_file("notes.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "original");
+        assert!(!fs::metadata("notes.txt").unwrap().permissions().readonly());
+
+        assert_eq!(app.prune_backups("notes.txt", 0).unwrap(), vec![path.clone()]);
+        assert!(!path.exists());
+        assert!(app.list_backups("notes.txt").unwrap().is_empty());
+    });
+}
+
+#[test]
+fn content_named_backups_of_identical_contents_share_one_file() {
+    common::in_temp_dir(|_| {
+        let app = quiet().content_names(true).force(true).build().unwrap();
+        let backup_files = || fs::read_dir(".").unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".bak"))
+            .count();
+
+        fs::write("notes.txt", "first").unwrap();
+        let ActionOutcome::Backed { path: first, .. } = app.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert!(matches!(app.backup_file("notes.txt").unwrap(), ActionOutcome::Unchanged { latest } if latest == first));
+        assert_eq!(backup_files(), 1);
+
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        fs::write("notes.txt", "second").unwrap();
+        app.backup_file("notes.txt").unwrap();
+        assert_eq!(backup_files(), 2);
+
+        // Going back to earlier contents reuses their backup and makes it the latest
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        fs::write("notes.txt", "first").unwrap();
+        assert!(matches!(app.backup_file("notes.txt").unwrap(), ActionOutcome::Unchanged { .. }));
+        assert_eq!(backup_files(), 2);
+        fs::write("notes.txt", "edited").unwrap();
+        app.restore_file("notes.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "first");
+    });
+}
+
+#[test]
+fn exports_every_version_named_by_timestamp() {
+    common::in_temp_dir(|_| {
+        let app = quiet().force(true).build().unwrap();
+        fs::write("notes.txt", "first").unwrap();
+        app.backup_file("notes.txt").unwrap();
+        fs::write("notes.txt", "second").unwrap();
+        quiet().compress(true).build().unwrap().backup_file("notes.txt").unwrap();
+        fs::write("notes.txt", "current").unwrap();
+
+        let backups = app.list_backups("notes.txt").unwrap();
+        let ActionOutcome::ExportedVersions { paths, bytes } = app.export_versions("notes.txt", "history").unwrap() else {
+            panic!("expected exported versions");
+        };
+        assert_eq!(paths.len(), 2);
+        assert_eq!(bytes, 11);
+        assert_eq!(fs::read_to_string(format!("history/{}.txt", backups[0].version)).unwrap(), "second");
+        assert_eq!(fs::read_to_string(format!("history/{}.txt", backups[1].version)).unwrap(), "first");
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "current");
+    });
+}
+
+#[test]
+fn reports_disk_usage_per_file_and_in_total() {
+    common::in_temp_dir(|_| {
+        let app = quiet().backup_dir("backups").build().unwrap();
+        assert_eq!(app.disk_usage().unwrap(), 0);
+        assert!(app.usage_by_file().unwrap().is_empty());
+
+        fs::write("notes.txt", "original").unwrap();
+        fs::write("todo.txt", "todo").unwrap();
+        let ActionOutcome::Backed { path, .. } = app.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        app.backup_file("todo.txt").unwrap();
+
+        let files = app.usage_by_file().unwrap();
+        assert_eq!(files.len(), 2);
+        let notes = files.iter().find(|file| file.original == std::path::Path::new("notes.txt")).unwrap();
+        assert_eq!(notes.versions, 1);
+        assert!(notes.bytes > fs::metadata(&path).unwrap().len());
+        assert_eq!(app.disk_usage().unwrap(), files.iter().map(|file| file.bytes).sum::<u64>());
+
+        // Dedup contents are shared, so they only show up in the total
+        let app = quiet().dedup(true).build().unwrap();
+        fs::write("photo.bin", vec![7u8; 4096]).unwrap();
+        app.backup_file("photo.bin").unwrap();
+        let listed: u64 = app.usage_by_file().unwrap().iter().map(|file| file.bytes).sum();
+        assert!(app.disk_usage().unwrap() >= listed + 4096);
+    });
+}
@@ -0,0 +1,14 @@
+use safe_backup::{Command, SafeBackupError};
+
+#[test]
+fn parses_command_names_case_insensitively() {
+    assert_eq!(Command::try_from("backup").unwrap(), Command::Backup);
+    assert_eq!(Command::try_from("RESTORE").unwrap(), Command::Restore);
+    assert_eq!(Command::try_from("Verify-Log").unwrap(), Command::VerifyLog);
+    for command in Command::ALL {
+        assert_eq!(Command::try_from(command.to_string().as_str()).unwrap(), command);
+    }
+
+    let error = Command::try_from("backpu").unwrap_err();
+    assert!(matches!(&error, SafeBackupError::InvalidPath(message) if message == "Unknown command: 'backpu'"), "{}", error);
+}
@@ -0,0 +1,19 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+// SafeBackup resolves paths against the working directory, which is shared by every test thread
+static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+// Run a test with a fresh temporary directory as the working directory
+pub fn in_temp_dir<T>(test: impl FnOnce(&Path) -> T) -> T {
+    let _guard = CWD_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let previous = std::env::current_dir().expect("current dir");
+    std::env::set_current_dir(dir.path()).expect("enter temp dir");
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| test(dir.path())));
+    std::env::set_current_dir(previous).expect("restore current dir");
+    match result {
+        Ok(value) => value,
+        Err(panic) => std::panic::resume_unwind(panic),
+    }
+}
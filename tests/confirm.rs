@@ -0,0 +1,55 @@
+use std::fs;
+use std::process::{Command, Stdio};
+
+// Run the binary in dir with stdin closed, as in CI, optionally answering confirmations through the environment
+fn run_without_terminal(dir: &std::path::Path, args: &[&str], confirm: Option<&str>) -> std::process::Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_safe_backup"));
+    command.args(args).current_dir(dir).stdin(Stdio::null()).env_remove("SAFE_BACKUP_CONFIRM");
+    if let Some(confirm) = confirm {
+        command.env("SAFE_BACKUP_CONFIRM", confirm);
+    }
+    command.output().expect("run safe_backup")
+}
+
+#[test]
+fn delete_without_a_terminal_needs_the_confirm_variable() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+
+    // stdin is at EOF, which must not be mistaken for a "no"
+    let output = run_without_terminal(dir.path(), &["delete", "notes.txt"], None);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("SAFE_BACKUP_CONFIRM=yes"));
+    assert!(dir.path().join("notes.txt").exists());
+
+    let output = run_without_terminal(dir.path(), &["delete", "notes.txt"], Some("no"));
+    assert!(output.status.success());
+    assert!(dir.path().join("notes.txt").exists());
+
+    let output = run_without_terminal(dir.path(), &["delete", "notes.txt"], Some("yes"));
+    assert!(output.status.success());
+    assert!(!dir.path().join("notes.txt").exists());
+}
+
+#[test]
+fn relative_to_works_on_another_directory_without_entering_it() {
+    let (working_dir, base) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let base_arg = base.path().to_str().unwrap();
+    fs::write(base.path().join("notes.txt"), "notes").unwrap();
+    fs::write(base.path().join("safe_backup.toml"), "backup_dir = \"backups\"\n").unwrap();
+    fs::write(base.path().join("scratch.tmp"), "scratch").unwrap();
+    fs::write(working_dir.path().join("scratch.tmp"), "scratch").unwrap();
+
+    let output = run_without_terminal(working_dir.path(), &["--relative-to", base_arg, "backup", "notes.txt"], None);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let backups = fs::read_dir(base.path().join("backups")).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap());
+    assert_eq!(backups.filter(|name| name.starts_with("notes.txt.") && name.ends_with(".bak")).count(), 1);
+    assert!(base.path().join("logfile.txt").is_file());
+
+    let output = run_without_terminal(working_dir.path(), &["--relative-to", base_arg, "--pattern", "delete", "*.tmp"], Some("yes"));
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!base.path().join("scratch.tmp").exists());
+    assert!(working_dir.path().join("scratch.tmp").exists());
+    let entries: Vec<_> = fs::read_dir(working_dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(entries, ["scratch.tmp"]);
+}
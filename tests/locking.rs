@@ -0,0 +1,54 @@
+mod common;
+
+use std::fs;
+use std::sync::Barrier;
+
+use safe_backup::{ActionOutcome, SafeBackup, SafeBackupError};
+
+#[test]
+fn backup_fails_while_another_run_holds_the_lock() {
+    common::in_temp_dir(|_| {
+        let app = SafeBackup::builder().quiet(true).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+
+        let held = fs::File::create(".notes.txt.lock").unwrap();
+        held.lock().unwrap();
+        let result = std::thread::scope(|scope| scope.spawn(|| app.backup_file("notes.txt")).join().unwrap());
+        assert!(matches!(result, Err(SafeBackupError::IoError(ref e)) if e.kind() == std::io::ErrorKind::WouldBlock));
+        assert!(app.list_backups("notes.txt").unwrap().is_empty());
+
+        held.unlock().unwrap();
+        assert!(matches!(app.backup_file("notes.txt").unwrap(), ActionOutcome::Backed { .. }));
+    });
+}
+
+#[test]
+fn concurrent_backups_of_the_same_file_never_corrupt_it() {
+    common::in_temp_dir(|_| {
+        let app = SafeBackup::builder().quiet(true).build().unwrap();
+        fs::write("large.bin", vec![7u8; 4 * 1024 * 1024]).unwrap();
+
+        let barrier = Barrier::new(2);
+        let results: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..2)
+                .map(|_| scope.spawn(|| {
+                    barrier.wait();
+                    app.backup_file("large.bin")
+                }))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        // Each run either wrote the backup, found it unchanged, or was turned away by the lock
+        for result in &results {
+            match result {
+                Ok(_) => {}
+                Err(SafeBackupError::IoError(e)) => assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock),
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+        assert!(results.iter().any(|result| result.is_ok()));
+        assert_eq!(app.list_backups("large.bin").unwrap().len(), 1);
+        assert!(app.verify_file("large.bin").unwrap());
+    });
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use safe_backup::{ActionOutcome, S3Storage, SafeBackup, SafeBackupError};
+
+type Objects = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+// A minimal S3 endpoint for the "backups" bucket: PUT, GET, HEAD, DELETE and ListObjectsV2,
+// answering 403 unless a request is signed with the access key "test-key"
+fn fake_s3() -> (String, Objects) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let objects = Objects::default();
+    let served = Arc::clone(&objects);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            serve(stream.unwrap(), &served);
+        }
+    });
+    (endpoint, objects)
+}
+
+fn serve(stream: TcpStream, objects: &Objects) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let mut words = line.split_whitespace();
+    let (method, target) = (words.next().unwrap().to_string(), words.next().unwrap().to_string());
+    let (mut length, mut authorization) = (0, String::new());
+    loop {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        let Some((name, value)) = line.trim_end().split_once(':') else { break };
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => length = value.trim().parse().unwrap(),
+            "authorization" => authorization = value.trim().to_string(),
+            _ => {}
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let key = decode(path.strip_prefix("/backups").unwrap().trim_start_matches('/'));
+    let mut objects = objects.lock().unwrap();
+    let (status, response) = if !authorization.starts_with("AWS4-HMAC-SHA256 Credential=test-key/") {
+        ("403 Forbidden", b"<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>".to_vec())
+    } else if method == "GET" && key.is_empty() {
+        let prefix = query.split('&').find_map(|pair| pair.strip_prefix("prefix=")).map(decode).unwrap_or_default();
+        let delimited = query.split('&').any(|pair| pair.starts_with("delimiter="));
+        let contents: String = objects.iter()
+            .filter(|(name, _)| name.strip_prefix(&prefix).is_some_and(|rest| !delimited || !rest.contains('/')))
+            .map(|(name, contents)| format!(
+                "<Contents><Key>{}</Key><Size>{}</Size><LastModified>2024-05-01T12:00:00.000Z</LastModified></Contents>",
+                name, contents.len()
+            ))
+            .collect();
+        ("200 OK", format!("<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>", contents).into_bytes())
+    } else {
+        match (method.as_str(), objects.get(&key)) {
+            ("PUT", _) => {
+                objects.insert(key, body);
+                ("200 OK", Vec::new())
+            }
+            ("DELETE", _) => {
+                objects.remove(&key);
+                ("204 No Content", Vec::new())
+            }
+            ("GET" | "HEAD", Some(contents)) => ("200 OK", contents.clone()),
+            _ => ("404 Not Found", b"<Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message></Error>".to_vec()),
+        }
+    };
+    let mut stream = stream;
+    let body = if method == "HEAD" { &[][..] } else { &response[..] };
+    write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, response.len()).unwrap();
+    stream.write_all(body).unwrap();
+}
+
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            decoded.push(u8::from_str_radix(&value[index + 1..index + 3], 16).unwrap());
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).unwrap()
+}
+
+#[test]
+fn backups_are_kept_as_objects_in_an_s3_bucket() {
+    let (endpoint, objects) = fake_s3();
+    common::in_temp_dir(|_| {
+        let storage = S3Storage::new(&endpoint, "backups", "us-east-1", "test-key", "test-secret").unwrap();
+        let app = SafeBackup::builder().quiet(true).force(true).backup_dir("saved").storage(storage).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let ActionOutcome::Backed { path, .. } = app.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert!(!path.exists());
+        let key = path.to_string_lossy().replace('\\', "/");
+        assert_eq!(objects.lock().unwrap().get(&key).map(Vec::as_slice), Some(&b"original"[..]));
+
+        assert_eq!(app.list_backups("notes.txt").unwrap().len(), 1);
+        fs::write("notes.txt", "changed").unwrap();
+        app.restore_file("notes.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "original");
+
+        // Status lists every key under the backup directory, however deep
+        fs::create_dir("docs").unwrap();
+        fs::write("docs/todo.txt", "todo").unwrap();
+        app.backup_file("docs/todo.txt").unwrap();
+        assert_eq!(app.status().unwrap().len(), 2);
+
+        // Only completing a multipart upload reports errors in a 200 response; a backup may contain anything
+        fs::write("error.xml", "<Error><Code>NotAnError</Code></Error>").unwrap();
+        app.backup_file("error.xml").unwrap();
+        fs::write("error.xml", "changed").unwrap();
+        app.restore_file("error.xml", None).unwrap();
+        assert_eq!(fs::read_to_string("error.xml").unwrap(), "<Error><Code>NotAnError</Code></Error>");
+
+        app.prune_backups("notes.txt", 0).unwrap();
+        assert!(!objects.lock().unwrap().contains_key(&key));
+    });
+}
+
+#[test]
+fn s3_failures_say_what_the_bucket_answered() {
+    let (endpoint, _) = fake_s3();
+    common::in_temp_dir(|_| {
+        let storage = S3Storage::new(&endpoint, "backups", "us-east-1", "wrong-key", "test-secret").unwrap();
+        let app = SafeBackup::builder().quiet(true).storage(storage).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let error = app.backup_file("notes.txt").unwrap_err();
+        assert!(error.to_string().contains("AccessDenied: Access Denied"), "{}", error);
+
+        let https = S3Storage::new("https://s3.amazonaws.com", "backups", "us-east-1", "test-key", "test-secret");
+        if cfg!(all(feature = "tls", unix)) {
+            assert!(https.is_ok());
+        } else {
+            assert!(matches!(https, Err(SafeBackupError::InvalidPath(message)) if message.contains("TLS")));
+        }
+    });
+}
+
+#[test]
+#[cfg(all(feature = "tls", unix))]
+fn https_endpoints_refuse_servers_without_tls() {
+    // An endpoint answering in plain HTTP cannot complete the handshake, so nothing is sent in the clear
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("https://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let _ = stream.unwrap().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        }
+    });
+    common::in_temp_dir(|_| {
+        let storage = S3Storage::new(&endpoint, "backups", "us-east-1", "test-key", "test-secret").unwrap();
+        let app = SafeBackup::builder().quiet(true).storage(storage).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let error = app.backup_file("notes.txt").unwrap_err();
+        assert!(error.to_string().contains("TLS connection to the S3 endpoint"), "{}", error);
+    });
+}
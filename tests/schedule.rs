@@ -0,0 +1,75 @@
+// Scheduled backups are only built with the chrono feature
+#![cfg(feature = "chrono")]
+
+mod common;
+
+use std::fs;
+
+use chrono::{DateTime, TimeZone, Utc};
+use safe_backup::{CronSchedule, SafeBackup, ScheduledBackup};
+
+fn at(text: &str) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&chrono::NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap())
+}
+
+fn next(expression: &str, after: &str) -> String {
+    let schedule = CronSchedule::parse(expression).unwrap();
+    schedule.next_after(&at(after)).unwrap().format("%Y-%m-%d %H:%M").to_string()
+}
+
+#[test]
+fn cron_expressions_find_the_next_due_minute() {
+    assert_eq!(next("*/15 * * * *", "2024-05-01 12:07"), "2024-05-01 12:15");
+    assert_eq!(next("*/15 * * * *", "2024-05-01 12:15"), "2024-05-01 12:30");
+    assert_eq!(next("30 2 * * *", "2024-05-01 12:00"), "2024-05-02 02:30");
+    assert_eq!(next("0 9-17 * * 1-5", "2024-05-03 18:00"), "2024-05-06 09:00");
+    assert_eq!(next("0 0 1,15 * *", "2024-05-02 00:00"), "2024-05-15 00:00");
+    assert_eq!(next("0 0 29 2 *", "2024-03-01 00:00"), "2028-02-29 00:00");
+    assert_eq!(next("0 0 * * 7", "2024-05-01 00:00"), "2024-05-05 00:00");
+    // With both day fields restricted either one matching is enough
+    assert_eq!(next("0 0 13 * 5", "2024-05-01 00:00"), "2024-05-03 00:00");
+    // A field starting with * is not restricted, even with a step, so both must match
+    assert_eq!(next("0 0 */2 * 1", "2024-05-01 00:00"), "2024-05-13 00:00");
+    assert_eq!(next("0 0 13 * */2", "2024-05-01 00:00"), "2024-06-13 00:00");
+    assert_eq!(next("@monthly", "2024-12-15 08:00"), "2025-01-01 00:00");
+
+    assert!(CronSchedule::parse("0 0 30 2 *").unwrap().next_after(&at("2024-01-01 00:00")).is_none());
+    for invalid in ["", "* * * *", "60 * * * *", "*/0 * * * *", "5-1 * * * *", "0 0 * * mon"] {
+        assert!(CronSchedule::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn scheduled_backups_apply_their_retention() {
+    common::in_temp_dir(|_| {
+        fs::create_dir("docs").unwrap();
+        fs::write("docs/report.txt", "report").unwrap();
+        fs::write("notes.txt", "first").unwrap();
+        let app = SafeBackup::builder().quiet(true).backup_dir("backups").build().unwrap();
+        let notes = ScheduledBackup {
+            schedule: CronSchedule::parse("@hourly").unwrap(),
+            files: vec!["notes.txt".to_string(), "missing.txt".to_string()],
+            recursive: false,
+            keep: Some(1),
+            max_age: None,
+        };
+        assert_eq!(app.run_scheduled(&notes).unwrap(), 1);
+        assert_eq!(app.list_backups("notes.txt").unwrap().len(), 1);
+
+        fs::write("notes.txt", "second").unwrap();
+        assert_eq!(app.run_scheduled(&notes).unwrap(), 1);
+        let backups = app.list_backups("notes.txt").unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(fs::read_to_string(&backups[0].path).unwrap(), "second");
+
+        // Directory trees apply the retention to every file that got a new backup
+        let docs = ScheduledBackup { files: vec!["docs".to_string()], recursive: true, ..notes };
+        assert_eq!(app.run_scheduled(&docs).unwrap(), 1);
+        fs::write("docs/report.txt", "edited").unwrap();
+        assert_eq!(app.run_scheduled(&docs).unwrap(), 1);
+        let backups = app.list_backups("docs/report.txt").unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(fs::read_to_string(&backups[0].path).unwrap(), "edited");
+        assert!(fs::read_to_string(app.log_file()).unwrap().contains("Scheduled backup of 'missing.txt' failed"));
+    });
+}
@@ -0,0 +1,19 @@
+use std::path::Path;
+
+use safe_backup::SftpTarget;
+
+#[test]
+fn parses_sftp_urls() {
+    let target = SftpTarget::parse("sftp://alice@backup.example.com/srv/backups/", "id_ed25519").unwrap();
+    assert_eq!(target.describe(Path::new("notes.txt.bak")), "sftp://alice@backup.example.com:22/srv/backups/notes.txt.bak");
+
+    let target = SftpTarget::parse("sftp://bob@10.0.0.5:2222/data", "key").unwrap();
+    assert_eq!(target.describe(Path::new("a.bak")), "sftp://bob@10.0.0.5:2222/data/a.bak");
+}
+
+#[test]
+fn rejects_malformed_sftp_urls() {
+    for url in ["ftp://alice@host/x", "sftp://host/x", "sftp://@host/x", "sftp://alice@host:port/x", "sftp://alice@host/../etc"] {
+        assert!(SftpTarget::parse(url, "key").is_err(), "{} should be rejected", url);
+    }
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use safe_backup::{ActionOutcome, SafeBackup, StorageBackend, StorageEntry};
+
+// Keeps backups in memory, so nothing but the log and manifest reaches the disk
+#[derive(Debug, Default, Clone)]
+struct MemoryStorage {
+    files: Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>,
+}
+
+impl StorageBackend for MemoryStorage {
+    fn store(&self, name: &Path, reader: &mut dyn Read) -> io::Result<u64> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        let size = contents.len() as u64;
+        self.files.lock().unwrap().insert(name.to_path_buf(), contents);
+        Ok(size)
+    }
+
+    fn load(&self, name: &Path) -> io::Result<Box<dyn Read>> {
+        let contents = self.files.lock().unwrap().get(name).cloned().ok_or(io::ErrorKind::NotFound)?;
+        Ok(Box::new(io::Cursor::new(contents)))
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<StorageEntry>> {
+        let dir = if dir == Path::new(".") { Path::new("") } else { dir };
+        Ok(self.files.lock().unwrap().iter()
+            .filter(|(name, _)| name.parent() == Some(dir))
+            .map(|(name, contents)| StorageEntry { name: name.clone(), size: contents.len() as u64, modified: None })
+            .collect())
+    }
+
+    fn remove(&self, name: &Path) -> io::Result<()> {
+        self.files.lock().unwrap().remove(name).map(|_| ()).ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+}
+
+#[test]
+fn backups_go_through_a_custom_storage_backend() {
+    common::in_temp_dir(|_| {
+        let storage = MemoryStorage::default();
+        let app = SafeBackup::builder().quiet(true).force(true).storage(storage.clone()).build().unwrap();
+        fs::write("notes.txt", "original").unwrap();
+        let ActionOutcome::Backed { path, .. } = app.backup_file("notes.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert!(!path.exists());
+        assert!(storage.files.lock().unwrap().contains_key(&path));
+
+        assert_eq!(app.list_backups("notes.txt").unwrap().len(), 1);
+        fs::write("notes.txt", "changed").unwrap();
+        app.restore_file("notes.txt", None).unwrap();
+        assert_eq!(fs::read_to_string("notes.txt").unwrap(), "original");
+        // Status and usage list the backend, not the working directory
+        assert_eq!(app.status().unwrap().len(), 1);
+        assert_eq!(app.disk_usage().unwrap(), app.usage_by_file().unwrap()[0].bytes);
+
+        app.prune_backups("notes.txt", 0).unwrap();
+        assert!(storage.files.lock().unwrap().is_empty());
+    });
+}
@@ -0,0 +1,265 @@
+mod common;
+
+use safe_backup::{SafeBackup, SafeBackupError, expand_home};
+
+fn is_invalid(app: &SafeBackup, filename: &str) -> bool {
+    matches!(app.validate_path(filename), Err(SafeBackupError::InvalidPath(_)))
+}
+
+#[test]
+fn rejects_traversal_absolute_and_empty_paths() {
+    common::in_temp_dir(|_| {
+        let app = SafeBackup::new().unwrap();
+        assert!(is_invalid(&app, ""));
+        assert!(is_invalid(&app, "../secret.txt"));
+        assert!(is_invalid(&app, "a/../../secret.txt"));
+        assert!(is_invalid(&app, "/etc/passwd"));
+        assert!(is_invalid(&app, "bad\0name"));
+        assert!(app.validate_path("notes.txt").is_ok());
+        assert!(app.validate_path("sub/dir/notes.txt").is_ok());
+    });
+}
+
+// Only NUL and control characters are invalid everywhere; <>:"|?* are Windows restrictions
+#[cfg(unix)]
+#[test]
+fn accepts_windows_reserved_characters_on_unix() {
+    common::in_temp_dir(|_| {
+        let app = SafeBackup::builder().quiet(true).build().unwrap();
+        assert!(app.validate_path("report:final.txt").is_ok());
+        assert!(app.validate_path("what?.txt").is_ok());
+        assert!(is_invalid(&app, "report\u{1b}final.txt"));
+
+        std::fs::write("report:final.txt", "final").unwrap();
+        assert!(app.backup_file("report:final.txt").is_ok());
+        assert_eq!(app.list_backups("report:final.txt").unwrap().len(), 1);
+    });
+}
+
+#[cfg(windows)]
+#[test]
+fn rejects_windows_reserved_characters_on_windows() {
+    common::in_temp_dir(|_| {
+        let app = SafeBackup::new().unwrap();
+        assert!(is_invalid(&app, "report:final.txt"));
+        for name in ["a<b.txt", "a>b.txt", "a\"b.txt", "a|b.txt", "what?.txt", "star*.txt"] {
+            assert!(is_invalid(&app, name), "{}", name);
+        }
+        assert!(app.validate_path("report-final.txt").is_ok());
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn rejects_symlinked_subdirectory_escaping_the_working_dir() {
+    common::in_temp_dir(|dir| {
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.join("escape")).unwrap();
+        std::fs::create_dir(dir.join("inside")).unwrap();
+        std::os::unix::fs::symlink(dir.join("inside"), dir.join("alias")).unwrap();
+
+        // A symlink further down the tree escapes just the same
+        std::os::unix::fs::symlink(outside.path(), dir.join("inside/deeper")).unwrap();
+
+        let app = SafeBackup::builder().quiet(true).force(true).build().unwrap();
+        assert!(is_invalid(&app, "escape/secret.txt"));
+        assert!(is_invalid(&app, "escape/not/created/yet.txt"));
+        assert!(is_invalid(&app, "./escape/./secret.txt"));
+        assert!(is_invalid(&app, "inside/deeper/secret.txt"));
+        assert!(is_invalid(&app, "alias/deeper/secret.txt"));
+        assert!(app.validate_path("alias/notes.txt").is_ok());
+        assert!(app.validate_path("inside/notes.txt").is_ok());
+        assert!(app.validate_path("inside/not/created/yet.txt").is_ok());
+
+        // Neither the source nor a restore destination can be reached through the symlink
+        assert!(matches!(app.backup_file("escape/secret.txt"), Err(SafeBackupError::InvalidPath(_))));
+        std::fs::write("notes.txt", "notes").unwrap();
+        app.backup_file("notes.txt").unwrap();
+        assert!(matches!(app.restore_file_to("notes.txt", "escape/notes.txt"), Err(SafeBackupError::InvalidPath(_))));
+        assert!(!outside.path().join("notes.txt").exists());
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn refuses_to_back_up_a_symlink_to_a_file_outside_the_working_dir() {
+    common::in_temp_dir(|dir| {
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret.txt"), dir.join("link.txt")).unwrap();
+        std::fs::write("notes.txt", "notes").unwrap();
+        std::os::unix::fs::symlink(dir.join("notes.txt"), dir.join("alias.txt")).unwrap();
+
+        let app = SafeBackup::builder().quiet(true).build().unwrap();
+        for name in ["link.txt", "alias.txt"] {
+            let Err(SafeBackupError::InvalidPath(message)) = app.backup_file(name) else {
+                panic!("expected '{}' to be refused as a symlink", name);
+            };
+            assert!(message.contains(name) && message.contains("symlink"), "{}", message);
+        }
+
+        // Following symlinks still stops at the working directory
+        let app = SafeBackup::builder().quiet(true).follow_symlinks(true).build().unwrap();
+        let Err(SafeBackupError::InvalidPath(message)) = app.backup_file("link.txt") else {
+            panic!("expected a symlink leaving the working directory to be refused");
+        };
+        assert!(message.contains("link.txt") && message.contains("outside"), "{}", message);
+        let safe_backup::ActionOutcome::Backed { path, .. } = app.backup_file("alias.txt").unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "notes");
+        assert!(app.list_backups("link.txt").unwrap().is_empty());
+    });
+}
+
+#[test]
+fn rejects_tilde_filenames_but_expands_configured_paths() {
+    common::in_temp_dir(|_| {
+        let app = SafeBackup::new().unwrap();
+        assert!(is_invalid(&app, "~/notes.txt"));
+        assert!(is_invalid(&app, "~"));
+        assert!(app.validate_path("~$report.docx").is_ok());
+        assert!(app.validate_path("notes.txt~").is_ok());
+
+        assert_eq!(expand_home(std::path::Path::new("backups/~")).unwrap(), std::path::Path::new("backups/~"));
+        if let Some(home) = std::env::var_os("HOME") {
+            assert_eq!(expand_home(std::path::Path::new("~/backups")).unwrap(), std::path::Path::new(&home).join("backups"));
+        }
+
+        // An expanded backup directory is used when it lies inside the working directory
+        let inside = std::env::current_dir().unwrap().join("backups");
+        let app = SafeBackup::builder().quiet(true).backup_dir(&inside).build().unwrap();
+        std::fs::write("notes.txt", "original").unwrap();
+        assert!(app.backup_file("notes.txt").is_ok());
+        assert!(inside.is_dir());
+    });
+}
+
+// With an injected base directory the working directory is never consulted, so no lock is needed
+#[cfg(unix)]
+#[test]
+fn checks_paths_against_the_injected_base_dir() {
+    let base = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    std::os::unix::fs::symlink(outside.path(), base.path().join("escape")).unwrap();
+    std::fs::create_dir(base.path().join("inside")).unwrap();
+
+    let app = SafeBackup::builder().base_dir(base.path()).log_file(base.path().join("log.txt")).build().unwrap();
+    assert!(is_invalid(&app, "escape/secret.txt"));
+    assert!(is_invalid(&app, "../secret.txt"));
+    assert!(app.validate_path("inside/notes.txt").is_ok());
+}
+
+#[cfg(windows)]
+#[test]
+fn rejects_windows_reserved_device_names() {
+    common::in_temp_dir(|_| {
+        let app = SafeBackup::new().unwrap();
+        assert!(is_invalid(&app, "nul.txt"));
+        assert!(is_invalid(&app, "COM3"));
+        for name in ["sub/Con", "con", "Aux.tar.gz", "prn.txt", "lpt1", "LPT9.log", "com1.", "nul/notes.txt"] {
+            assert!(is_invalid(&app, name), "{}", name);
+        }
+        for name in ["confetti.txt", "console.log", "auxiliary.txt", "com10.txt", "lpt.txt", "sub/nullable.txt"] {
+            assert!(app.validate_path(name).is_ok(), "{}", name);
+        }
+        assert!(matches!(app.backup_file("nul.txt"), Err(SafeBackupError::InvalidPath(_))));
+    });
+}
+
+#[cfg(windows)]
+#[test]
+fn rejects_trailing_dots_and_spaces_on_windows() {
+    common::in_temp_dir(|_| {
+        let app = SafeBackup::builder().quiet(true).build().unwrap();
+        assert!(is_invalid(&app, "file. "));
+        assert!(is_invalid(&app, "file."));
+        assert!(is_invalid(&app, "data.txt "));
+        assert!(is_invalid(&app, "dir./file.txt"));
+        assert!(app.validate_path("file.txt").is_ok());
+        assert!(app.validate_path(".hidden").is_ok());
+
+        // A restore to "data.txt " would otherwise overwrite data.txt
+        std::fs::write("data.txt", "original").unwrap();
+        app.backup_file("data.txt").unwrap();
+        let Err(SafeBackupError::InvalidPath(message)) = app.restore_file_to("data.txt", "data.txt ") else {
+            panic!("expected a trailing space to be refused");
+        };
+        assert!(message.contains("dot or space"), "{}", message);
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn allows_trailing_dots_and_spaces_on_unix() {
+    common::in_temp_dir(|_| {
+        let app = SafeBackup::builder().quiet(true).build().unwrap();
+        assert!(app.validate_path("file. ").is_ok());
+        assert!(app.validate_path("file.").is_ok());
+        assert!(app.validate_path("file.txt").is_ok());
+
+        // "data.txt " is a file of its own here, so backing it up leaves data.txt alone
+        std::fs::write("data.txt", "original").unwrap();
+        std::fs::write("data.txt ", "other").unwrap();
+        app.backup_file("data.txt ").unwrap();
+        assert_eq!(app.list_backups("data.txt ").unwrap().len(), 1);
+        assert!(app.list_backups("data.txt").unwrap().is_empty());
+    });
+}
+
+#[cfg(windows)]
+#[test]
+fn keeps_containment_for_paths_past_max_path_on_windows() {
+    common::in_temp_dir(|dir| {
+        // Well past the 260 character MAX_PATH, where fs::canonicalize returns \\?\ paths
+        let nested: Vec<String> = (0..30).map(|i| format!("directory_{:02}", i)).collect();
+        let nested = nested.join("/");
+        std::fs::create_dir_all(dir.join(&nested)).unwrap();
+        assert!(dir.join(&nested).to_string_lossy().len() > 260);
+
+        let app = SafeBackup::new().unwrap();
+        assert!(app.validate_path(&format!("{}/notes.txt", nested)).is_ok());
+        assert!(app.validate_path(&format!("{}/not/created/yet.txt", nested)).is_ok());
+        assert!(is_invalid(&app, &format!("{}/../../../..", nested)));
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn backs_up_files_whose_names_are_not_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    common::in_temp_dir(|_| {
+        let app = SafeBackup::builder().quiet(true).build().unwrap();
+        // "caf\xe9.txt" is Latin-1, which is not valid UTF-8
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        assert!(app.validate_os_path(OsStr::from_bytes(b"\xff/../secret.txt")).is_err());
+        assert!(app.validate_os_path(OsStr::from_bytes(b"\xff\x01name")).is_err());
+        assert_eq!(app.validate_os_path(name).unwrap().as_os_str(), name);
+
+        std::fs::write(name, "original").unwrap();
+        let safe_backup::ActionOutcome::Backed { path, .. } = app.backup_os_file(name).unwrap() else {
+            panic!("expected a new backup");
+        };
+        assert!(path.as_os_str().as_bytes().starts_with(b"caf\xe9.txt."));
+        assert_eq!(std::fs::read(&path).unwrap(), b"original");
+        assert_eq!(app.list_os_backups(name).unwrap().len(), 1);
+        assert!(matches!(app.backup_os_file(name).unwrap(), safe_backup::ActionOutcome::Unchanged { .. }));
+
+        // Another name with the same lossy text shares none of its backups
+        std::fs::write(OsStr::from_bytes(b"caf\xe8.txt"), "other").unwrap();
+        assert!(app.list_os_backups(OsStr::from_bytes(b"caf\xe8.txt")).unwrap().is_empty());
+
+        // The backup is found again by its original bytes, both by name and by the scanners
+        std::fs::write(name, "edited").unwrap();
+        assert!(!app.verify_os_file(name).unwrap());
+        app.restore_os_file(name, None).unwrap();
+        assert_eq!(std::fs::read(name).unwrap(), b"original");
+        let status = app.status().unwrap();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].original.as_os_str(), name);
+        assert_eq!(status[0].live, safe_backup::LiveState::Matches);
+    });
+}